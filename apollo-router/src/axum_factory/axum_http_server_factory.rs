@@ -19,6 +19,7 @@ use futures::channel::oneshot;
 use futures::future::join_all;
 use futures::prelude::*;
 use http::header::ACCEPT_ENCODING;
+use http::header::ALT_SVC;
 use http::header::CONTENT_ENCODING;
 use http::HeaderValue;
 use http::Request;
@@ -38,6 +39,7 @@ use tower::ServiceExt;
 use tower_http::decompression::DecompressionBody;
 use tower_http::trace::TraceLayer;
 
+use super::http3;
 use super::listeners::ensure_endpoints_consistency;
 use super::listeners::ensure_listenaddrs_consistency;
 use super::listeners::extra_endpoints;
@@ -45,6 +47,8 @@ use super::listeners::ListenersAndRouters;
 use super::utils::PropagatingMakeSpan;
 use super::ListenAddrAndRouter;
 use super::ENDPOINT_CALLBACK;
+use crate::axum_factory::client_certificate::ClientCertificate;
+use crate::axum_factory::client_certificate::CLIENT_CERTIFICATE_CONTEXT_KEY;
 use crate::axum_factory::compression::Compressor;
 use crate::axum_factory::listeners::get_extra_listeners;
 use crate::axum_factory::listeners::serve_router_on_listen_addr;
@@ -295,6 +299,38 @@ impl HttpServerFactory for AxumHttpServerFactory {
                 .local_addr()
                 .map_err(ApolloRouterError::ServerCreationError)?;
 
+            // the HTTP/3 listener serves the same router as the main listener
+            let http3_server = match configuration
+                .supergraph
+                .http3
+                .listen_address(&configuration.supergraph.listen)
+                .map_err(|e| ApolloRouterError::ServiceCreationError(e.to_string().into()))?
+            {
+                Some(address) => {
+                    let tls = configuration.tls.supergraph.as_ref().ok_or_else(|| {
+                        ApolloRouterError::ServiceCreationError(
+                            "the HTTP/3 listener requires the tls.supergraph configuration".into(),
+                        )
+                    })?;
+                    let endpoint = http3::bind(address, tls)?;
+                    tracing::info!(
+                        "GraphQL endpoint exposed over HTTP/3 at {}{} 🚀",
+                        endpoint
+                            .local_addr()
+                            .map_err(ApolloRouterError::ServerCreationError)?,
+                        configuration.supergraph.path
+                    );
+                    Some(http3::serve_router_on_endpoint(
+                        endpoint,
+                        all_routers.main.1.clone(),
+                        configuration.limits.http_max_request_bytes,
+                        all_connections_stopped_sender.clone(),
+                    ))
+                }
+                None => None,
+            };
+            let (http3_server, http3_shutdown_sender) = http3_server.unzip();
+
             let (main_server, main_shutdown_sender) = serve_router_on_listen_addr(
                 main_listener,
                 actual_main_listen_address.clone(),
@@ -363,6 +399,11 @@ impl HttpServerFactory for AxumHttpServerFactory {
                 if let Err(_err) = main_shutdown_sender.send(()) {
                     tracing::error!("Failed to notify http thread of shutdown");
                 }
+                if let Some(http3_shutdown_sender) = http3_shutdown_sender {
+                    if let Err(_err) = http3_shutdown_sender.send(()) {
+                        tracing::error!("Failed to notify HTTP/3 thread of shutdown");
+                    }
+                }
             });
 
             let (outer_extra_shutdown_sender, outer_extra_shutdown_receiver) =
@@ -376,7 +417,17 @@ impl HttpServerFactory for AxumHttpServerFactory {
                 })
             });
 
-            // Spawn the main (GraphQL) server into a task
+            // Spawn the main (GraphQL) server into a task. The HTTP/3 server must stop before the
+            // listener is handed over to the next server, to release the UDP socket
+            let main_server = async move {
+                let (listener, _) = future::join(main_server, async move {
+                    if let Some(http3_server) = http3_server {
+                        http3_server.await
+                    }
+                })
+                .await;
+                listener
+            };
             let main_future = tokio::task::spawn(main_server)
                 .map_err(|_| ApolloRouterError::HttpServerLifecycleError)
                 .boxed();
@@ -455,7 +506,23 @@ where
             (license, Instant::now(), Arc::new(AtomicU64::new(0))),
             license_handler,
         ))
-        .layer(Extension(service_factory))
+        .layer(Extension(service_factory));
+    if let Some(http3_address) = configuration
+        .supergraph
+        .http3
+        .listen_address(&configuration.supergraph.listen)
+        .map_err(|e| ApolloRouterError::ServiceCreationError(e.to_string().into()))?
+    {
+        // advertise the HTTP/3 listener to clients connected over TCP
+        main_route = main_route.layer(middleware::from_fn_with_state(
+            http3::alt_svc(
+                http3_address.port(),
+                configuration.supergraph.http3.alt_svc_max_age,
+            ),
+            alt_svc_handler,
+        ));
+    }
+    let mut main_route = main_route
         .layer(cors)
         // Telemetry layers MUST be last. This means that they will be hit first during execution of the pipeline
         // Adding layers after telemetry will cause us to lose metrics and spans.
@@ -494,6 +561,16 @@ async fn metrics_handler<B>(request: Request<B>, next: Next<B>) -> Response {
     resp
 }
 
+async fn alt_svc_handler<B>(
+    State(alt_svc): State<HeaderValue>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let mut response = next.run(request).await;
+    response.headers_mut().insert(ALT_SVC, alt_svc);
+    response
+}
+
 async fn license_handler<B>(
     State((license, start, delta)): State<(LicenseState, Instant, Arc<AtomicU64>)>,
     request: Request<B>,
@@ -595,6 +672,18 @@ async fn handle_graphql(
     let http_request = http::Request::from_parts(parts, Body::wrap_stream(BodyStream::new(body)));
 
     let request: router::Request = http_request.into();
+    if let Some(client_certificate) = request
+        .router_request
+        .extensions()
+        .get::<ClientCertificate>()
+    {
+        if let Err(e) = request
+            .context
+            .insert(CLIENT_CERTIFICATE_CONTEXT_KEY, client_certificate.clone())
+        {
+            tracing::error!("could not insert the client certificate into the context: {e}");
+        }
+    }
     let context = request.context.clone();
     let accept_encoding = request
         .router_request
@@ -0,0 +1,281 @@
+//! Identity of the clients authenticated with a TLS certificate on the supergraph listener
+
+use std::net::IpAddr;
+use std::net::Ipv4Addr;
+use std::net::Ipv6Addr;
+
+use serde::Deserialize;
+use serde::Serialize;
+use sha2::Digest;
+use sha2::Sha256;
+use x509_parser::extensions::GeneralName;
+use x509_parser::parse_x509_certificate;
+
+/// Context key of the verified client certificate
+pub(crate) const CLIENT_CERTIFICATE_CONTEXT_KEY: &str = "apollo_tls::client_certificate";
+
+/// The verified certificate of a client, inserted in the request extensions and context
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct ClientCertificate {
+    /// distinguished name of the subject, like `C=FR, O=Apollo GraphQL, CN=router`
+    pub(crate) subject: String,
+    /// subject alternative names, like `DNS:example.com` or `URI:spiffe://example.com/service`
+    pub(crate) subject_alternative_names: Vec<String>,
+    /// hex encoded SHA-256 fingerprint of the certificate
+    pub(crate) fingerprint: String,
+}
+
+impl ClientCertificate {
+    /// Identity of the end-entity certificate of the chain presented by the client, which rustls verified
+    /// during the handshake
+    pub(crate) fn from_peer_certificates(
+        certificates: Option<&[rustls::Certificate]>,
+    ) -> Option<Self> {
+        let certificate = certificates?.first()?;
+        match Self::parse(&certificate.0) {
+            Ok(certificate) => Some(certificate),
+            Err(e) => {
+                tracing::warn!("could not parse the client certificate: {e}");
+                None
+            }
+        }
+    }
+
+    fn parse(der: &[u8]) -> Result<Self, String> {
+        let (_, certificate) = parse_x509_certificate(der).map_err(|e| e.to_string())?;
+        let subject_alternative_names = certificate
+            .subject_alternative_name()
+            .map_err(|e| e.to_string())?
+            .map(|extension| {
+                extension
+                    .value
+                    .general_names
+                    .iter()
+                    .filter_map(general_name)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Ok(Self {
+            subject: certificate.subject().to_string(),
+            subject_alternative_names,
+            fingerprint: hex::encode(Sha256::digest(der)),
+        })
+    }
+}
+
+// Uses the prefixes of OpenSSL. The other kinds of names are not exposed
+fn general_name(name: &GeneralName) -> Option<String> {
+    match name {
+        GeneralName::DNSName(name) => Some(format!("DNS:{name}")),
+        GeneralName::RFC822Name(email) => Some(format!("email:{email}")),
+        GeneralName::URI(uri) => Some(format!("URI:{uri}")),
+        GeneralName::IPAddress(bytes) => {
+            let address = match bytes.len() {
+                4 => IpAddr::from(Ipv4Addr::from(<[u8; 4]>::try_from(*bytes).ok()?)),
+                16 => IpAddr::from(Ipv6Addr::from(<[u8; 16]>::try_from(*bytes).ok()?)),
+                _ => return None,
+            };
+            Some(format!("IP:{address}"))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use futures::future::BoxFuture;
+    use hyper::Body;
+    use multimap::MultiMap;
+    use serde_json::json;
+    use tower::service_fn;
+    use tower::BoxError;
+
+    use super::*;
+    use crate::axum_factory::tests::init_with_config;
+    use crate::configuration::load_certs;
+    use crate::configuration::load_crls;
+    use crate::configuration::load_key;
+    use crate::configuration::Supergraph;
+    use crate::configuration::Tls;
+    use crate::configuration::TlsSupergraph;
+    use crate::configuration::TlsSupergraphClientAuth;
+    use crate::graphql;
+    use crate::http_server_factory::HttpServerHandle;
+    use crate::services::router;
+    use crate::Configuration;
+    use crate::ListenAddr;
+
+    const CA_CERTIFICATE: &str = include_str!("../services/http/testdata/CA/ca.crt");
+
+    async fn init_tls(client_authentication: TlsSupergraphClientAuth) -> HttpServerHandle {
+        let configuration = Configuration::fake_builder()
+            .supergraph(
+                Supergraph::fake_builder()
+                    .listen(ListenAddr::SocketAddr("127.0.0.1:0".parse().unwrap()))
+                    .build(),
+            )
+            .tls(Tls {
+                supergraph: Some(TlsSupergraph {
+                    certificate: load_certs(include_str!("../services/http/testdata/server.crt"))
+                        .unwrap()
+                        .remove(0),
+                    key: load_key(include_str!("../services/http/testdata/server.key")).unwrap(),
+                    certificate_chain: vec![],
+                    client_authentication: Some(client_authentication),
+                }),
+                subgraph: Default::default(),
+            })
+            .build()
+            .unwrap();
+        // answers with the client certificate found in the context
+        let router_service = service_fn(|request: router::Request| {
+            Box::pin(async move {
+                let client_certificate = request
+                    .context
+                    .get::<_, ClientCertificate>(CLIENT_CERTIFICATE_CONTEXT_KEY)
+                    .unwrap();
+                router::Response::fake_builder()
+                    .data(json!({ "client_certificate": client_certificate }))
+                    .context(request.context)
+                    .build()
+            }) as BoxFuture<'static, router::ServiceResult>
+        });
+
+        let (server, _) =
+            init_with_config(router_service, Arc::new(configuration), MultiMap::new())
+                .await
+                .unwrap();
+        server
+    }
+
+    async fn send_request(
+        server: &HttpServerHandle,
+        with_client_certificate: bool,
+    ) -> Result<graphql::Response, BoxError> {
+        let ListenAddr::SocketAddr(address) = server.graphql_listen_address().clone().unwrap()
+        else {
+            panic!("the supergraph listens on a TCP socket");
+        };
+
+        let mut roots = rustls::RootCertStore::empty();
+        for certificate in load_certs(CA_CERTIFICATE).unwrap() {
+            roots.add(&certificate).unwrap();
+        }
+        let tls_config = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots);
+        let tls_config = if with_client_certificate {
+            tls_config.with_client_auth_cert(
+                load_certs(include_str!("../services/http/testdata/client.crt")).unwrap(),
+                load_key(include_str!("../services/http/testdata/client.key")).unwrap(),
+            )?
+        } else {
+            tls_config.with_no_client_auth()
+        };
+        let connector = hyper_rustls::HttpsConnectorBuilder::new()
+            .with_tls_config(tls_config)
+            .https_only()
+            .enable_http1()
+            .build();
+        let response = hyper::Client::builder()
+            .build::<_, Body>(connector)
+            .request(
+                http::Request::post(format!("https://localhost:{}/", address.port()))
+                    .header(http::header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(json!({ "query": "{ me }" }).to_string()))
+                    .unwrap(),
+            )
+            .await?;
+        let body = hyper::body::to_bytes(response.into_body()).await?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    fn client_authentication(required: bool) -> TlsSupergraphClientAuth {
+        TlsSupergraphClientAuth {
+            certificate_authorities: load_certs(CA_CERTIFICATE).unwrap(),
+            certificate_revocation_lists: vec![],
+            required,
+        }
+    }
+
+    #[tokio::test]
+    async fn it_inserts_the_client_certificate_in_the_context() {
+        let server = init_tls(client_authentication(true)).await;
+
+        let response = send_request(&server, true).await.unwrap();
+        assert_eq!(
+            response.data,
+            Some(
+                json!({
+                    "client_certificate": {
+                        "subject": "C=FR, O=Apollo GraphQL, CN=router",
+                        "subject_alternative_names": [],
+                        "fingerprint": "14163883e27d29ba6c37de3ab761af895aedab73b85cfc7a00a5ca97700e67ba"
+                    }
+                })
+                .into()
+            )
+        );
+
+        server.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn it_rejects_clients_without_certificate_when_required() {
+        let server = init_tls(client_authentication(true)).await;
+        assert!(send_request(&server, false).await.is_err());
+        server.shutdown().await.unwrap();
+
+        // the certificate is optional, so there is no client identity in the context
+        let server = init_tls(client_authentication(false)).await;
+        let response = send_request(&server, false).await.unwrap();
+        assert_eq!(
+            response.data,
+            Some(json!({ "client_certificate": null }).into())
+        );
+        server.shutdown().await.unwrap();
+    }
+
+    // client_revoked.crl revokes client.crt. In the testdata directory, with an `openssl ca`
+    // configuration file pointing to an empty index:
+    // openssl ca -config ca.cnf -keyfile CA/ca.key -cert CA/ca.crt -revoke client.crt
+    // openssl ca -config ca.cnf -keyfile CA/ca.key -cert CA/ca.crt -gencrl -crldays 9000 -out client_revoked.crl
+    #[tokio::test]
+    async fn it_rejects_revoked_client_certificates() {
+        let mut client_authentication = client_authentication(true);
+        client_authentication.certificate_revocation_lists =
+            load_crls(include_str!("../services/http/testdata/client_revoked.crl")).unwrap();
+        let server = init_tls(client_authentication).await;
+
+        assert!(send_request(&server, true).await.is_err());
+
+        server.shutdown().await.unwrap();
+    }
+
+    #[test]
+    fn parses_the_client_identity() {
+        let certificates =
+            load_certs(include_str!("../services/http/testdata/client.crt")).unwrap();
+        assert_eq!(
+            ClientCertificate::from_peer_certificates(Some(certificates.as_slice())),
+            Some(ClientCertificate {
+                subject: "C=FR, O=Apollo GraphQL, CN=router".to_string(),
+                subject_alternative_names: vec![],
+                fingerprint: "14163883e27d29ba6c37de3ab761af895aedab73b85cfc7a00a5ca97700e67ba"
+                    .to_string(),
+            })
+        );
+
+        let certificates =
+            load_certs(include_str!("../services/http/testdata/server.crt")).unwrap();
+        assert_eq!(
+            ClientCertificate::from_peer_certificates(Some(certificates.as_slice()))
+                .unwrap()
+                .subject_alternative_names,
+            vec!["DNS:localhost".to_string()]
+        );
+    }
+}
@@ -0,0 +1,374 @@
+//! HTTP/3 listener for the supergraph endpoint
+//!
+//! QUIC connections are accepted on a UDP socket, and their requests go through the same axum router as
+//! the requests received on the TCP listener.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use axum::Router;
+use bytes::BufMut;
+use bytes::Bytes;
+use bytes::BytesMut;
+use futures::channel::oneshot;
+use futures::prelude::*;
+use h3::error::ErrorLevel;
+use h3::server::RequestStream;
+use http::StatusCode;
+use http_body::Body as _;
+use hyper::Body;
+use tokio::sync::mpsc;
+use tokio::sync::Notify;
+use tower::BoxError;
+use tower::ServiceExt;
+
+use crate::axum_factory::client_certificate::ClientCertificate;
+use crate::axum_factory::utils::ConnectionInfo;
+use crate::axum_factory::utils::InjectConnectionInfo;
+use crate::configuration::TlsSupergraph;
+use crate::router::ApolloRouterError;
+
+type H3Stream = RequestStream<h3_quinn::BidiStream<Bytes>, Bytes>;
+
+/// Creates the QUIC endpoint, using the supergraph TLS certificate
+pub(super) fn bind(
+    address: SocketAddr,
+    tls: &TlsSupergraph,
+) -> Result<quinn::Endpoint, ApolloRouterError> {
+    let server_config = quinn::ServerConfig::with_crypto(Arc::new(tls.http3_tls_config()?));
+
+    quinn::Endpoint::server(server_config, address).map_err(ApolloRouterError::ServerCreationError)
+}
+
+/// Value of the `Alt-Svc` header advertising the HTTP/3 listener
+pub(super) fn alt_svc(port: u16, max_age: std::time::Duration) -> http::HeaderValue {
+    http::HeaderValue::from_str(&format!("h3=\":{port}\"; ma={}", max_age.as_secs()))
+        .expect("the Alt-Svc header value is always valid")
+}
+
+pub(super) fn serve_router_on_endpoint(
+    endpoint: quinn::Endpoint,
+    router: Router,
+    http_max_request_bytes: usize,
+    all_connections_stopped_sender: mpsc::Sender<()>,
+) -> (impl Future<Output = ()>, oneshot::Sender<()>) {
+    let (shutdown_sender, shutdown_receiver) = oneshot::channel::<()>();
+    let server = async move {
+        tokio::pin!(shutdown_receiver);
+
+        let connection_shutdown = Arc::new(Notify::new());
+        let server_address = endpoint.local_addr().ok();
+
+        loop {
+            tokio::select! {
+                _ = &mut shutdown_receiver => {
+                    break;
+                }
+                connecting = endpoint.accept() => {
+                    let connecting = match connecting {
+                        Some(connecting) => connecting,
+                        // the endpoint was closed
+                        None => break,
+                    };
+                    let connection_info = ConnectionInfo {
+                        peer_address: Some(connecting.remote_address()),
+                        server_address,
+                    };
+                    let router = router.clone();
+                    let connection_shutdown = connection_shutdown.clone();
+                    let connection_stop_signal = all_connections_stopped_sender.clone();
+
+                    tokio::task::spawn(async move {
+                        // this sender must be moved into the session to track that it is still running
+                        let _connection_stop_signal = connection_stop_signal;
+
+                        if let Err(err) = serve_connection(
+                            connecting,
+                            router,
+                            connection_info,
+                            http_max_request_bytes,
+                            connection_shutdown,
+                        )
+                        .await
+                        {
+                            tracing::debug!("HTTP/3 connection error: {err}");
+                        }
+                    });
+                }
+            }
+        }
+
+        // the connections stop accepting requests, then we wait for the current ones to finish
+        // before releasing the UDP socket
+        connection_shutdown.notify_waiters();
+        endpoint.wait_idle().await;
+    };
+
+    (server, shutdown_sender)
+}
+
+async fn serve_connection(
+    connecting: quinn::Connecting,
+    router: Router,
+    connection_info: ConnectionInfo,
+    http_max_request_bytes: usize,
+    connection_shutdown: Arc<Notify>,
+) -> Result<(), BoxError> {
+    let connection = connecting.await?;
+    let client_certificate = connection
+        .peer_identity()
+        .and_then(|identity| identity.downcast::<Vec<rustls::Certificate>>().ok())
+        .and_then(|certificates| {
+            ClientCertificate::from_peer_certificates(Some(certificates.as_slice()))
+        });
+    let mut connection =
+        h3::server::Connection::<_, Bytes>::new(h3_quinn::Connection::new(connection)).await?;
+
+    let shutdown = connection_shutdown.notified();
+    tokio::pin!(shutdown);
+    let mut shutting_down = false;
+
+    loop {
+        tokio::select! {
+            _ = &mut shutdown, if !shutting_down => {
+                // sends a GOAWAY frame, the requests already received are still handled
+                shutting_down = true;
+                connection.shutdown(0).await?;
+            }
+            request = connection.accept() => match request {
+                Ok(Some((request, stream))) => {
+                    let app = InjectConnectionInfo::new(router.clone(), connection_info.clone())
+                        .with_client_certificate(client_certificate.clone());
+                    tokio::task::spawn(async move {
+                        if let Err(err) =
+                            serve_request(request, stream, app, http_max_request_bytes).await
+                        {
+                            tracing::debug!("HTTP/3 request error: {err}");
+                        }
+                    });
+                }
+                Ok(None) => break,
+                Err(err) => match err.get_error_level() {
+                    ErrorLevel::ConnectionError => return Err(err.into()),
+                    ErrorLevel::StreamError => continue,
+                },
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn serve_request(
+    request: http::Request<()>,
+    mut stream: H3Stream,
+    app: InjectConnectionInfo<Router>,
+    http_max_request_bytes: usize,
+) -> Result<(), BoxError> {
+    let mut body = BytesMut::new();
+    while let Some(chunk) = stream.recv_data().await? {
+        body.put(chunk);
+        if body.len() > http_max_request_bytes {
+            stream
+                .send_response(
+                    http::Response::builder()
+                        .status(StatusCode::PAYLOAD_TOO_LARGE)
+                        .body(())?,
+                )
+                .await?;
+            stream.finish().await?;
+            return Ok(());
+        }
+    }
+
+    let (parts, ()) = request.into_parts();
+    let request = http::Request::from_parts(parts, Body::from(body.freeze()));
+    let response = app.oneshot(request).await?;
+
+    let (parts, mut body) = response.into_parts();
+    stream
+        .send_response(http::Response::from_parts(parts, ()))
+        .await?;
+    // deferred responses and subscriptions are sent as they are produced
+    while let Some(chunk) = body.data().await {
+        stream.send_data(chunk?).await?;
+    }
+    stream.finish().await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::UdpSocket;
+
+    use futures::future::BoxFuture;
+    use http::header::ALT_SVC;
+    use multimap::MultiMap;
+    use serde_json::json;
+    use tower::service_fn;
+
+    use super::*;
+    use crate::axum_factory::tests::init_with_config;
+    use crate::configuration::load_certs;
+    use crate::configuration::load_key;
+    use crate::configuration::Http3;
+    use crate::configuration::Supergraph;
+    use crate::configuration::Tls;
+    use crate::graphql;
+    use crate::http_server_factory::HttpServerHandle;
+    use crate::services::router;
+    use crate::Configuration;
+    use crate::ListenAddr;
+
+    const SERVER_CERTIFICATE: &str = include_str!("../services/http/testdata/server.crt");
+    const SERVER_KEY: &str = include_str!("../services/http/testdata/server.key");
+    const CA_CERTIFICATE: &str = include_str!("../services/http/testdata/CA/ca.crt");
+
+    async fn init_http3() -> (HttpServerHandle, SocketAddr) {
+        // reserves a free UDP port for the HTTP/3 listener
+        let http3_address = UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let configuration = Configuration::fake_builder()
+            .supergraph(
+                Supergraph::fake_builder()
+                    .listen(ListenAddr::SocketAddr("127.0.0.1:0".parse().unwrap()))
+                    .http3(Http3 {
+                        enabled: true,
+                        listen: Some(http3_address),
+                        ..Default::default()
+                    })
+                    .build(),
+            )
+            .tls(Tls {
+                supergraph: Some(TlsSupergraph {
+                    certificate: load_certs(SERVER_CERTIFICATE).unwrap().remove(0),
+                    key: load_key(SERVER_KEY).unwrap(),
+                    certificate_chain: vec![],
+                    client_authentication: None,
+                }),
+                subgraph: Default::default(),
+            })
+            .build()
+            .unwrap();
+        let router_service = service_fn(|request: router::Request| {
+            Box::pin(async move {
+                router::Response::fake_builder()
+                    .data(json!({ "response": "yay" }))
+                    .context(request.context)
+                    .build()
+            }) as BoxFuture<'static, router::ServiceResult>
+        });
+
+        let (server, _) =
+            init_with_config(router_service, Arc::new(configuration), MultiMap::new())
+                .await
+                .unwrap();
+        (server, http3_address)
+    }
+
+    fn root_certificates() -> rustls::RootCertStore {
+        let mut roots = rustls::RootCertStore::empty();
+        for certificate in load_certs(CA_CERTIFICATE).unwrap() {
+            roots.add(&certificate).unwrap();
+        }
+        roots
+    }
+
+    #[tokio::test]
+    async fn it_answers_over_http3() {
+        let (server, http3_address) = init_http3().await;
+
+        let mut tls_config = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(root_certificates())
+            .with_no_client_auth();
+        tls_config.alpn_protocols = vec![b"h3".to_vec()];
+        let mut endpoint = quinn::Endpoint::client("127.0.0.1:0".parse().unwrap()).unwrap();
+        endpoint.set_default_client_config(quinn::ClientConfig::new(Arc::new(tls_config)));
+
+        let connection = endpoint
+            .connect(http3_address, "localhost")
+            .unwrap()
+            .await
+            .unwrap();
+        let (mut driver, mut send_request) = h3::client::new(h3_quinn::Connection::new(connection))
+            .await
+            .unwrap();
+        let driver = tokio::task::spawn(async move {
+            let _ = future::poll_fn(|cx| driver.poll_close(cx)).await;
+        });
+
+        let mut stream = send_request
+            .send_request(
+                http::Request::post(format!("https://localhost:{}/", http3_address.port()))
+                    .header(http::header::CONTENT_TYPE, "application/json")
+                    .body(())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        stream
+            .send_data(Bytes::from(json!({ "query": "{ me }" }).to_string()))
+            .await
+            .unwrap();
+        stream.finish().await.unwrap();
+
+        let response = stream.recv_response().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let mut body = BytesMut::new();
+        while let Some(mut chunk) = stream.recv_data().await.unwrap() {
+            body.put(&mut chunk);
+        }
+        assert_eq!(
+            serde_json::from_slice::<graphql::Response>(&body).unwrap(),
+            graphql::Response::builder()
+                .data(json!({ "response": "yay" }))
+                .build()
+        );
+
+        drop(send_request);
+        endpoint.close(0u32.into(), b"");
+        driver.abort();
+        server.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn it_advertises_http3_over_tcp() {
+        let (server, http3_address) = init_http3().await;
+        let ListenAddr::SocketAddr(tcp_address) = server.graphql_listen_address().clone().unwrap()
+        else {
+            panic!("the supergraph listens on a TCP socket");
+        };
+
+        let tls_config = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(root_certificates())
+            .with_no_client_auth();
+        let connector = hyper_rustls::HttpsConnectorBuilder::new()
+            .with_tls_config(tls_config)
+            .https_only()
+            .enable_http1()
+            .build();
+        let client = hyper::Client::builder().build::<_, Body>(connector);
+        let response = client
+            .request(
+                http::Request::post(format!("https://localhost:{}/", tcp_address.port()))
+                    .header(http::header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(json!({ "query": "{ me }" }).to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(ALT_SVC).unwrap(),
+            &format!("h3=\":{}\"; ma=86400", http3_address.port())
+        );
+
+        server.shutdown().await.unwrap();
+    }
+}
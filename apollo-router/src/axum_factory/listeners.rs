@@ -21,6 +21,7 @@ use tokio::sync::mpsc;
 use tokio::sync::Notify;
 use tower_service::Service;
 
+use crate::axum_factory::client_certificate::ClientCertificate;
 use crate::axum_factory::utils::ConnectionInfo;
 use crate::axum_factory::utils::InjectConnectionInfo;
 use crate::axum_factory::ENDPOINT_CALLBACK;
@@ -316,6 +317,14 @@ pub(super) fn serve_router_on_listen_addr(
                                     },
                                     NetworkStream::Tls(stream) => {
                                         let received_first_request = Arc::new(AtomicBool::new(false));
+                                        let (tcp_stream, tls_connection) = stream.get_ref();
+                                        let app = InjectConnectionInfo::new(app, ConnectionInfo {
+                                            peer_address: tcp_stream.peer_addr().ok(),
+                                            server_address: tcp_stream.local_addr().ok(),
+                                        })
+                                        .with_client_certificate(ClientCertificate::from_peer_certificates(
+                                            tls_connection.peer_certificates(),
+                                        ));
                                         let app = IdleConnectionChecker::new(received_first_request.clone(), app);
 
                                         stream.get_ref().0
@@ -1,6 +1,8 @@
 //! axum factory is useful to create an [`AxumHttpServerFactory`] which implements [`crate::http_server_factory::HttpServerFactory`]
 mod axum_http_server_factory;
+pub(crate) mod client_certificate;
 pub(crate) mod compression;
+mod http3;
 mod listeners;
 #[cfg(test)]
 pub(crate) mod tests;
@@ -8,6 +8,7 @@ use tower_http::trace::MakeSpan;
 use tower_service::Service;
 use tracing::Span;
 
+use crate::axum_factory::client_certificate::ClientCertificate;
 use crate::plugins::telemetry::SpanMode;
 use crate::plugins::telemetry::OTEL_STATUS_CODE;
 use crate::plugins::telemetry::OTEL_STATUS_CODE_ERROR;
@@ -67,6 +68,7 @@ impl<B> MakeSpan<B> for PropagatingMakeSpan {
 pub(crate) struct InjectConnectionInfo<S> {
     inner: S,
     connection_info: ConnectionInfo,
+    client_certificate: Option<ClientCertificate>,
 }
 
 #[derive(Clone)]
@@ -80,8 +82,18 @@ impl<S> InjectConnectionInfo<S> {
         InjectConnectionInfo {
             inner: service,
             connection_info,
+            client_certificate: None,
         }
     }
+
+    /// Also injects the verified certificate of the client, on TLS connections
+    pub(crate) fn with_client_certificate(
+        mut self,
+        client_certificate: Option<ClientCertificate>,
+    ) -> Self {
+        self.client_certificate = client_certificate;
+        self
+    }
 }
 
 impl<S, B> Service<http::Request<B>> for InjectConnectionInfo<S>
@@ -103,6 +115,9 @@ where
 
     fn call(&mut self, mut req: http::Request<B>) -> Self::Future {
         req.extensions_mut().insert(self.connection_info.clone());
+        if let Some(client_certificate) = &self.client_certificate {
+            req.extensions_mut().insert(client_certificate.clone());
+        }
         self.inner.call(req)
     }
 }
@@ -1,8 +1,10 @@
+use std::collections::HashMap;
 use std::fmt;
 use std::sync::Arc;
 use std::time::Duration;
 
 use fred::interfaces::EventInterface;
+use fred::interfaces::LuaInterface;
 #[cfg(test)]
 use fred::mocks::Mocks;
 use fred::prelude::ClientLike;
@@ -10,13 +12,17 @@ use fred::prelude::KeysInterface;
 use fred::prelude::RedisClient;
 use fred::prelude::RedisError;
 use fred::prelude::RedisErrorKind;
+use fred::prelude::StreamsInterface;
 use fred::types::Expiration;
 use fred::types::FromRedis;
+use fred::types::Options;
 use fred::types::PerformanceConfig;
 use fred::types::ReconnectPolicy;
 use fred::types::RedisConfig;
 use fred::types::TlsConfig;
 use fred::types::TlsHostMapping;
+use fred::util::redis_keyslot;
+use futures::future::join_all;
 use tower::BoxError;
 use url::Url;
 
@@ -25,6 +31,33 @@ use super::ValueType;
 use crate::configuration::RedisCache;
 use crate::services::generate_tls_client_config;
 
+// increments the key, and sets the expiration (ARGV[2] seconds) if the key has none
+const INCREMENT_SCRIPT: &str = r#"
+local value = redis.call('INCRBY', KEYS[1], ARGV[1])
+if tonumber(ARGV[2]) > 0 and redis.call('TTL', KEYS[1]) == -1 then
+    redis.call('EXPIRE', KEYS[1], ARGV[2])
+end
+return value
+"#;
+
+// sets the key to ARGV[3] if its value is ARGV[2], or if it does not exist when ARGV[1] is '0'
+const COMPARE_AND_SWAP_SCRIPT: &str = r#"
+local current = redis.call('GET', KEYS[1])
+if ARGV[1] == '1' then
+    if current ~= ARGV[2] then
+        return 0
+    end
+elseif current then
+    return 0
+end
+if tonumber(ARGV[4]) > 0 then
+    redis.call('SET', KEYS[1], ARGV[3], 'EX', ARGV[4])
+else
+    redis.call('SET', KEYS[1], ARGV[3])
+end
+return 1
+"#;
+
 const SUPPORTED_REDIS_SCHEMES: [&str; 6] = [
     "redis",
     "rediss",
@@ -456,6 +489,138 @@ impl RedisCacheStorage {
         };
         tracing::trace!("insert result {:?}", r);
     }
+
+    /// Increments the integer value of a key, and sets its expiration if it has none
+    pub(crate) async fn increment<K: KeyType>(
+        &self,
+        key: RedisKey<K>,
+        delta: i64,
+        ttl: Option<Duration>,
+    ) -> Result<i64, RedisError> {
+        let key = self.make_key(key);
+        let ttl = ttl
+            .or(self.ttl)
+            .map(|ttl| ttl.as_secs())
+            .unwrap_or_default();
+        tracing::trace!("incrementing in redis: {:?} by {}", key, delta);
+        self.inner
+            .eval(INCREMENT_SCRIPT, key, vec![delta, ttl as i64])
+            .await
+    }
+
+    /// Replaces the value of a key if it is `expected`, or if the key does not exist when `expected` is `None`
+    pub(crate) async fn compare_and_swap<K: KeyType, V: ValueType>(
+        &self,
+        key: RedisKey<K>,
+        expected: Option<RedisValue<V>>,
+        value: RedisValue<V>,
+        ttl: Option<Duration>,
+    ) -> Result<bool, RedisError> {
+        let key = self.make_key(key);
+        let ttl = ttl
+            .or(self.ttl)
+            .map(|ttl| ttl.as_secs())
+            .unwrap_or_default();
+        let to_json = |value: RedisValue<V>| {
+            serde_json::to_string(&value.0)
+                .map_err(|e| RedisError::new(RedisErrorKind::Parse, e.to_string()))
+        };
+        let args = vec![
+            if expected.is_some() { "1" } else { "0" }.to_string(),
+            expected.map(to_json).transpose()?.unwrap_or_default(),
+            to_json(value)?,
+            ttl.to_string(),
+        ];
+        tracing::trace!("compare and swap in redis: {:?}", key);
+        let swapped: i64 = self.inner.eval(COMPARE_AND_SWAP_SCRIPT, key, args).await?;
+        Ok(swapped == 1)
+    }
+
+    /// Client with its own connection, for blocking commands which would delay the commands of the other callers
+    pub(crate) async fn with_new_connection(&self) -> Result<Self, RedisError> {
+        let client = self.inner.clone_new();
+        let _handle = client.connect();
+        tokio::time::timeout(Duration::from_secs(5), client.wait_for_connect())
+            .await
+            .map_err(|_| {
+                RedisError::new(RedisErrorKind::Timeout, "timeout connecting to Redis")
+            })??;
+
+        Ok(Self {
+            inner: Arc::new(client),
+            namespace: self.namespace.clone(),
+            ttl: self.ttl,
+        })
+    }
+
+    /// Close the connection
+    pub(crate) async fn quit(&self) {
+        if let Err(err) = self.inner.quit().await {
+            tracing::trace!("cannot close the redis connection: {err}");
+        }
+    }
+
+    /// Get the id of the last entry of a stream, if it has any
+    pub(crate) async fn last_stream_id(&self, key: String) -> Result<Option<String>, RedisError> {
+        let key = self.make_key(RedisKey(key));
+        let entries: Vec<(String, HashMap<String, String>)> =
+            self.inner.xrevrange_values(key, "+", "-", Some(1)).await?;
+
+        Ok(entries.into_iter().next().map(|(id, _)| id))
+    }
+
+    /// Read the entries added to streams after their last id, waiting up to `block` for new entries
+    ///
+    /// The streams are read with a single command, or with one command per hash slot on a Redis cluster.
+    pub(crate) async fn read_streams(
+        &self,
+        streams: Vec<(String, String)>,
+        count: u64,
+        block: Duration,
+    ) -> Result<HashMap<String, Vec<(String, HashMap<String, String>)>>, RedisError> {
+        let clustered = self.inner.is_clustered();
+        let mut keys = HashMap::with_capacity(streams.len());
+        let mut reads: HashMap<u16, (Vec<String>, Vec<String>)> = HashMap::new();
+        for (key, last_id) in streams {
+            let namespaced_key = self.make_key(RedisKey(key.clone()));
+            let slot = if clustered {
+                redis_keyslot(namespaced_key.as_bytes())
+            } else {
+                0
+            };
+            let (slot_keys, slot_ids) = reads.entry(slot).or_default();
+            slot_keys.push(namespaced_key.clone());
+            slot_ids.push(last_id);
+            keys.insert(namespaced_key, key);
+        }
+        tracing::trace!("reading redis streams {:?}", keys.values());
+
+        // the command timeout starts when the command is sent, it must leave time for the blocking read
+        let options = Options {
+            timeout: Some(block + self.inner.perf_config().default_command_timeout),
+            ..Default::default()
+        };
+        let client = self.inner.with_options(&options);
+        let responses = join_all(reads.into_values().map(|(slot_keys, slot_ids)| {
+            client.xread_map::<String, String, String, String, _, _>(
+                Some(count),
+                Some(block.as_millis() as u64),
+                slot_keys,
+                slot_ids,
+            )
+        }))
+        .await;
+
+        let mut entries = HashMap::new();
+        for response in responses {
+            for (key, key_entries) in response? {
+                if let Some(key) = keys.remove(&key) {
+                    entries.insert(key, key_entries);
+                }
+            }
+        }
+        Ok(entries)
+    }
 }
 
 #[cfg(test)]
@@ -30,10 +30,18 @@ pub(crate) use persisted_queries::PersistedQueries;
 #[cfg(test)]
 pub(crate) use persisted_queries::PersistedQueriesSafelist;
 use regex::Regex;
+use rustls::server::AllowAnyAnonymousOrAuthenticatedClient;
+use rustls::server::AllowAnyAuthenticatedClient;
+use rustls::server::ClientCertVerifier;
+use rustls::server::NoClientAuth;
+use rustls::server::UnparsedCertRevocationList;
+use rustls::CertRevocationListError;
 use rustls::Certificate;
 use rustls::PrivateKey;
+use rustls::RootCertStore;
 use rustls::ServerConfig;
 use rustls_pemfile::certs;
+use rustls_pemfile::crls;
 use rustls_pemfile::read_one;
 use rustls_pemfile::Item;
 use schemars::gen::SchemaGenerator;
@@ -469,6 +477,9 @@ impl Configuration {
                 },
             );
         }
+        self.supergraph
+            .http3
+            .listen_address(&self.supergraph.listen)?;
 
         // PQs.
         if self.persisted_queries.enabled {
@@ -613,6 +624,10 @@ pub(crate) struct Supergraph {
     /// Set to false to disable defer support
     pub(crate) defer_support: bool,
 
+    /// HTTP/3 (QUIC) listener, next to the TCP one
+    #[serde(rename = "experimental_http3")]
+    pub(crate) http3: Http3,
+
     /// Query planning options
     pub(crate) query_planning: QueryPlanning,
 }
@@ -621,6 +636,63 @@ fn default_defer_support() -> bool {
     true
 }
 
+/// Configuration of the HTTP/3 listener for the supergraph endpoint
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields, default)]
+pub(crate) struct Http3 {
+    /// Serve the supergraph endpoint over HTTP/3 too. Requires `tls.supergraph`
+    /// Default: false
+    pub(crate) enabled: bool,
+
+    /// The UDP socket address and port to listen on
+    /// Defaults to the address of `supergraph.listen`
+    pub(crate) listen: Option<SocketAddr>,
+
+    /// Max age of the `Alt-Svc` header advertising HTTP/3 on the TCP listener (default: 24h)
+    #[serde(with = "humantime_serde")]
+    #[schemars(with = "String")]
+    pub(crate) alt_svc_max_age: Duration,
+}
+
+impl Default for Http3 {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            listen: None,
+            alt_svc_max_age: Duration::from_secs(24 * 60 * 60),
+        }
+    }
+}
+
+impl Http3 {
+    /// The UDP address of the HTTP/3 listener, if it is enabled
+    ///
+    /// A supergraph listening on a Unix socket has no UDP address to reuse, so the
+    /// HTTP/3 listener address must be set explicitly
+    pub(crate) fn listen_address(
+        &self,
+        supergraph_listen: &ListenAddr,
+    ) -> Result<Option<SocketAddr>, ConfigurationError> {
+        if !self.enabled {
+            return Ok(None);
+        }
+        match (self.listen, supergraph_listen) {
+            (Some(address), _) => Ok(Some(address)),
+            (None, ListenAddr::SocketAddr(address)) => Ok(Some(*address)),
+            #[cfg(unix)]
+            (None, ListenAddr::UnixSocket(path)) => {
+                Err(ConfigurationError::InvalidConfiguration {
+                    message: "invalid 'supergraph.experimental_http3' configuration",
+                    error: format!(
+                        "the supergraph listens on the Unix socket '{}', 'supergraph.experimental_http3.listen' must be set",
+                        path.display()
+                    ),
+                })
+            }
+        }
+    }
+}
+
 #[buildstructor::buildstructor]
 impl Supergraph {
     #[builder]
@@ -629,6 +701,7 @@ impl Supergraph {
         path: Option<String>,
         introspection: Option<bool>,
         defer_support: Option<bool>,
+        http3: Option<Http3>,
         query_planning: Option<QueryPlanning>,
         reuse_query_fragments: Option<bool>,
     ) -> Self {
@@ -637,6 +710,7 @@ impl Supergraph {
             path: path.unwrap_or_else(default_graphql_path),
             introspection: introspection.unwrap_or_else(default_graphql_introspection),
             defer_support: defer_support.unwrap_or_else(default_defer_support),
+            http3: http3.unwrap_or_default(),
             query_planning: query_planning.unwrap_or_default(),
             reuse_query_fragments,
         }
@@ -652,6 +726,7 @@ impl Supergraph {
         path: Option<String>,
         introspection: Option<bool>,
         defer_support: Option<bool>,
+        http3: Option<Http3>,
         query_planning: Option<QueryPlanning>,
         reuse_query_fragments: Option<bool>,
     ) -> Self {
@@ -660,6 +735,7 @@ impl Supergraph {
             path: path.unwrap_or_else(default_graphql_path),
             introspection: introspection.unwrap_or_else(default_graphql_introspection),
             defer_support: defer_support.unwrap_or_else(default_defer_support),
+            http3: http3.unwrap_or_default(),
             query_planning: query_planning.unwrap_or_default(),
             reuse_query_fragments,
         }
@@ -906,7 +982,7 @@ impl Default for InMemoryCache {
     }
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
 /// Redis cache configuration
 pub(crate) struct RedisCache {
@@ -972,22 +1048,97 @@ pub(crate) struct TlsSupergraph {
     #[serde(deserialize_with = "deserialize_certificate_chain", skip_serializing)]
     #[schemars(with = "String")]
     pub(crate) certificate_chain: Vec<Certificate>,
+    /// client certificate authentication
+    #[serde(default)]
+    pub(crate) client_authentication: Option<TlsSupergraphClientAuth>,
+}
+
+/// Verification of the client certificates on the supergraph listener
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct TlsSupergraphClientAuth {
+    /// list of certificate authorities of the client certificates in PEM format
+    #[serde(deserialize_with = "deserialize_certificate_chain", skip_serializing)]
+    #[schemars(with = "String")]
+    pub(crate) certificate_authorities: Vec<Certificate>,
+    /// list of certificate revocation lists in PEM format
+    #[serde(default, deserialize_with = "deserialize_crls", skip_serializing)]
+    #[schemars(with = "Option<String>")]
+    pub(crate) certificate_revocation_lists: Vec<Vec<u8>>,
+    /// reject the connections without a client certificate (default: true). If false, they are accepted,
+    /// but the certificates that are presented are still verified
+    #[serde(default = "default_client_certificate_required")]
+    pub(crate) required: bool,
+}
+
+fn default_client_certificate_required() -> bool {
+    true
 }
 
 impl TlsSupergraph {
+    fn client_cert_verifier(&self) -> Result<Arc<dyn ClientCertVerifier>, ApolloRouterError> {
+        let Some(client_authentication) = &self.client_authentication else {
+            return Ok(NoClientAuth::boxed());
+        };
+
+        let mut roots = RootCertStore::empty();
+        for certificate in &client_authentication.certificate_authorities {
+            roots.add(certificate).map_err(ApolloRouterError::Rustls)?;
+        }
+        let crls = client_authentication
+            .certificate_revocation_lists
+            .iter()
+            .cloned()
+            .map(UnparsedCertRevocationList);
+        let invalid_crl = |e: CertRevocationListError| {
+            ApolloRouterError::Rustls(rustls::Error::InvalidCertRevocationList(e))
+        };
+
+        Ok(if client_authentication.required {
+            AllowAnyAuthenticatedClient::new(roots)
+                .with_crls(crls)
+                .map_err(invalid_crl)?
+                .boxed()
+        } else {
+            AllowAnyAnonymousOrAuthenticatedClient::new(roots)
+                .with_crls(crls)
+                .map_err(invalid_crl)?
+                .boxed()
+        })
+    }
+
     pub(crate) fn tls_config(&self) -> Result<Arc<rustls::ServerConfig>, ApolloRouterError> {
         let mut certificates = vec![self.certificate.clone()];
         certificates.extend(self.certificate_chain.iter().cloned());
 
         let mut config = ServerConfig::builder()
             .with_safe_defaults()
-            .with_no_client_auth()
+            .with_client_cert_verifier(self.client_cert_verifier()?)
             .with_single_cert(certificates, self.key.clone())
             .map_err(ApolloRouterError::Rustls)?;
         config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
 
         Ok(Arc::new(config))
     }
+
+    /// TLS configuration of the HTTP/3 listener
+    pub(crate) fn http3_tls_config(&self) -> Result<rustls::ServerConfig, ApolloRouterError> {
+        let mut certificates = vec![self.certificate.clone()];
+        certificates.extend(self.certificate_chain.iter().cloned());
+
+        // QUIC requires TLS 1.3. Early data stays disabled to avoid replaying requests
+        let mut config = ServerConfig::builder()
+            .with_safe_default_cipher_suites()
+            .with_safe_default_kx_groups()
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .map_err(ApolloRouterError::Rustls)?
+            .with_client_cert_verifier(self.client_cert_verifier()?)
+            .with_single_cert(certificates, self.key.clone())
+            .map_err(ApolloRouterError::Rustls)?;
+        config.alpn_protocols = vec![b"h3".to_vec()];
+
+        Ok(config)
+    }
 }
 
 fn deserialize_certificate<'de, D>(deserializer: D) -> Result<Certificate, D::Error>
@@ -1018,6 +1169,15 @@ where
     load_certs(&data).map_err(serde::de::Error::custom)
 }
 
+fn deserialize_crls<'de, D>(deserializer: D) -> Result<Vec<Vec<u8>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let data = String::deserialize(deserializer)?;
+
+    load_crls(&data).map_err(serde::de::Error::custom)
+}
+
 fn deserialize_key<'de, D>(deserializer: D) -> Result<PrivateKey, D::Error>
 where
     D: Deserializer<'de>,
@@ -1033,6 +1193,15 @@ pub(crate) fn load_certs(data: &str) -> io::Result<Vec<Certificate>> {
         .map(|mut certs| certs.drain(..).map(Certificate).collect())
 }
 
+pub(crate) fn load_crls(data: &str) -> io::Result<Vec<Vec<u8>>> {
+    crls(&mut BufReader::new(data.as_bytes())).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "invalid certificate revocation list",
+        )
+    })
+}
+
 pub(crate) fn load_key(data: &str) -> io::Result<PrivateKey> {
     let mut reader = BufReader::new(data.as_bytes());
     let mut key_iterator = iter::from_fn(|| read_one(&mut reader).transpose());
@@ -1071,7 +1240,7 @@ pub(crate) fn load_key(data: &str) -> io::Result<PrivateKey> {
 }
 
 /// Configuration options pertaining to the subgraph server component.
-#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
 #[serde(default)]
 pub(crate) struct TlsClient {
@@ -1102,7 +1271,7 @@ impl Default for TlsClient {
 }
 
 /// TLS client authentication
-#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub(crate) struct TlsClientAuth {
     /// list of certificates in PEM format
@@ -75,6 +75,7 @@ use serde::Serialize;
 
 /// Configuration options pertaining to the subgraph server component.
 #[derive(Default, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub(crate) struct SubgraphConfiguration<T>
 where
     T: Default + Serialize + JsonSchema,
@@ -91,8 +92,7 @@ impl<T> SubgraphConfiguration<T>
 where
     T: Default + Serialize + JsonSchema,
 {
-    #[allow(dead_code)]
-    fn get(&self, subgraph_name: &str) -> &T {
+    pub(crate) fn get(&self, subgraph_name: &str) -> &T {
         self.subgraphs.get(subgraph_name).unwrap_or(&self.all)
     }
 }
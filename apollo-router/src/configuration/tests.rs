@@ -156,6 +156,25 @@ fn bad_graphql_path_configuration_with_wildcard_as_prefix() {
     assert_eq!(error.to_string(), String::from("invalid 'server.graphql_path' configuration: '/*/test' is invalid, if you need to set a path like '/*/graphql' then specify it as a path parameter with a name, for example '/:my_project_key/graphql'"));
 }
 
+#[cfg(unix)]
+#[test]
+fn http3_requires_a_listen_address_on_unix_socket() {
+    let error = Configuration::fake_builder()
+        .supergraph(
+            Supergraph::fake_builder()
+                .listen(ListenAddr::UnixSocket("/tmp/router.sock".into()))
+                .http3(Http3 {
+                    enabled: true,
+                    ..Default::default()
+                })
+                .build(),
+        )
+        .build()
+        .unwrap_err();
+
+    assert_eq!(error.to_string(), String::from("invalid 'supergraph.experimental_http3' configuration: the supergraph listens on the Unix socket '/tmp/router.sock', 'supergraph.experimental_http3.listen' must be set"));
+}
+
 #[test]
 fn unknown_fields() {
     let error = validate_yaml_configuration(
@@ -401,6 +420,8 @@ fn validate_project_config_files() {
     std::env::set_var("TEST_CONFIG_ENDPOINT", "http://example.com");
     std::env::set_var("TEST_CONFIG_COLLECTOR_ENDPOINT", "http://example.com");
     std::env::set_var("PARSER_MAX_RECURSION", "500");
+    std::env::set_var("INTROSPECTION_CLIENT_SECRET", "secret");
+    std::env::set_var("COPROCESSOR_SECRET", "secret");
 
     #[cfg(not(unix))]
     let filename_matcher = Regex::from_str("((.+[.])?router\\.yaml)|(.+\\.mdx)").unwrap();
@@ -452,6 +473,11 @@ fn validate_project_config_files() {
             };
 
             for yaml in yamls {
+                // The documentation reads files from `/path/to`, use the test fixtures instead
+                let yaml = yaml.replace(
+                    "${file./path/to/",
+                    "${file.src/configuration/testdata/docs/",
+                );
                 if let Err(e) = validate_yaml_configuration(
                     &yaml,
                     Expansion::default().unwrap(),
@@ -780,6 +806,46 @@ tls:
     cfg.tls.supergraph.unwrap().tls_config().unwrap();
 }
 
+#[test]
+fn load_tls_client_authentication() {
+    let testdata = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("src");
+    let cert_path = testdata.join("configuration/testdata/server.crt");
+    let key_path = testdata.join("configuration/testdata/server.key");
+    let ca_path = testdata.join("services/http/testdata/CA/ca.crt");
+    let crl_path = testdata.join("services/http/testdata/CA/ca.crl");
+
+    let cfg = validate_yaml_configuration(
+        &format!(
+            r#"
+tls:
+  supergraph:
+    certificate: ${{file.{}}}
+    certificate_chain: ${{file.{}}}
+    key: ${{file.{}}}
+    client_authentication:
+      certificate_authorities: ${{file.{}}}
+      certificate_revocation_lists: ${{file.{}}}
+      required: false
+"#,
+            cert_path.to_string_lossy(),
+            cert_path.to_string_lossy(),
+            key_path.to_string_lossy(),
+            ca_path.to_string_lossy(),
+            crl_path.to_string_lossy(),
+        ),
+        Expansion::builder().supported_mode("file").build(),
+        Mode::NoUpgrade,
+    )
+    .expect("should not have resulted in an error");
+    let tls = cfg.tls.supergraph.unwrap();
+    let client_authentication = tls.client_authentication.as_ref().unwrap();
+    assert_eq!(client_authentication.certificate_authorities.len(), 1);
+    assert_eq!(client_authentication.certificate_revocation_lists.len(), 1);
+    assert!(!client_authentication.required);
+    tls.tls_config().unwrap();
+    tls.http3_tls_config().unwrap();
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
 struct TestSubgraphOverride {
     value: Option<u8>,
@@ -158,6 +158,16 @@ impl Context {
         self.entries.get(&key.into()).map(|v| v.value().clone())
     }
 
+    /// Remove a json value from the context using the provided key.
+    ///
+    /// Semantics: the result is the removed value as an [`Option`].
+    pub(crate) fn remove_json_value<K>(&self, key: K) -> Option<Value>
+    where
+        K: Into<String>,
+    {
+        self.entries.remove(&key.into()).map(|(_, value)| value)
+    }
+
     /// Upsert a value in the context using the provided key and resolving
     /// function.
     ///
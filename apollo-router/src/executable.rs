@@ -6,8 +6,10 @@ use std::fmt;
 use std::fmt::Debug;
 use std::net::SocketAddr;
 use std::path::PathBuf;
+use std::str::FromStr;
 use std::sync::atomic::AtomicBool;
 use std::sync::atomic::Ordering;
+use std::sync::Arc;
 use std::time::Duration;
 
 use anyhow::anyhow;
@@ -28,9 +30,11 @@ use url::Url;
 
 use crate::configuration::generate_config_schema;
 use crate::configuration::generate_upgrade;
+use crate::configuration::Configuration;
 use crate::configuration::Discussed;
 use crate::metrics::meter_provider;
 use crate::plugin::plugins;
+use crate::plugins::rhai::test_runner;
 use crate::plugins::telemetry::reload::init_telemetry;
 use crate::router::ConfigurationSource;
 use crate::router::RouterHttpServer;
@@ -116,6 +120,9 @@ extern "C" fn drop_ad_hoc_profiler() {
 enum Commands {
     /// Configuration subcommands.
     Config(ConfigSubcommandArgs),
+
+    /// Rhai subcommands.
+    Rhai(RhaiSubcommandArgs),
 }
 
 #[derive(Args, Debug)]
@@ -146,6 +153,41 @@ enum ConfigSubcommand {
     Preview,
 }
 
+#[derive(Args, Debug)]
+struct RhaiSubcommandArgs {
+    /// Subcommands
+    #[clap(subcommand)]
+    command: RhaiSubcommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum RhaiSubcommand {
+    /// Run the test cases of fixture files against the Rhai scripts, without starting the router.
+    Test {
+        /// The location of the router configuration, for its rhai section.
+        #[clap(
+            short,
+            long = "config",
+            value_parser,
+            env = "APOLLO_ROUTER_CONFIG_PATH"
+        )]
+        config_path: Option<PathBuf>,
+
+        /// The location of the supergraph schema, exposed to the scripts.
+        #[clap(
+            short,
+            long = "supergraph",
+            value_parser,
+            env = "APOLLO_ROUTER_SUPERGRAPH_PATH"
+        )]
+        supergraph_path: Option<PathBuf>,
+
+        /// The YAML or JSON fixture files.
+        #[clap(value_parser, required = true)]
+        fixtures: Vec<PathBuf>,
+    },
+}
+
 /// Options for the router
 #[derive(Parser, Debug)]
 #[clap(name = "router", about = "Apollo federation router")]
@@ -484,6 +526,14 @@ impl Executable {
                 Discussed::new().print_preview();
                 Ok(())
             }
+            Some(Commands::Rhai(RhaiSubcommandArgs {
+                command:
+                    RhaiSubcommand::Test {
+                        config_path,
+                        supergraph_path,
+                        fixtures,
+                    },
+            })) => Self::rhai_test(config_path, supergraph_path, fixtures).await,
             None => Self::inner_start(shutdown, schema, config, license, opt).await,
         };
 
@@ -498,6 +548,36 @@ impl Executable {
         result
     }
 
+    async fn rhai_test(
+        config_path: &Option<PathBuf>,
+        supergraph_path: &Option<PathBuf>,
+        fixtures: &[PathBuf],
+    ) -> Result<()> {
+        let conf = match config_path {
+            Some(config_path) => Configuration::from_str(&std::fs::read_to_string(config_path)?)?
+                .apollo_plugins
+                .plugins
+                .get("rhai")
+                .cloned()
+                .unwrap_or_else(|| serde_json::Value::Object(Default::default())),
+            None => serde_json::Value::Object(Default::default()),
+        };
+        let supergraph_sdl = match supergraph_path {
+            Some(supergraph_path) => std::fs::read_to_string(supergraph_path)?,
+            None => String::new(),
+        };
+
+        let report = test_runner::run(conf, Arc::new(supergraph_sdl), fixtures)
+            .await
+            .map_err(|e| anyhow!("could not run the rhai tests: {e}"))?;
+        println!("{report}");
+        if report.is_success() {
+            Ok(())
+        } else {
+            Err(anyhow!("rhai tests failed"))
+        }
+    }
+
     async fn inner_start(
         shutdown: Option<ShutdownSource>,
         schema: Option<SchemaSource>,
@@ -135,6 +135,11 @@ impl AggregateMeterProvider {
         }
     }
 
+    /// Identifies this meter provider, for callers that cache the instruments registered on it.
+    pub(crate) fn id(&self) -> usize {
+        Arc::as_ptr(&self.inner) as usize
+    }
+
     /// Create a registered instrument. This enables caching at callsites and invalidation at the meter provider via weak reference.
     #[allow(dead_code)]
     pub(crate) fn create_registered_instrument<T>(
@@ -0,0 +1,327 @@
+//! Metrics created at runtime by Rhai scripts and coprocessors
+//!
+//! Their names and attributes are only known at runtime, so they can't use the metric macros, which cache an instrument
+//! per callsite. They are guarded against high cardinality: there can be at most `max_metrics` custom metrics, each with
+//! at most `max_attribute_sets` distinct sets of attributes. The data points beyond these limits are dropped.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::hash::Hash;
+use std::hash::Hasher;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Weak;
+
+use dashmap::mapref::entry::Entry;
+use dashmap::DashMap;
+use displaydoc::Display;
+use once_cell::sync::Lazy;
+use opentelemetry::metrics::Counter;
+use opentelemetry::metrics::Histogram;
+use opentelemetry::KeyValue;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
+use thiserror::Error;
+
+const DEFAULT_MAX_CUSTOM_METRICS: usize = 100;
+const DEFAULT_MAX_ATTRIBUTE_SETS: usize = 1000;
+const MAX_ATTRIBUTES: usize = 10;
+const MAX_NAME_LENGTH: usize = 255;
+// the metrics of the router
+const RESERVED_PREFIX: &str = "apollo";
+
+static REGISTRY: Lazy<Registry> = Lazy::new(|| Registry::new(&CustomMetricsLimits::default()));
+
+/// Cardinality limits of the metrics created by Rhai scripts and coprocessors
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, default)]
+pub(crate) struct CustomMetricsLimits {
+    /// Maximum number of custom metrics, the data points of new metrics beyond this limit are dropped (default: 100)
+    pub(crate) max_metrics: usize,
+    /// Maximum number of distinct sets of attributes of each custom metric, the data points with new sets of attributes
+    /// beyond this limit are dropped (default: 1000)
+    pub(crate) max_attribute_sets: usize,
+}
+
+impl Default for CustomMetricsLimits {
+    fn default() -> Self {
+        Self {
+            max_metrics: DEFAULT_MAX_CUSTOM_METRICS,
+            max_attribute_sets: DEFAULT_MAX_ATTRIBUTE_SETS,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum CustomMetricKind {
+    Counter,
+    Histogram,
+}
+
+#[derive(Debug, Display, Error)]
+pub(crate) enum CustomMetricError {
+    /// invalid metric name '{0}': it must start with a letter, only contain letters, digits, '_' and '.', and not start with 'apollo'
+    InvalidName(String),
+
+    /// metric '{0}' is already registered as a {1:?}
+    KindMismatch(String, CustomMetricKind),
+
+    /// invalid value {1} for metric '{0}': values must be finite, and positive for counters
+    InvalidValue(String, f64),
+
+    /// too many attributes for metric '{0}': the maximum is 10
+    TooManyAttributes(String),
+
+    /// invalid value for attribute '{0}': {1}
+    InvalidAttribute(String, String),
+}
+
+struct CustomMetric {
+    kind: CustomMetricKind,
+    attribute_sets: HashSet<u64>,
+    // the meter provider that the instruments were registered on
+    meter_provider: usize,
+    counter: Weak<Counter<f64>>,
+    histogram: Weak<Histogram<f64>>,
+}
+
+impl CustomMetric {
+    fn new(kind: CustomMetricKind) -> Self {
+        Self {
+            kind,
+            attribute_sets: HashSet::new(),
+            meter_provider: 0,
+            counter: Weak::new(),
+            histogram: Weak::new(),
+        }
+    }
+
+    // Instruments are registered on the meter provider, which drops them when its configuration changes. The cache is
+    // also checked against the current meter provider, since each test has its own.
+    fn counter(&mut self, name: &str) -> Arc<Counter<f64>> {
+        let meter_provider = crate::metrics::meter_provider();
+        if self.meter_provider == meter_provider.id() {
+            if let Some(counter) = self.counter.upgrade() {
+                return counter;
+            }
+        }
+        let counter = meter_provider.create_registered_instrument(|p| {
+            p.meter("apollo/router")
+                .f64_counter(name.to_string())
+                .init()
+        });
+        self.meter_provider = meter_provider.id();
+        self.counter = Arc::downgrade(&counter);
+        counter
+    }
+
+    fn histogram(&mut self, name: &str) -> Arc<Histogram<f64>> {
+        let meter_provider = crate::metrics::meter_provider();
+        if self.meter_provider == meter_provider.id() {
+            if let Some(histogram) = self.histogram.upgrade() {
+                return histogram;
+            }
+        }
+        let histogram = meter_provider.create_registered_instrument(|p| {
+            p.meter("apollo/router")
+                .f64_histogram(name.to_string())
+                .init()
+        });
+        self.meter_provider = meter_provider.id();
+        self.histogram = Arc::downgrade(&histogram);
+        histogram
+    }
+}
+
+fn is_valid_name(name: &str) -> bool {
+    name.len() <= MAX_NAME_LENGTH
+        && name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+        && !name.to_ascii_lowercase().starts_with(RESERVED_PREFIX)
+}
+
+/// Applies new limits, the metrics and sets of attributes already recorded are kept
+pub(crate) fn configure(limits: &CustomMetricsLimits) {
+    REGISTRY.configure(limits);
+}
+
+/// Adds a value to a custom counter, or records it in a custom histogram
+pub(crate) fn record(
+    kind: CustomMetricKind,
+    name: &str,
+    value: f64,
+    attributes: Vec<KeyValue>,
+) -> Result<(), CustomMetricError> {
+    REGISTRY.record(kind, name, value, attributes)
+}
+
+struct Registry {
+    // each metric is only locked with its shard of the map, recording different metrics rarely contends
+    metrics: DashMap<String, CustomMetric>,
+    // metrics are never removed, so they are counted without locking every shard of the map
+    metrics_count: AtomicUsize,
+    max_metrics: AtomicUsize,
+    max_attribute_sets: AtomicUsize,
+}
+
+impl Registry {
+    fn new(limits: &CustomMetricsLimits) -> Self {
+        Self {
+            metrics: DashMap::new(),
+            metrics_count: AtomicUsize::new(0),
+            max_metrics: AtomicUsize::new(limits.max_metrics),
+            max_attribute_sets: AtomicUsize::new(limits.max_attribute_sets),
+        }
+    }
+
+    fn configure(&self, limits: &CustomMetricsLimits) {
+        self.max_metrics
+            .store(limits.max_metrics, Ordering::Relaxed);
+        self.max_attribute_sets
+            .store(limits.max_attribute_sets, Ordering::Relaxed);
+    }
+
+    fn record(
+        &self,
+        kind: CustomMetricKind,
+        name: &str,
+        value: f64,
+        mut attributes: Vec<KeyValue>,
+    ) -> Result<(), CustomMetricError> {
+        if !is_valid_name(name) {
+            return Err(CustomMetricError::InvalidName(name.to_string()));
+        }
+        if !value.is_finite() || (kind == CustomMetricKind::Counter && value < 0.0) {
+            return Err(CustomMetricError::InvalidValue(name.to_string(), value));
+        }
+        if attributes.len() > MAX_ATTRIBUTES {
+            return Err(CustomMetricError::TooManyAttributes(name.to_string()));
+        }
+        attributes.sort_by(|a, b| a.key.cmp(&b.key));
+
+        let mut metric = match self.metrics.entry(name.to_string()) {
+            Entry::Occupied(entry) => entry.into_ref(),
+            Entry::Vacant(entry) => {
+                let max_metrics = self.max_metrics.load(Ordering::Relaxed);
+                if self.metrics_count.fetch_add(1, Ordering::Relaxed) >= max_metrics {
+                    self.metrics_count.fetch_sub(1, Ordering::Relaxed);
+                    tracing::warn!(
+                        "custom metric '{name}' dropped: there are already {max_metrics} custom metrics"
+                    );
+                    return Ok(());
+                }
+                entry.insert(CustomMetric::new(kind))
+            }
+        };
+        if metric.kind != kind {
+            return Err(CustomMetricError::KindMismatch(
+                name.to_string(),
+                metric.kind,
+            ));
+        }
+
+        let mut hasher = DefaultHasher::new();
+        for attribute in &attributes {
+            attribute.key.as_str().hash(&mut hasher);
+            attribute.value.to_string().hash(&mut hasher);
+        }
+        let attribute_set = hasher.finish();
+        if !metric.attribute_sets.contains(&attribute_set) {
+            let max_attribute_sets = self.max_attribute_sets.load(Ordering::Relaxed);
+            if metric.attribute_sets.len() >= max_attribute_sets {
+                tracing::warn!(
+                    "custom metric '{name}' dropped: it already has {max_attribute_sets} sets of attributes"
+                );
+                return Ok(());
+            }
+            metric.attribute_sets.insert(attribute_set);
+        }
+
+        // the instruments are recorded once the metric is unlocked
+        match kind {
+            CustomMetricKind::Counter => {
+                let counter = metric.counter(name);
+                drop(metric);
+                counter.add(value, &attributes)
+            }
+            CustomMetricKind::Histogram => {
+                let histogram = metric.histogram(name);
+                drop(metric);
+                histogram.record(value, &attributes)
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Converts JSON attributes, as sent by coprocessors
+pub(crate) fn json_attributes(
+    attributes: serde_json::Map<String, serde_json::Value>,
+) -> Result<Vec<KeyValue>, CustomMetricError> {
+    attributes
+        .into_iter()
+        .map(|(key, value)| match value {
+            serde_json::Value::String(value) => Ok(KeyValue::new(key, value)),
+            serde_json::Value::Bool(value) => Ok(KeyValue::new(key, value)),
+            serde_json::Value::Number(number) => match number.as_i64() {
+                Some(value) => Ok(KeyValue::new(key, value)),
+                None => Ok(KeyValue::new(key, number.as_f64().unwrap_or_default())),
+            },
+            other => Err(CustomMetricError::InvalidAttribute(
+                key,
+                format!("expected a string, number or boolean, found {other}"),
+            )),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_applies_configured_limits() {
+        let registry = Registry::new(&CustomMetricsLimits {
+            max_metrics: 1,
+            max_attribute_sets: 2,
+        });
+        let record = |name: &str, partner: &str| {
+            registry
+                .record(
+                    CustomMetricKind::Counter,
+                    name,
+                    1.0,
+                    vec![KeyValue::new("partner", partner.to_string())],
+                )
+                .unwrap()
+        };
+
+        record("orders", "acme");
+        record("orders", "globex");
+        record("orders", "acme");
+        record("orders", "initech");
+        record("returns", "acme");
+        assert_eq!(registry.metrics.len(), 1);
+        assert_eq!(
+            registry.metrics.get("orders").unwrap().attribute_sets.len(),
+            2
+        );
+
+        registry.configure(&CustomMetricsLimits {
+            max_metrics: 2,
+            max_attribute_sets: 3,
+        });
+        record("orders", "initech");
+        record("returns", "acme");
+        assert_eq!(registry.metrics.len(), 2);
+        assert_eq!(
+            registry.metrics.get("orders").unwrap().attribute_sets.len(),
+            3
+        );
+    }
+}
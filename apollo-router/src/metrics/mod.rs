@@ -10,6 +10,7 @@ use futures::FutureExt;
 use crate::metrics::aggregation::AggregateMeterProvider;
 
 pub(crate) mod aggregation;
+pub(crate) mod custom;
 pub(crate) mod filter;
 pub(crate) mod layer;
 
@@ -932,7 +933,7 @@ macro_rules! assert_histogram {
     };
 
     ($name:literal, $value: expr) => {
-        let result = crate::metrics::collect_metrics().assert($name, $value, &[]);
+        let result = crate::metrics::collect_metrics().assert($name, crate::metrics::test_utils::MetricType::Histogram, $value, &[]);
         assert_metric!(result, $name, None, Some($value.into()), &[]);
     };
 }
@@ -1010,8 +1011,11 @@ impl<T> FutureMetricsExt<T> for T where T: Future {}
 mod test {
     use opentelemetry_api::metrics::MeterProvider;
     use opentelemetry_api::KeyValue;
+    use serde_json::json;
 
     use crate::metrics::aggregation::MeterProviderType;
+    use crate::metrics::custom;
+    use crate::metrics::custom::CustomMetricKind;
     use crate::metrics::meter_provider;
     use crate::metrics::FutureMetricsExt;
 
@@ -1253,4 +1257,58 @@ mod test {
         test();
         assert_eq!(meter_provider().registered_instruments(), 1);
     }
+
+    #[tokio::test]
+    async fn it_records_custom_metrics() {
+        async {
+            let attributes = custom::json_attributes(
+                json!({ "partner": "acme", "items": 2 })
+                    .as_object()
+                    .unwrap()
+                    .clone(),
+            )
+            .unwrap();
+            custom::record(CustomMetricKind::Counter, "orders", 1.0, attributes.clone()).unwrap();
+            custom::record(CustomMetricKind::Counter, "orders", 2.0, attributes).unwrap();
+            assert_counter!("orders", 3.0, "partner" = "acme", "items" = 2);
+
+            custom::record(CustomMetricKind::Histogram, "basket.size", 5.0, vec![]).unwrap();
+            assert_histogram!("basket.size", 1.0);
+        }
+        .with_metrics()
+        .await;
+    }
+
+    #[tokio::test]
+    async fn it_caches_custom_metric_instruments() {
+        // Each test has its own meter provider: the instrument cached for the first one must not be reused
+        for _ in 0..2 {
+            async {
+                custom::record(CustomMetricKind::Counter, "cached.orders", 1.0, vec![]).unwrap();
+                custom::record(CustomMetricKind::Counter, "cached.orders", 2.0, vec![]).unwrap();
+                assert_counter!("cached.orders", 3.0);
+                assert_eq!(meter_provider().registered_instruments(), 1);
+            }
+            .with_metrics()
+            .await;
+        }
+    }
+
+    #[test]
+    fn it_rejects_invalid_custom_metrics() {
+        assert!(custom::record(
+            CustomMetricKind::Counter,
+            "apollo_router_requests",
+            1.0,
+            vec![]
+        )
+        .is_err());
+        assert!(custom::record(CustomMetricKind::Counter, "1st", 1.0, vec![]).is_err());
+        assert!(custom::record(CustomMetricKind::Counter, "negative", -1.0, vec![]).is_err());
+        custom::record(CustomMetricKind::Histogram, "latency", 1.0, vec![]).unwrap();
+        assert!(custom::record(CustomMetricKind::Counter, "latency", 1.0, vec![]).is_err());
+        assert!(
+            custom::json_attributes(json!({ "list": [1] }).as_object().unwrap().clone()).is_err()
+        );
+    }
 }
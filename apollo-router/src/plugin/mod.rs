@@ -529,6 +529,15 @@ pub(crate) trait PluginPrivate: Send + Sync + 'static {
         service
     }
 
+    /// This service runs right after the query planner cache, which means that it will be called once per unique
+    /// query, unless the cache entry was evicted
+    fn query_planner_service(
+        &self,
+        service: crate::services::query_planner::BoxService,
+    ) -> crate::services::query_planner::BoxService {
+        service
+    }
+
     /// This service handles HTTP communication
     fn http_client_service(
         &self,
@@ -636,6 +645,13 @@ pub(crate) trait DynPlugin: Send + Sync + 'static {
         service: subgraph::BoxService,
     ) -> subgraph::BoxService;
 
+    /// This service runs right after the query planner cache, which means that it will be called once per unique
+    /// query, unless the cache entry was evicted
+    fn query_planner_service(
+        &self,
+        service: crate::services::query_planner::BoxService,
+    ) -> crate::services::query_planner::BoxService;
+
     /// This service handles HTTP communication
     fn http_client_service(
         &self,
@@ -678,6 +694,13 @@ where
         self.subgraph_service(name, service)
     }
 
+    fn query_planner_service(
+        &self,
+        service: crate::services::query_planner::BoxService,
+    ) -> crate::services::query_planner::BoxService {
+        self.query_planner_service(service)
+    }
+
     /// This service handles HTTP communication
     fn http_client_service(
         &self,
@@ -0,0 +1,294 @@
+//! API key authentication
+//!
+//! API keys are looked up by their SHA-256 hash in a file that maps them to claims, so the file never contains the keys
+//! themselves. The file is watched: keys can be added and revoked without restarting the router.
+
+use std::collections::HashMap;
+use std::ops::ControlFlow;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use arc_swap::ArcSwap;
+use futures::StreamExt;
+use http::StatusCode;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json::Map;
+use serde_json::Value;
+use sha2::Digest;
+use sha2::Sha256;
+use tokio::sync::oneshot;
+use tower::BoxError;
+
+use super::authentication_failure;
+use super::authentication_success;
+use super::AuthenticationError;
+use super::APOLLO_AUTHENTICATION_JWT_CLAIMS;
+use crate::services::router;
+
+const AUTHENTICATION_KIND: &str = "API_KEY";
+
+/// API key authentication
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(super) struct ApiKeyConf {
+    /// Path of the file of hashed API keys, reloaded when it changes
+    path: PathBuf,
+    /// HTTP header expected to contain the API key
+    #[serde(default = "default_header_name")]
+    header_name: String,
+    /// Query parameter expected to contain the API key, when the header is absent
+    query_parameter: Option<String>,
+}
+
+fn default_header_name() -> String {
+    "x-api-key".to_string()
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeysFile {
+    keys: Vec<KeyEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeyEntry {
+    /// Hex encoded SHA-256 hash of the key
+    #[serde(deserialize_with = "hex::deserialize")]
+    sha256: [u8; 32],
+    /// The claims of the requests authenticated with the key
+    #[serde(default)]
+    claims: Map<String, Value>,
+}
+
+type Keys = HashMap<[u8; 32], Value>;
+
+pub(super) struct ApiKeyStore {
+    conf: ApiKeyConf,
+    keys: Arc<ArcSwap<Keys>>,
+    _drop_signal: oneshot::Sender<()>,
+}
+
+impl ApiKeyStore {
+    pub(super) async fn new(conf: ApiKeyConf) -> Result<Self, BoxError> {
+        let keys = load_keys(&conf.path)
+            .await
+            .map_err(|e| format!("could not load the API keys from {:?}: {e}", conf.path))?;
+        tracing::info!(path = ?conf.path, "API key authentication using {} keys", keys.len());
+
+        let keys = Arc::new(ArcSwap::from_pointee(keys));
+        let (_drop_signal, drop_receiver) = oneshot::channel::<()>();
+        tokio::task::spawn(watch(conf.path.clone(), keys.clone(), drop_receiver));
+
+        Ok(Self {
+            conf,
+            keys,
+            _drop_signal,
+        })
+    }
+
+    fn claims(&self, key: &str) -> Option<Value> {
+        let hash: [u8; 32] = Sha256::digest(key.as_bytes()).into();
+        self.keys.load().get(&hash).cloned()
+    }
+
+    fn extract_key(&self, request: &router::Request) -> Option<String> {
+        if let Some(value) = request.router_request.headers().get(&self.conf.header_name) {
+            // a header that is not a string can't match any key
+            return Some(value.to_str().unwrap_or_default().trim().to_string());
+        }
+        let name = self.conf.query_parameter.as_ref()?;
+        let query = request.router_request.uri().query()?;
+        url::form_urlencoded::parse(query.as_bytes())
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.into_owned())
+    }
+}
+
+async fn load_keys(path: &Path) -> Result<Keys, BoxError> {
+    // YAML is a superset of JSON, both formats are accepted
+    let file: KeysFile = serde_yaml::from_str(&tokio::fs::read_to_string(path).await?)?;
+    let mut keys = HashMap::with_capacity(file.keys.len());
+    for entry in file.keys {
+        if keys
+            .insert(entry.sha256, Value::Object(entry.claims))
+            .is_some()
+        {
+            return Err(format!("duplicate key hash {}", hex::encode(entry.sha256)).into());
+        }
+    }
+    Ok(keys)
+}
+
+// Reloads the keys when the file changes, until the store is dropped. An invalid file is reported and
+// the previous keys are kept.
+async fn watch(path: PathBuf, keys: Arc<ArcSwap<Keys>>, mut drop_receiver: oneshot::Receiver<()>) {
+    let mut changes = Box::pin(crate::files::watch(&path));
+    loop {
+        tokio::select! {
+            // the _drop_signal was dropped, we must shut down the task
+            _ = &mut drop_receiver => return,
+            change = changes.next() => {
+                if change.is_none() {
+                    return;
+                }
+                match load_keys(&path).await {
+                    Ok(new_keys) => {
+                        if **keys.load() != new_keys {
+                            tracing::info!(
+                                path = ?path,
+                                "API keys reloaded: {} keys",
+                                new_keys.len()
+                            );
+                            keys.store(Arc::new(new_keys));
+                        }
+                    }
+                    Err(e) => tracing::error!(
+                        path = ?path,
+                        "could not reload the API keys, the previous keys are kept: {e}"
+                    ),
+                }
+            }
+        }
+    }
+}
+
+pub(super) fn authenticate(
+    store: &ApiKeyStore,
+    request: router::Request,
+) -> ControlFlow<router::Response, router::Request> {
+    let Some(key) = store.extract_key(&request) else {
+        return ControlFlow::Continue(request);
+    };
+
+    match store.claims(&key) {
+        Some(claims) => {
+            if let Err(e) = request
+                .context
+                .insert(APOLLO_AUTHENTICATION_JWT_CLAIMS, claims)
+            {
+                return authentication_failure(
+                    request.context,
+                    AuthenticationError::CannotInsertClaimsIntoContext(e),
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    AUTHENTICATION_KIND,
+                );
+            }
+            authentication_success(AUTHENTICATION_KIND);
+            ControlFlow::Continue(request)
+        }
+        None => authentication_failure(
+            request.context,
+            AuthenticationError::InvalidApiKey,
+            StatusCode::UNAUTHORIZED,
+            AUTHENTICATION_KIND,
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::files::tests::create_temp_file;
+    use crate::files::tests::write_and_flush;
+
+    fn keys_file(keys: &[(&str, Value)]) -> String {
+        let keys: Vec<Value> = keys
+            .iter()
+            .map(|(key, claims)| {
+                json!({
+                    "sha256": hex::encode(Sha256::digest(key.as_bytes())),
+                    "claims": claims,
+                })
+            })
+            .collect();
+        serde_json::to_string(&json!({ "keys": keys })).unwrap()
+    }
+
+    async fn store(path: PathBuf) -> ApiKeyStore {
+        ApiKeyStore::new(
+            serde_json::from_value(json!({
+                "path": path,
+                "query_parameter": "api_key",
+            }))
+            .unwrap(),
+        )
+        .await
+        .unwrap()
+    }
+
+    fn authenticate_with(
+        store: &ApiKeyStore,
+        request: router::Request,
+    ) -> Result<Option<Value>, StatusCode> {
+        match authenticate(store, request) {
+            ControlFlow::Continue(request) => Ok(request
+                .context
+                .get(APOLLO_AUTHENTICATION_JWT_CLAIMS)
+                .unwrap()),
+            ControlFlow::Break(response) => Err(response.response.status()),
+        }
+    }
+
+    fn header_request(key: &str) -> router::Request {
+        router::Request::fake_builder()
+            .header("x-api-key", key)
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn api_keys_are_authenticated() {
+        let (path, mut file) = create_temp_file();
+        let claims = json!({"client_name": "partner", "scope": "read:products", "tier": "gold"});
+        write_and_flush(&mut file, &keys_file(&[("partner-key", claims.clone())])).await;
+        let store = store(path).await;
+
+        assert_eq!(
+            authenticate_with(&store, header_request("partner-key")),
+            Ok(Some(claims.clone()))
+        );
+        let query_request = router::Request::fake_builder()
+            .uri(http::Uri::from_static(
+                "http://localhost/graphql?api_key=partner-key",
+            ))
+            .build()
+            .unwrap();
+        assert_eq!(authenticate_with(&store, query_request), Ok(Some(claims)));
+        assert_eq!(
+            authenticate_with(&store, header_request("unknown-key")),
+            Err(StatusCode::UNAUTHORIZED)
+        );
+        assert_eq!(
+            authenticate_with(&store, router::Request::fake_builder().build().unwrap()),
+            Ok(None)
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn api_keys_are_reloaded() {
+        let (path, mut file) = create_temp_file();
+        write_and_flush(&mut file, &keys_file(&[("revoked-key", json!({}))])).await;
+        let store = store(path).await;
+        assert!(authenticate_with(&store, header_request("revoked-key")).is_ok());
+
+        write_and_flush(&mut file, &keys_file(&[("new-key", json!({}))])).await;
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert_eq!(
+            authenticate_with(&store, header_request("revoked-key")),
+            Err(StatusCode::UNAUTHORIZED)
+        );
+        assert!(authenticate_with(&store, header_request("new-key")).is_ok());
+
+        // invalid files are ignored
+        write_and_flush(&mut file, "keys: invalid").await;
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert!(authenticate_with(&store, header_request("new-key")).is_ok());
+    }
+}
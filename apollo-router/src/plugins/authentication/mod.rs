@@ -3,11 +3,13 @@
 use std::collections::HashMap;
 use std::ops::ControlFlow;
 use std::str::FromStr;
+use std::sync::Arc;
 use std::time::Duration;
 use std::time::SystemTime;
 use std::time::UNIX_EPOCH;
 
 use displaydoc::Display;
+use futures::FutureExt;
 use http::HeaderMap;
 use http::HeaderName;
 use http::HeaderValue;
@@ -36,6 +38,10 @@ use tower::ServiceBuilder;
 use tower::ServiceExt;
 use url::Url;
 
+use self::api_key::ApiKeyConf;
+use self::api_key::ApiKeyStore;
+use self::introspection::IntrospectionConf;
+use self::introspection::Introspector;
 use self::jwks::JwksManager;
 use self::subgraph::SigningParams;
 use self::subgraph::SigningParamsConfig;
@@ -52,6 +58,8 @@ use crate::register_plugin;
 use crate::services::router;
 use crate::Context;
 
+mod api_key;
+mod introspection;
 mod jwks;
 pub(crate) mod subgraph;
 
@@ -99,6 +107,15 @@ pub(crate) enum AuthenticationError<'a> {
 
     /// Unsupported key algorithm: {0}
     UnsupportedKeyAlgorithm(KeyAlgorithm),
+
+    /// Inactive access token
+    InactiveToken,
+
+    /// Cannot introspect access token: {0}
+    CannotIntrospectToken(BoxError),
+
+    /// Invalid API key
+    InvalidApiKey,
 }
 
 const DEFAULT_AUTHENTICATION_NETWORK_TIMEOUT: Duration = Duration::from_secs(15);
@@ -114,11 +131,13 @@ pub(crate) enum Error {
 
 struct Router {
     configuration: JWTConf,
-    jwks_manager: JwksManager,
+    jwks_manager: Arc<JwksManager>,
 }
 
 struct AuthenticationPlugin {
     router: Option<Router>,
+    introspection: Option<Arc<Introspector>>,
+    api_key: Option<Arc<ApiKeyStore>>,
     subgraph: Option<SubgraphAuth>,
 }
 
@@ -204,14 +223,16 @@ struct Conf {
     subgraph: Option<subgraph::Config>,
 }
 
-// We may support additional authentication mechanisms in future, so all
-// configuration (which is currently JWT specific) is isolated to the
-// JWTConf structure.
+// The configuration of each authentication mechanism is isolated in its own structure.
 #[derive(Clone, Debug, Default, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
 struct RouterConf {
     /// The JWT configuration
-    jwt: JWTConf,
+    jwt: Option<JWTConf>,
+    /// OAuth2 token introspection (RFC 7662), for opaque access tokens
+    introspection: Option<IntrospectionConf>,
+    /// API keys, checked against a file of hashed keys
+    api_key: Option<ApiKeyConf>,
 }
 
 fn default_header_name() -> String {
@@ -430,9 +451,9 @@ impl Plugin for AuthenticationPlugin {
             None
         };
 
-        let router = if let Some(mut router_conf) = init.config.router {
-            if router_conf
-                .jwt
+        let router_conf = init.config.router.unwrap_or_default();
+        let router = if let Some(mut jwt_conf) = router_conf.jwt {
+            if jwt_conf
                 .header_value_prefix
                 .as_bytes()
                 .iter()
@@ -441,7 +462,7 @@ impl Plugin for AuthenticationPlugin {
                 return Err(Error::BadHeaderValuePrefix.into());
             }
 
-            for source in &router_conf.jwt.sources {
+            for source in &jwt_conf.sources {
                 if let Source::Header { value_prefix, .. } = source {
                     if value_prefix.as_bytes().iter().any(u8::is_ascii_whitespace) {
                         return Err(Error::BadHeaderValuePrefix.into());
@@ -449,16 +470,16 @@ impl Plugin for AuthenticationPlugin {
                 }
             }
 
-            router_conf.jwt.sources.insert(
+            jwt_conf.sources.insert(
                 0,
                 Source::Header {
-                    name: router_conf.jwt.header_name.clone(),
-                    value_prefix: router_conf.jwt.header_value_prefix.clone(),
+                    name: jwt_conf.header_name.clone(),
+                    value_prefix: jwt_conf.header_value_prefix.clone(),
                 },
             );
 
             let mut list = vec![];
-            for jwks_conf in &router_conf.jwt.jwks {
+            for jwks_conf in &jwt_conf.jwks {
                 let url: Url = Url::from_str(jwks_conf.url.as_str())?;
                 list.push(JwksConfig {
                     url,
@@ -472,45 +493,95 @@ impl Plugin for AuthenticationPlugin {
                 });
             }
 
-            tracing::info!(jwks=?router_conf.jwt.jwks, "JWT authentication using JWKSets from");
+            tracing::info!(jwks=?jwt_conf.jwks, "JWT authentication using JWKSets from");
 
-            let jwks_manager = JwksManager::new(list).await?;
+            let jwks_manager = Arc::new(JwksManager::new(list).await?);
 
             Some(Router {
-                configuration: router_conf.jwt,
+                configuration: jwt_conf,
                 jwks_manager,
             })
         } else {
             None
         };
 
-        Ok(Self { router, subgraph })
+        let introspection = router_conf
+            .introspection
+            .map(Introspector::new)
+            .transpose()?
+            .map(Arc::new);
+
+        let api_key = match router_conf.api_key {
+            Some(conf) => Some(Arc::new(ApiKeyStore::new(conf).await?)),
+            None => None,
+        };
+
+        Ok(Self {
+            router,
+            introspection,
+            api_key,
+            subgraph,
+        })
     }
 
     fn router_service(&self, service: router::BoxService) -> router::BoxService {
-        if let Some(config) = &self.router {
+        let service = if let Some(config) = &self.router {
             let jwks_manager = config.jwks_manager.clone();
             let configuration = config.configuration.clone();
 
-            fn authentication_service_span() -> impl Fn(&router::Request) -> tracing::Span + Clone {
-                move |_request: &router::Request| {
-                    tracing::info_span!(
-                        AUTHENTICATION_SPAN_NAME,
-                        "authentication service" = stringify!(router::Request),
-                        "otel.kind" = "INTERNAL"
-                    )
-                }
-            }
-
             ServiceBuilder::new()
                 .instrument(authentication_service_span())
                 .checkpoint(move |request: router::Request| {
+                    // The JWKS of this pipeline, used by the `jwt::verify` function of Rhai scripts
+                    request
+                        .context
+                        .extensions()
+                        .lock()
+                        .insert(jwks_manager.clone());
                     Ok(authenticate(&configuration, &jwks_manager, request))
                 })
                 .service(service)
                 .boxed()
         } else {
             service
+        };
+
+        // Introspection runs before the JWT authentication, and leaves the JWTs to it
+        let service = if let Some(introspector) = &self.introspection {
+            let introspector = introspector.clone();
+            let jwt_configured = self.router.is_some();
+
+            ServiceBuilder::new()
+                .instrument(authentication_service_span())
+                .oneshot_checkpoint_async(move |request: router::Request| {
+                    let introspector = introspector.clone();
+                    async move {
+                        Ok(
+                            introspection::authenticate(&introspector, jwt_configured, request)
+                                .await,
+                        )
+                    }
+                    .boxed()
+                })
+                .service(service)
+                .boxed()
+        } else {
+            service
+        };
+
+        // API keys are checked first. The other mechanisms skip the requests they authenticated
+        if let Some(store) = &self.api_key {
+            let store = store.clone();
+
+            ServiceBuilder::new()
+                .instrument(authentication_service_span())
+                .checkpoint(move |request: router::Request| {
+                    Ok(api_key::authenticate(&store, request))
+                })
+                .service(service)
+                .boxed()
+        } else {
+            service
         }
     }
 
@@ -527,6 +598,50 @@ impl Plugin for AuthenticationPlugin {
     }
 }
 
+fn authentication_service_span() -> impl Fn(&router::Request) -> tracing::Span + Clone {
+    move |_request: &router::Request| {
+        tracing::info_span!(
+            AUTHENTICATION_SPAN_NAME,
+            "authentication service" = stringify!(router::Request),
+            "otel.kind" = "INTERNAL"
+        )
+    }
+}
+
+/// Rejects the request, for the authentication mechanisms other than JWT
+fn authentication_failure(
+    context: Context,
+    error: AuthenticationError,
+    status: StatusCode,
+    kind: &'static str,
+) -> ControlFlow<router::Response, router::Request> {
+    // This is a metric and will not appear in the logs
+    tracing::info!(
+        monotonic_counter.apollo_authentication_failure_count = 1u64,
+        kind = %kind
+    );
+    tracing::info!(message = %error, kind = %kind, "authentication failure");
+    let response = router::Response::infallible_builder()
+        .error(
+            graphql::Error::builder()
+                .message(error.to_string())
+                .extension_code("AUTH_ERROR")
+                .build(),
+        )
+        .status_code(status)
+        .context(context)
+        .build();
+    ControlFlow::Break(response)
+}
+
+fn authentication_success(kind: &'static str) {
+    // This is a metric and will not appear in the logs
+    tracing::info!(
+        monotonic_counter.apollo_authentication_success_count = 1u64,
+        kind = %kind
+    );
+}
+
 fn authenticate(
     config: &JWTConf,
     jwks_manager: &JwksManager,
@@ -569,6 +684,14 @@ fn authenticate(
         ControlFlow::Break(response)
     }
 
+    // The request was already authenticated by another mechanism
+    if request
+        .context
+        .contains_key(APOLLO_AUTHENTICATION_JWT_CLAIMS)
+    {
+        return ControlFlow::Continue(request);
+    }
+
     let mut jwt = None;
     for source in &config.sources {
         match extract_jwt(source, request.router_request.headers()) {
@@ -838,6 +961,58 @@ fn decode_jwt(
     }
 }
 
+/// Verifies a JWT with the JWKS of the JWT authentication that handled the request, and returns its claims
+pub(crate) fn verify_jwt(context: &Context, jwt: &str) -> Result<Value, BoxError> {
+    let jwks_manager = context
+        .extensions()
+        .lock()
+        .get::<Arc<JwksManager>>()
+        .cloned()
+        .ok_or("JWT authentication is not configured")?;
+    verify_jwt_with(&jwks_manager, jwt)
+}
+
+fn verify_jwt_with(jwks_manager: &JwksManager, jwt: &str) -> Result<Value, BoxError> {
+    let jwt_header = decode_header(jwt)
+        .map_err(|e| AuthenticationError::InvalidHeader(HEADER_TOKEN_TRUNCATED, e).to_string())?;
+    let criteria = JWTCriteria {
+        kid: jwt_header.kid,
+        alg: jwt_header.alg,
+    };
+
+    let keys = match search_jwks(jwks_manager, &criteria) {
+        Some(keys) => keys,
+        None if criteria.kid.is_some() => {
+            return Err(AuthenticationError::CannotFindKID(criteria.kid)
+                .to_string()
+                .into())
+        }
+        None => {
+            return Err(
+                AuthenticationError::CannotFindSuitableKey(criteria.alg, criteria.kid)
+                    .to_string()
+                    .into(),
+            )
+        }
+    };
+    let (issuer, token_data) =
+        decode_jwt(jwt, keys, criteria).map_err(|(error, _)| error.to_string())?;
+
+    if let Some(configured_issuer) = issuer {
+        if let Some(token_issuer) = token_data.claims.get("iss").and_then(Value::as_str) {
+            if configured_issuer != token_issuer {
+                return Err(AuthenticationError::InvalidIssuer {
+                    expected: configured_issuer,
+                    token: token_issuer.to_string(),
+                }
+                .to_string()
+                .into());
+            }
+        }
+    }
+    Ok(token_data.claims)
+}
+
 pub(crate) fn jwt_expires_in(context: &Context) -> Duration {
     let claims = context
         .get(APOLLO_AUTHENTICATION_JWT_CLAIMS)
@@ -0,0 +1,398 @@
+//! Asynchronous coprocessor stages
+//!
+//! An asynchronous stage doesn't wait for the coprocessor: its payload is queued, and the stage continues as if the
+//! coprocessor returned it unmodified. A background task sends the queued payloads in batches, as JSON arrays.
+//!
+//! The queues are created once per stage with the plugin, and shared by the services it creates.
+
+use std::task::Poll;
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures::future::BoxFuture;
+use http::header::CONTENT_TYPE;
+use http::Method;
+use hyper::Body;
+use mime::APPLICATION_JSON;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use tokio::sync::mpsc;
+use tower::BoxError;
+use tower::Service;
+use tower::ServiceExt;
+
+use crate::plugins::coprocessor::failure::OnError;
+use crate::plugins::coprocessor::failure::OnErrorService;
+use crate::plugins::coprocessor::transport::StageBatch;
+use crate::plugins::coprocessor::transport::StagePayload;
+use crate::services::external::PipelineStep;
+
+/// Configuration of the queues of the asynchronous stages
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, default)]
+pub(super) struct AsyncQueueConf {
+    /// Maximum number of payloads waiting to be sent, per stage. Payloads are dropped when the queue is full
+    /// (default: 1024)
+    pub(super) capacity: usize,
+    /// Maximum number of payloads sent in one batch (default: 64)
+    pub(super) batch_size: usize,
+    /// Maximum time the first payload of a batch waits for the batch to fill up (default: 100ms)
+    #[serde(deserialize_with = "humantime_serde::deserialize")]
+    #[schemars(with = "String")]
+    pub(super) batch_timeout: Duration,
+}
+
+impl Default for AsyncQueueConf {
+    fn default() -> Self {
+        Self {
+            capacity: 1024,
+            batch_size: 64,
+            batch_timeout: Duration::from_millis(100),
+        }
+    }
+}
+
+/// Queues of the asynchronous request and response of a stage, `None` for the synchronous ones
+#[derive(Clone, Default)]
+pub(crate) struct StageQueues {
+    pub(super) request: Option<AsyncQueue>,
+    pub(super) response: Option<AsyncQueue>,
+}
+
+/// Client of a stage: asynchronous stages queue their payloads, the others call the coprocessor
+#[derive(Clone)]
+pub(super) enum StageClient<C> {
+    Sync(OnErrorService<C>),
+    Async(AsyncQueue),
+}
+
+impl<C> StageClient<C>
+where
+    C: Service<hyper::Request<Body>, Response = hyper::Response<Body>, Error = BoxError>
+        + Send
+        + 'static,
+    C::Future: Send + 'static,
+{
+    /// Asynchronous stages use the queue created with the plugin
+    pub(super) fn new(
+        client: C,
+        stage: PipelineStep,
+        on_error: Option<OnError>,
+        queue: Option<&AsyncQueue>,
+        coprocessor_name: &str,
+    ) -> Self {
+        match queue {
+            Some(queue) => StageClient::Async(queue.clone()),
+            None => StageClient::Sync(OnErrorService::new(
+                client,
+                stage,
+                on_error,
+                coprocessor_name.to_string(),
+            )),
+        }
+    }
+}
+
+impl<C> Service<hyper::Request<Body>> for StageClient<C>
+where
+    C: Service<hyper::Request<Body>, Response = hyper::Response<Body>, Error = BoxError>
+        + Clone
+        + Send
+        + 'static,
+    C::Future: Send + 'static,
+{
+    type Response = hyper::Response<Body>;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        match self {
+            StageClient::Sync(client) => client.poll_ready(cx),
+            StageClient::Async(_) => Poll::Ready(Ok(())),
+        }
+    }
+
+    fn call(&mut self, request: hyper::Request<Body>) -> Self::Future {
+        match self {
+            StageClient::Sync(client) => client.call(request),
+            StageClient::Async(queue) => Box::pin(queue.clone().enqueue(request)),
+        }
+    }
+}
+
+/// Bounded queue of the payloads of an asynchronous stage
+#[derive(Clone)]
+pub(super) struct AsyncQueue {
+    stage: PipelineStep,
+    coprocessor_name: String,
+    sender: mpsc::Sender<QueuedPayload>,
+}
+
+/// JSON payload of an asynchronous stage, and the stage data used by the gRPC transport
+struct QueuedPayload {
+    body: Bytes,
+    stage_payload: Option<StagePayload>,
+}
+
+impl AsyncQueue {
+    /// Creates the queue of an asynchronous stage, and spawns the task sending its batches
+    pub(super) fn new<C>(
+        client: C,
+        stage: PipelineStep,
+        coprocessor_url: String,
+        coprocessor_name: String,
+        configuration: &AsyncQueueConf,
+    ) -> Self
+    where
+        C: Service<hyper::Request<Body>, Response = hyper::Response<Body>, Error = BoxError>
+            + Send
+            + 'static,
+        C::Future: Send + 'static,
+    {
+        let (sender, receiver) = mpsc::channel(configuration.capacity.max(1));
+        // stops when the plugin and its services are dropped, after sending the queued payloads
+        tokio::spawn(send_batches(
+            client,
+            stage,
+            coprocessor_url,
+            coprocessor_name.clone(),
+            configuration.batch_size.max(1),
+            configuration.batch_timeout,
+            receiver,
+        ));
+        Self {
+            stage,
+            coprocessor_name,
+            sender,
+        }
+    }
+
+    async fn enqueue(
+        self,
+        request: hyper::Request<Body>,
+    ) -> Result<hyper::Response<Body>, BoxError> {
+        let (mut parts, body) = request.into_parts();
+        let sent = hyper::body::to_bytes(body).await?;
+        let payload = QueuedPayload {
+            body: sent.clone(),
+            stage_payload: parts.extensions.remove::<StagePayload>(),
+        };
+        if self.sender.try_send(payload).is_err() {
+            tracing::warn!(
+                coprocessor.name = self.coprocessor_name.as_str(),
+                coprocessor.stage = %self.stage,
+                "the asynchronous coprocessor queue is full, dropping the payload"
+            );
+            u64_counter!(
+                "apollo.router.operations.coprocessor.async.dropped",
+                "Payloads of asynchronous coprocessor stages dropped because the queue was full",
+                1,
+                "coprocessor.name" = self.coprocessor_name.clone(),
+                "coprocessor.stage" = self.stage
+            );
+        }
+
+        // the control returned by the coprocessor is ignored
+        Ok(hyper::Response::new(Body::from(
+            OnError::FailOpen.response(self.stage, &sent)?,
+        )))
+    }
+}
+
+async fn send_batches<C>(
+    mut client: C,
+    stage: PipelineStep,
+    coprocessor_url: String,
+    coprocessor_name: String,
+    batch_size: usize,
+    batch_timeout: Duration,
+    mut receiver: mpsc::Receiver<QueuedPayload>,
+) where
+    C: Service<hyper::Request<Body>, Response = hyper::Response<Body>, Error = BoxError>,
+{
+    let mut batch = Vec::with_capacity(batch_size);
+    while let Some(payload) = receiver.recv().await {
+        batch.push(payload);
+
+        let timeout = tokio::time::sleep(batch_timeout);
+        tokio::pin!(timeout);
+        while batch.len() < batch_size {
+            tokio::select! {
+                payload = receiver.recv() => match payload {
+                    Some(payload) => batch.push(payload),
+                    None => break,
+                },
+                _ = &mut timeout => break,
+            }
+        }
+
+        let size = batch.len();
+        if let Err(error) = send_batch(&mut client, &coprocessor_url, &mut batch).await {
+            tracing::warn!(
+                coprocessor.name = coprocessor_name.as_str(),
+                coprocessor.stage = %stage,
+                "could not send {size} payloads to the coprocessor: {error}"
+            );
+            u64_counter!(
+                "apollo.router.operations.coprocessor.async.failed",
+                "Payloads of asynchronous coprocessor stages that could not be sent",
+                size as u64,
+                "coprocessor.name" = coprocessor_name.clone(),
+                "coprocessor.stage" = stage
+            );
+        }
+    }
+}
+
+async fn send_batch<C>(
+    client: &mut C,
+    coprocessor_url: &str,
+    batch: &mut Vec<QueuedPayload>,
+) -> Result<(), BoxError>
+where
+    C: Service<hyper::Request<Body>, Response = hyper::Response<Body>, Error = BoxError>,
+{
+    let mut body = Vec::with_capacity(
+        batch
+            .iter()
+            .map(|payload| payload.body.len() + 1)
+            .sum::<usize>()
+            + 1,
+    );
+    let mut stage_payloads = Some(Vec::with_capacity(batch.len()));
+    body.push(b'[');
+    for (index, payload) in batch.drain(..).enumerate() {
+        if index > 0 {
+            body.push(b',');
+        }
+        body.extend_from_slice(&payload.body);
+        stage_payloads =
+            stage_payloads
+                .zip(payload.stage_payload)
+                .map(|(mut payloads, payload)| {
+                    payloads.push(payload);
+                    payloads
+                });
+    }
+    body.push(b']');
+
+    let mut request = hyper::Request::builder()
+        .uri(coprocessor_url)
+        .method(Method::POST)
+        .header(CONTENT_TYPE, APPLICATION_JSON.essence_str())
+        .body(Body::from(body))?;
+    if let Some(stage_payloads) = stage_payloads {
+        request.extensions_mut().insert(StageBatch(stage_payloads));
+    }
+    let response = client.ready().await?.call(request).await?;
+    if !response.status().is_success() {
+        return Err(format!("coprocessor returned status {}", response.status()).into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::future::BoxFuture;
+    use serde_json::json;
+    use serde_json::Value;
+
+    use super::*;
+    use crate::plugin::test::MockHttpClientService;
+
+    #[tokio::test]
+    async fn asynchronous_stages_send_batches() {
+        let (batches, mut received) = mpsc::unbounded_channel();
+        let mut mock = MockHttpClientService::new();
+        mock.expect_call()
+            .returning(move |request: hyper::Request<Body>| {
+                let batches = batches.clone();
+                Box::pin(async move {
+                    let body = hyper::body::to_bytes(request.into_body()).await.unwrap();
+                    batches
+                        .send(serde_json::from_slice::<Value>(&body).unwrap())
+                        .unwrap();
+                    Ok(hyper::Response::new(Body::empty()))
+                }) as BoxFuture<'static, _>
+            });
+
+        let queue = AsyncQueue::new(
+            mock,
+            PipelineStep::SupergraphResponse,
+            "http://test".to_string(),
+            "test".to_string(),
+            &AsyncQueueConf::default(),
+        );
+        let mut client = StageClient::new(
+            MockHttpClientService::new(),
+            PipelineStep::SupergraphResponse,
+            None,
+            Some(&queue),
+            "test",
+        );
+        for id in ["1", "2"] {
+            let payload = json!({
+                "version": 1,
+                "stage": "SupergraphResponse",
+                "control": { "break": 400 },
+                "id": id,
+            });
+            let response = client
+                .ready()
+                .await
+                .unwrap()
+                .call(hyper::Request::new(Body::from(payload.to_string())))
+                .await
+                .unwrap();
+            // the stage continues with the payload that was sent
+            let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            assert_eq!(serde_json::from_slice::<Value>(&body).unwrap(), payload);
+        }
+        drop(client);
+        drop(queue);
+
+        let batch = received.recv().await.unwrap();
+        assert_eq!(batch[0]["id"], "1");
+        assert_eq!(batch[1]["id"], "2");
+        assert!(received.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn asynchronous_stages_use_the_configured_batch_size() {
+        let (batches, mut received) = mpsc::unbounded_channel();
+        let mut mock = MockHttpClientService::new();
+        mock.expect_call()
+            .returning(move |request: hyper::Request<Body>| {
+                let batches = batches.clone();
+                Box::pin(async move {
+                    let body = hyper::body::to_bytes(request.into_body()).await.unwrap();
+                    batches
+                        .send(serde_json::from_slice::<Vec<Value>>(&body).unwrap())
+                        .unwrap();
+                    Ok(hyper::Response::new(Body::empty()))
+                }) as BoxFuture<'static, _>
+            });
+
+        let queue = AsyncQueue::new(
+            mock,
+            PipelineStep::SubgraphRequest,
+            "http://test".to_string(),
+            "test".to_string(),
+            &serde_json::from_value(json!({ "batch_size": 2, "batch_timeout": "10s" })).unwrap(),
+        );
+        for id in ["1", "2", "3"] {
+            let payload = json!({ "version": 1, "stage": "SubgraphRequest", "id": id });
+            queue
+                .clone()
+                .enqueue(hyper::Request::new(Body::from(payload.to_string())))
+                .await
+                .unwrap();
+        }
+        drop(queue);
+
+        // the first batch is full, the second one is sent when the queue is dropped
+        assert_eq!(received.recv().await.unwrap().len(), 2);
+        assert_eq!(received.recv().await.unwrap().len(), 1);
+        assert!(received.recv().await.is_none());
+    }
+}
@@ -1,6 +1,7 @@
 use std::ops::ControlFlow;
 use std::sync::Arc;
 
+use derivative::Derivative;
 use futures::future;
 use futures::stream;
 use schemars::JsonSchema;
@@ -20,7 +21,8 @@ use crate::response;
 use crate::services::execution;
 
 /// What information is passed to a router request/response stage
-#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize, JsonSchema)]
+#[derive(Clone, Debug, Default, Deserialize, Derivative, Serialize, JsonSchema)]
+#[derivative(PartialEq)]
 #[serde(default, deny_unknown_fields)]
 pub(super) struct ExecutionRequestConf {
     /// Send the headers
@@ -35,10 +37,18 @@ pub(super) struct ExecutionRequestConf {
     pub(super) method: bool,
     /// Send the query plan
     pub(super) query_plan: bool,
+    /// Queue the payload and continue without waiting for the coprocessor, whose control is ignored
+    #[serde(rename = "async")]
+    pub(super) is_async: bool,
+    /// What to do when the coprocessor call fails (default: fail the request)
+    #[serde(skip_serializing)]
+    #[derivative(PartialEq = "ignore")]
+    pub(super) on_error: Option<OnError>,
 }
 
 /// What information is passed to a router request/response stage
-#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize, JsonSchema)]
+#[derive(Clone, Debug, Deserialize, Derivative, Serialize, JsonSchema)]
+#[derivative(PartialEq)]
 #[serde(default, deny_unknown_fields)]
 pub(super) struct ExecutionResponseConf {
     /// Send the headers
@@ -51,6 +61,30 @@ pub(super) struct ExecutionResponseConf {
     pub(super) sdl: bool,
     /// Send the HTTP status
     pub(super) status_code: bool,
+    /// Send the subsequent chunks of deferred responses and subscription events (default: true)
+    pub(super) stream: bool,
+    /// Queue the payload and continue without waiting for the coprocessor, whose control is ignored
+    #[serde(rename = "async")]
+    pub(super) is_async: bool,
+    /// What to do when the coprocessor call fails (default: fail the request)
+    #[serde(skip_serializing)]
+    #[derivative(PartialEq = "ignore")]
+    pub(super) on_error: Option<OnError>,
+}
+
+impl Default for ExecutionResponseConf {
+    fn default() -> Self {
+        Self {
+            headers: false,
+            context: false,
+            body: false,
+            sdl: false,
+            status_code: false,
+            stream: true,
+            is_async: false,
+            on_error: None,
+        }
+    }
 }
 
 #[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize, JsonSchema)]
@@ -68,6 +102,8 @@ impl ExecutionStage {
         http_client: C,
         service: execution::BoxService,
         coprocessor_url: String,
+        coprocessor_name: String,
+        queues: &StageQueues,
         sdl: Arc<String>,
     ) -> execution::BoxService
     where
@@ -78,15 +114,24 @@ impl ExecutionStage {
             + 'static,
         <C as tower::Service<http::Request<Body>>>::Future: Send + 'static,
     {
+        let service_span = external_service_span(coprocessor_name.clone());
         let request_layer = (self.request != Default::default()).then_some({
             let request_config = self.request.clone();
             let coprocessor_url = coprocessor_url.clone();
-            let http_client = http_client.clone();
+            let coprocessor_name = coprocessor_name.clone();
+            let http_client = StageClient::new(
+                http_client.clone(),
+                PipelineStep::ExecutionRequest,
+                request_config.on_error.clone(),
+                queues.request.as_ref(),
+                &coprocessor_name,
+            );
             let sdl = sdl.clone();
 
             OneShotAsyncCheckpointLayer::new(move |request: execution::Request| {
                 let request_config = request_config.clone();
                 let coprocessor_url = coprocessor_url.clone();
+                let coprocessor_name = coprocessor_name.clone();
                 let http_client = http_client.clone();
                 let sdl = sdl.clone();
 
@@ -95,6 +140,7 @@ impl ExecutionStage {
                     let result = process_execution_request_stage(
                         http_client,
                         coprocessor_url,
+                        coprocessor_name.clone(),
                         sdl,
                         request,
                         request_config,
@@ -112,6 +158,7 @@ impl ExecutionStage {
                         "apollo.router.operations.coprocessor",
                         "Total operations with co-processors enabled",
                         1,
+                        "coprocessor.name" = coprocessor_name.clone(),
                         "coprocessor.stage" = PipelineStep::ExecutionRequest,
                         "coprocessor.succeeded" = succeeded
                     );
@@ -122,9 +169,17 @@ impl ExecutionStage {
 
         let response_layer = (self.response != Default::default()).then_some({
             let response_config = self.response.clone();
+            let http_client = StageClient::new(
+                http_client,
+                PipelineStep::ExecutionResponse,
+                response_config.on_error.clone(),
+                queues.response.as_ref(),
+                &coprocessor_name,
+            );
 
             MapFutureLayer::new(move |fut| {
                 let coprocessor_url = coprocessor_url.clone();
+                let coprocessor_name = coprocessor_name.clone();
                 let sdl: Arc<String> = sdl.clone();
                 let http_client = http_client.clone();
                 let response_config = response_config.clone();
@@ -136,6 +191,7 @@ impl ExecutionStage {
                     let result = process_execution_response_stage(
                         http_client,
                         coprocessor_url,
+                        coprocessor_name.clone(),
                         sdl,
                         response,
                         response_config,
@@ -153,6 +209,7 @@ impl ExecutionStage {
                         "apollo.router.operations.coprocessor",
                         "Total operations with co-processors enabled",
                         1,
+                        "coprocessor.name" = coprocessor_name.clone(),
                         "coprocessor.stage" = PipelineStep::ExecutionResponse,
                         "coprocessor.succeeded" = succeeded
                     );
@@ -161,18 +218,21 @@ impl ExecutionStage {
             })
         });
 
-        fn external_service_span() -> impl Fn(&execution::Request) -> tracing::Span + Clone {
+        fn external_service_span(
+            coprocessor_name: String,
+        ) -> impl Fn(&execution::Request) -> tracing::Span + Clone {
             move |_request: &execution::Request| {
                 tracing::info_span!(
                     EXTERNAL_SPAN_NAME,
                     "external service" = stringify!(execution::Request),
-                    "otel.kind" = "INTERNAL"
+                    "otel.kind" = "INTERNAL",
+                    "coprocessor.name" = coprocessor_name.as_str()
                 )
             }
         }
 
         ServiceBuilder::new()
-            .instrument(external_service_span())
+            .instrument(service_span)
             .option_layer(request_layer)
             .option_layer(response_layer)
             .service(service)
@@ -183,6 +243,7 @@ impl ExecutionStage {
 async fn process_execution_request_stage<C>(
     http_client: C,
     coprocessor_url: String,
+    coprocessor_name: String,
     sdl: Arc<String>,
     mut request: execution::Request,
     request_config: ExecutionRequestConf,
@@ -237,6 +298,7 @@ where
     drop(guard);
     tracing::info!(
         histogram.apollo.router.operations.coprocessor.duration = duration,
+        coprocessor.name = coprocessor_name.as_str(),
         coprocessor.stage = %PipelineStep::ExecutionRequest,
     );
 
@@ -279,13 +341,11 @@ where
                 context: request.context,
             };
 
-            if let Some(context) = co_processor_output.context {
-                for (key, value) in context.try_into_iter()? {
-                    execution_response
-                        .context
-                        .upsert_json_value(key, move |_current| value);
-                }
-            }
+            update_context(
+                &execution_response.context,
+                co_processor_output.context,
+                co_processor_output.context_patch,
+            )?;
 
             execution_response
         };
@@ -303,13 +363,11 @@ where
 
     request.supergraph_request = http::Request::from_parts(parts, new_body);
 
-    if let Some(context) = co_processor_output.context {
-        for (key, value) in context.try_into_iter()? {
-            request
-                .context
-                .upsert_json_value(key, move |_current| value);
-        }
-    }
+    update_context(
+        &request.context,
+        co_processor_output.context,
+        co_processor_output.context_patch,
+    )?;
 
     if let Some(headers) = co_processor_output.headers {
         *request.supergraph_request.headers_mut() = internalize_header_map(headers)?;
@@ -325,6 +383,7 @@ where
 async fn process_execution_response_stage<C>(
     http_client: C,
     coprocessor_url: String,
+    coprocessor_name: String,
     sdl: Arc<String>,
     response: execution::Response,
     response_config: ExecutionResponseConf,
@@ -372,6 +431,7 @@ where
         .and_status_code(status_to_send)
         .and_sdl(sdl_to_send.clone())
         .and_has_next(first.has_next)
+        .sequence(0)
         .build();
 
     // Second, call our co-processor and get a reply.
@@ -383,6 +443,7 @@ where
     drop(guard);
     tracing::info!(
         histogram.apollo.router.operations.coprocessor.duration = duration,
+        coprocessor.name = coprocessor_name.as_str(),
         coprocessor.stage = %PipelineStep::ExecutionResponse,
     );
 
@@ -404,13 +465,11 @@ where
         parts.status = control.get_http_status()?
     }
 
-    if let Some(context) = co_processor_output.context {
-        for (key, value) in context.try_into_iter()? {
-            response
-                .context
-                .upsert_json_value(key, move |_current| value);
-        }
-    }
+    update_context(
+        &response.context,
+        co_processor_output.context,
+        co_processor_output.context_patch,
+    )?;
 
     if let Some(headers) = co_processor_output.headers {
         parts.headers = internalize_header_map(headers)?;
@@ -422,7 +481,8 @@ where
 
     // Map the rest of our body to process subsequent chunks of response
     let mapped_stream = rest
-        .then(move |deferred_response| {
+        .enumerate()
+        .then(move |(index, deferred_response)| {
             let generator_client = http_client.clone();
             let generator_coprocessor_url = coprocessor_url.clone();
             let generator_map_context = map_context.clone();
@@ -430,6 +490,10 @@ where
             let generator_id = map_context.id.clone();
 
             async move {
+                if !response_config.stream {
+                    return Ok(deferred_response);
+                }
+
                 let body_to_send = response_config.body.then(|| {
                     serde_json::to_value(&deferred_response).expect("serialization will not fail")
                 });
@@ -447,6 +511,7 @@ where
                     .and_context(context_to_send)
                     .and_sdl(generator_sdl_to_send)
                     .and_has_next(deferred_response.has_next)
+                    .sequence(index as u64 + 1)
                     .build();
 
                 // Second, call our co-processor and get a reply.
@@ -471,11 +536,11 @@ where
                         None => deferred_response,
                     };
 
-                if let Some(context) = co_processor_output.context {
-                    for (key, value) in context.try_into_iter()? {
-                        generator_map_context.upsert_json_value(key, move |_current| value);
-                    }
-                }
+                update_context(
+                    &generator_map_context,
+                    co_processor_output.context,
+                    co_processor_output.context_patch,
+                )?;
 
                 // We return the deferred_response into our stream of response chunks
                 Ok(new_deferred_response)
@@ -579,6 +644,8 @@ mod tests {
                 sdl: false,
                 method: false,
                 query_plan: false,
+                is_async: false,
+                on_error: None,
             },
             response: Default::default(),
         };
@@ -682,6 +749,8 @@ mod tests {
             mock_http_client,
             mock_execution_service.boxed(),
             "http://test".to_string(),
+            "test".to_string(),
+            &Default::default(),
             Arc::new("".to_string()),
         );
 
@@ -713,6 +782,8 @@ mod tests {
                 sdl: false,
                 method: false,
                 query_plan: false,
+                is_async: false,
+                on_error: None,
             },
             response: Default::default(),
         };
@@ -751,6 +822,8 @@ mod tests {
             mock_http_client,
             mock_execution_service.boxed(),
             "http://test".to_string(),
+            "test".to_string(),
+            &Default::default(),
             Arc::new("".to_string()),
         );
 
@@ -784,6 +857,9 @@ mod tests {
                 body: true,
                 sdl: true,
                 status_code: false,
+                stream: true,
+                is_async: false,
+                on_error: None,
             },
             request: Default::default(),
         };
@@ -876,6 +952,8 @@ mod tests {
             mock_http_client,
             mock_execution_service.boxed(),
             "http://test".to_string(),
+            "test".to_string(),
+            &Default::default(),
             Arc::new("".to_string()),
         );
 
@@ -915,6 +993,9 @@ mod tests {
                 body: true,
                 sdl: true,
                 status_code: false,
+                stream: true,
+                is_async: false,
+                on_error: None,
             },
             request: Default::default(),
         };
@@ -987,6 +1068,8 @@ mod tests {
             mock_http_client,
             mock_execution_service.boxed(),
             "http://test".to_string(),
+            "test".to_string(),
+            &Default::default(),
             Arc::new("".to_string()),
         );
 
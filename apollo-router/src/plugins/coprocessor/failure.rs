@@ -0,0 +1,607 @@
+//! Failure handling for coprocessor calls
+//!
+//! A circuit breaker is shared by all the stages of a coprocessor, and each stage can replace failed calls with a
+//! response generated from its `on_error` policy.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::task::Poll;
+use std::time::Duration;
+use std::time::Instant;
+
+use bytes::Bytes;
+use futures::future::BoxFuture;
+use hyper::Body;
+use parking_lot::Mutex;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json::Value;
+use tower::BoxError;
+use tower::Service;
+
+use crate::graphql;
+use crate::services::external::Control;
+use crate::services::external::Externalizable;
+use crate::services::external::PipelineStep;
+
+const DEFAULT_FAILURE_THRESHOLD: u32 = 5;
+const DEFAULT_OPEN_DURATION: Duration = Duration::from_secs(30);
+const DEFAULT_FAIL_CLOSED_CODE: &str = "COPROCESSOR_ERROR";
+const DEFAULT_FAIL_CLOSED_MESSAGE: &str = "coprocessor unavailable";
+
+/// What to do when the coprocessor call fails, times out or returns an invalid response
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub(crate) enum OnError {
+    /// Reject the request, or replace the response with an error
+    FailClosed(FailClosedConf),
+    /// Continue as if the coprocessor returned the payload unmodified
+    FailOpen,
+    /// Continue as if the coprocessor returned this control, body and headers
+    Fallback(FallbackConf),
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct FailClosedConf {
+    /// The HTTP status code of the error (default: 503)
+    pub(super) status_code: u16,
+    /// The `code` extension of the GraphQL error (default: COPROCESSOR_ERROR)
+    pub(super) code: String,
+    /// The message of the GraphQL error
+    pub(super) message: String,
+}
+
+impl Default for FailClosedConf {
+    fn default() -> Self {
+        Self {
+            status_code: 503,
+            code: DEFAULT_FAIL_CLOSED_CODE.to_string(),
+            message: DEFAULT_FAIL_CLOSED_MESSAGE.to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct FallbackConf {
+    /// The control used for request stages (default: continue)
+    pub(super) control: Control,
+    /// Replaces the body
+    pub(super) body: Option<Value>,
+    /// Replaces the headers
+    pub(super) headers: Option<HashMap<String, Vec<String>>>,
+}
+
+impl OnError {
+    fn name(&self) -> &'static str {
+        match self {
+            OnError::FailClosed(_) => "fail_closed",
+            OnError::FailOpen => "fail_open",
+            OnError::Fallback(_) => "fallback",
+        }
+    }
+
+    /// Generates the coprocessor response from the payload that was sent
+    pub(super) fn response(&self, stage: PipelineStep, sent: &[u8]) -> Result<Bytes, BoxError> {
+        let mut payload: serde_json::Map<String, Value> = serde_json::from_slice(sent)?;
+        let is_request = matches!(
+            stage,
+            PipelineStep::RouterRequest
+                | PipelineStep::SupergraphRequest
+                | PipelineStep::QueryPlannerRequest
+                | PipelineStep::ExecutionRequest
+                | PipelineStep::SubgraphRequest
+        );
+        // the query plan is read only, and doesn't need to be deserialized again
+        payload.remove("queryPlan");
+
+        match self {
+            OnError::FailOpen => {
+                if is_request {
+                    payload.insert(
+                        "control".to_string(),
+                        serde_json::to_value(Control::Continue)?,
+                    );
+                }
+            }
+            OnError::FailClosed(conf) => {
+                let errors = graphql::Response::builder()
+                    .error(
+                        graphql::Error::builder()
+                            .message(conf.message.clone())
+                            .extension_code(conf.code.clone())
+                            .build(),
+                    )
+                    .build();
+                payload.insert("body".to_string(), serde_json::to_value(errors)?);
+                if is_request {
+                    payload.insert(
+                        "control".to_string(),
+                        serde_json::to_value(Control::Break(conf.status_code))?,
+                    );
+                } else {
+                    payload.insert("statusCode".to_string(), conf.status_code.into());
+                }
+            }
+            OnError::Fallback(conf) => {
+                if is_request {
+                    payload.insert("control".to_string(), serde_json::to_value(&conf.control)?);
+                }
+                if let Some(body) = &conf.body {
+                    payload.insert("body".to_string(), body.clone());
+                }
+                if let Some(headers) = &conf.headers {
+                    payload.insert("headers".to_string(), serde_json::to_value(headers)?);
+                }
+            }
+        }
+
+        // router stages exchange the body as a string
+        if matches!(
+            stage,
+            PipelineStep::RouterRequest | PipelineStep::RouterResponse
+        ) {
+            if let Some(body) = payload.get_mut("body") {
+                if !body.is_string() {
+                    *body = Value::String(body.to_string());
+                }
+            }
+        }
+
+        Ok(serde_json::to_vec(&payload)?.into())
+    }
+}
+
+/// Opens the circuit after consecutive coprocessor failures, so that calls fail immediately instead of waiting for
+/// the timeout
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub(super) struct CircuitBreakerConf {
+    /// Consecutive failures that open the circuit (default: 5)
+    pub(super) failure_threshold: u32,
+    /// How long the circuit stays open before a call is let through to check the coprocessor (default: 30s)
+    #[serde(deserialize_with = "humantime_serde::deserialize")]
+    #[schemars(with = "String", default = "default_open_duration")]
+    pub(super) open_duration: Duration,
+}
+
+fn default_open_duration() -> Duration {
+    DEFAULT_OPEN_DURATION
+}
+
+impl Default for CircuitBreakerConf {
+    fn default() -> Self {
+        Self {
+            failure_threshold: DEFAULT_FAILURE_THRESHOLD,
+            open_duration: DEFAULT_OPEN_DURATION,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum CircuitState {
+    Closed {
+        failures: u32,
+    },
+    Open {
+        until: Instant,
+    },
+    /// A single call checks whether the coprocessor recovered
+    HalfOpen {
+        since: Instant,
+    },
+}
+
+#[derive(Debug)]
+struct CircuitBreaker {
+    conf: CircuitBreakerConf,
+    state: Mutex<CircuitState>,
+}
+
+impl CircuitBreaker {
+    fn new(conf: CircuitBreakerConf) -> Self {
+        Self {
+            conf,
+            state: Mutex::new(CircuitState::Closed { failures: 0 }),
+        }
+    }
+
+    fn allow(&self) -> bool {
+        let mut state = self.state.lock();
+        let now = Instant::now();
+        match *state {
+            CircuitState::Closed { .. } => true,
+            CircuitState::Open { until } if now >= until => {
+                *state = CircuitState::HalfOpen { since: now };
+                true
+            }
+            // the check call may have been cancelled, let another one through
+            CircuitState::HalfOpen { since } if now >= since + self.conf.open_duration => {
+                *state = CircuitState::HalfOpen { since: now };
+                true
+            }
+            CircuitState::Open { .. } | CircuitState::HalfOpen { .. } => false,
+        }
+    }
+
+    fn record(&self, succeeded: bool) {
+        let mut state = self.state.lock();
+        match (*state, succeeded) {
+            (CircuitState::Closed { .. }, true) => *state = CircuitState::Closed { failures: 0 },
+            (CircuitState::HalfOpen { .. }, true) => {
+                tracing::info!("coprocessor circuit breaker closed");
+                *state = CircuitState::Closed { failures: 0 };
+            }
+            (CircuitState::Closed { failures }, false)
+                if failures + 1 < self.conf.failure_threshold =>
+            {
+                *state = CircuitState::Closed {
+                    failures: failures + 1,
+                };
+            }
+            (CircuitState::Closed { .. } | CircuitState::HalfOpen { .. }, false) => {
+                tracing::warn!(
+                    "coprocessor circuit breaker opened for {}",
+                    humantime::format_duration(self.conf.open_duration)
+                );
+                *state = CircuitState::Open {
+                    until: Instant::now() + self.conf.open_duration,
+                };
+            }
+            (CircuitState::Open { .. }, _) => {}
+        }
+    }
+}
+
+#[derive(Debug)]
+struct CircuitOpenError;
+
+impl fmt::Display for CircuitOpenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the coprocessor circuit breaker is open")
+    }
+}
+
+impl std::error::Error for CircuitOpenError {}
+
+/// Client shared by the stages of a coprocessor, guarded by its circuit breaker
+#[derive(Clone)]
+pub(super) struct CircuitBreakerService<C> {
+    inner: C,
+    breaker: Option<Arc<CircuitBreaker>>,
+}
+
+impl<C> CircuitBreakerService<C> {
+    pub(super) fn new(inner: C, conf: Option<CircuitBreakerConf>) -> Self {
+        Self {
+            inner,
+            breaker: conf.map(|conf| Arc::new(CircuitBreaker::new(conf))),
+        }
+    }
+}
+
+impl<C> Service<hyper::Request<Body>> for CircuitBreakerService<C>
+where
+    C: Service<hyper::Request<Body>, Response = hyper::Response<Body>, Error = BoxError>,
+    C::Future: Send + 'static,
+{
+    type Response = hyper::Response<Body>;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: hyper::Request<Body>) -> Self::Future {
+        let breaker = self.breaker.clone();
+        if let Some(breaker) = &breaker {
+            if !breaker.allow() {
+                return Box::pin(futures::future::ready(Err(CircuitOpenError.into())));
+            }
+        }
+
+        let response = self.inner.call(request);
+        Box::pin(async move {
+            let result = response.await;
+            if let Some(breaker) = breaker {
+                breaker.record(
+                    matches!(&result, Ok(response) if !response.status().is_server_error()),
+                );
+            }
+            result
+        })
+    }
+}
+
+/// Why a coprocessor call failed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum FailureKind {
+    Timeout,
+    CircuitOpen,
+    Transport,
+    HttpStatus,
+    InvalidResponse,
+}
+
+impl FailureKind {
+    fn of(error: &BoxError) -> Self {
+        if error.is::<tower::timeout::error::Elapsed>() {
+            FailureKind::Timeout
+        } else if error.is::<CircuitOpenError>() {
+            FailureKind::CircuitOpen
+        } else {
+            FailureKind::Transport
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            FailureKind::Timeout => "timeout",
+            FailureKind::CircuitOpen => "circuit_open",
+            FailureKind::Transport => "transport",
+            FailureKind::HttpStatus => "http_status",
+            FailureKind::InvalidResponse => "invalid_response",
+        }
+    }
+}
+
+/// Client of a single stage, applying its `on_error` policy
+#[derive(Clone)]
+pub(super) struct OnErrorService<C> {
+    inner: C,
+    stage: PipelineStep,
+    on_error: Option<OnError>,
+    coprocessor_name: String,
+}
+
+impl<C> OnErrorService<C> {
+    pub(super) fn new(
+        inner: C,
+        stage: PipelineStep,
+        on_error: Option<OnError>,
+        coprocessor_name: String,
+    ) -> Self {
+        Self {
+            inner,
+            stage,
+            on_error,
+            coprocessor_name,
+        }
+    }
+}
+
+impl<C> Service<hyper::Request<Body>> for OnErrorService<C>
+where
+    C: Service<hyper::Request<Body>, Response = hyper::Response<Body>, Error = BoxError>
+        + Clone
+        + Send
+        + 'static,
+    C::Future: Send + 'static,
+{
+    type Response = hyper::Response<Body>;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: hyper::Request<Body>) -> Self::Future {
+        let stage = self.stage;
+        let coprocessor_name = self.coprocessor_name.clone();
+        let on_error = match self.on_error.clone() {
+            Some(on_error) => on_error,
+            None => {
+                let response = self.inner.call(request);
+                return Box::pin(async move {
+                    response.await.map_err(|error| {
+                        record_failure(
+                            &coprocessor_name,
+                            stage,
+                            FailureKind::of(&error),
+                            None,
+                            &error,
+                        );
+                        error
+                    })
+                });
+            }
+        };
+
+        // the request body is read before calling the coprocessor: take the service that was polled ready
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        Box::pin(async move {
+            let (parts, body) = request.into_parts();
+            let sent = hyper::body::to_bytes(body).await?;
+
+            let result = match inner
+                .call(hyper::Request::from_parts(parts, Body::from(sent.clone())))
+                .await
+            {
+                Ok(response) if !response.status().is_success() => Err((
+                    FailureKind::HttpStatus,
+                    format!("coprocessor returned status {}", response.status()).into(),
+                )),
+                Ok(response) => match hyper::body::to_bytes(response.into_body()).await {
+                    Ok(received) => {
+                        match serde_json::from_slice::<Externalizable<Value>>(&received) {
+                            Ok(_) => Ok(received),
+                            Err(error) => Err((FailureKind::InvalidResponse, error.into())),
+                        }
+                    }
+                    Err(error) => Err((FailureKind::Transport, error.into())),
+                },
+                Err(error) => Err((FailureKind::of(&error), error)),
+            };
+
+            let received = match result {
+                Ok(received) => received,
+                Err((kind, error)) => {
+                    record_failure(&coprocessor_name, stage, kind, Some(&on_error), &error);
+                    on_error.response(stage, &sent)?
+                }
+            };
+            Ok(hyper::Response::new(Body::from(received)))
+        })
+    }
+}
+
+fn record_failure(
+    coprocessor_name: &str,
+    stage: PipelineStep,
+    kind: FailureKind,
+    on_error: Option<&OnError>,
+    error: &BoxError,
+) {
+    let policy = on_error.map(OnError::name).unwrap_or("none");
+    tracing::warn!(
+        coprocessor.name = coprocessor_name,
+        coprocessor.stage = %stage,
+        coprocessor.error = kind.as_str(),
+        coprocessor.on_error = policy,
+        "coprocessor call failed: {error}"
+    );
+    u64_counter!(
+        "apollo.router.operations.coprocessor.errors",
+        "Coprocessor calls that failed, timed out or returned an invalid response",
+        1,
+        "coprocessor.name" = coprocessor_name.to_string(),
+        "coprocessor.stage" = stage,
+        "coprocessor.error" = kind.as_str(),
+        "coprocessor.on_error" = policy
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::future::BoxFuture;
+    use serde_json::json;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::metrics::FutureMetricsExt;
+    use crate::plugin::test::MockHttpClientService;
+
+    fn failing_client() -> MockHttpClientService {
+        let mut mock = MockHttpClientService::new();
+        mock.expect_clone().returning(failing_client);
+        mock.expect_call().returning(|_| {
+            Box::pin(async { Err(tower::timeout::error::Elapsed::new().into()) })
+                as BoxFuture<'static, _>
+        });
+        mock
+    }
+
+    fn payload(stage: PipelineStep) -> hyper::Request<Body> {
+        hyper::Request::new(Body::from(
+            json!({
+                "version": 1,
+                "stage": stage.to_string(),
+                "id": "1",
+                "body": "{\"query\":\"{ me { name } }\"}",
+            })
+            .to_string(),
+        ))
+    }
+
+    async fn received(response: hyper::Response<Body>) -> Value {
+        serde_json::from_slice(&hyper::body::to_bytes(response.into_body()).await.unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn fail_open_continues_unmodified() {
+        async {
+            let response = OnErrorService::new(
+                failing_client(),
+                PipelineStep::RouterRequest,
+                Some(OnError::FailOpen),
+                "auth".to_string(),
+            )
+            .oneshot(payload(PipelineStep::RouterRequest))
+            .await
+            .unwrap();
+
+            let received = received(response).await;
+            assert_eq!(received["control"], json!("continue"));
+            assert_eq!(received["body"], json!("{\"query\":\"{ me { name } }\"}"));
+            assert_counter!(
+                "apollo.router.operations.coprocessor.errors",
+                1,
+                "coprocessor.name" = "auth",
+                "coprocessor.stage" = "RouterRequest",
+                "coprocessor.error" = "timeout",
+                "coprocessor.on_error" = "fail_open"
+            );
+        }
+        .with_metrics()
+        .await;
+    }
+
+    #[tokio::test]
+    async fn fail_closed_breaks_with_an_error() {
+        let response = OnErrorService::new(
+            failing_client(),
+            PipelineStep::RouterRequest,
+            Some(OnError::FailClosed(FailClosedConf {
+                status_code: 401,
+                code: "UNAUTHENTICATED".to_string(),
+                ..Default::default()
+            })),
+            "auth".to_string(),
+        )
+        .oneshot(payload(PipelineStep::RouterRequest))
+        .await
+        .unwrap();
+
+        let received = received(response).await;
+        assert_eq!(received["control"], json!({ "break": 401 }));
+        let body: Value = serde_json::from_str(received["body"].as_str().unwrap()).unwrap();
+        assert_eq!(body["errors"][0]["extensions"]["code"], "UNAUTHENTICATED");
+    }
+
+    #[tokio::test]
+    async fn errors_are_returned_without_policy() {
+        assert!(OnErrorService::new(
+            failing_client(),
+            PipelineStep::SubgraphResponse,
+            None,
+            "auth".to_string(),
+        )
+        .oneshot(payload(PipelineStep::SubgraphResponse))
+        .await
+        .is_err());
+    }
+
+    #[test]
+    fn circuit_breaker_opens_after_consecutive_failures() {
+        let breaker = CircuitBreaker::new(CircuitBreakerConf {
+            failure_threshold: 2,
+            open_duration: Duration::from_secs(60),
+        });
+        breaker.record(false);
+        breaker.record(true);
+        breaker.record(false);
+        assert!(breaker.allow());
+        breaker.record(false);
+        assert!(!breaker.allow());
+    }
+
+    #[test]
+    fn circuit_breaker_closes_after_a_successful_check() {
+        let breaker = CircuitBreaker::new(CircuitBreakerConf {
+            failure_threshold: 1,
+            open_duration: Duration::from_millis(0),
+        });
+        breaker.record(false);
+        // the open duration elapsed: a single call checks the coprocessor
+        assert!(breaker.allow());
+        breaker.record(true);
+        assert!(breaker.allow());
+        assert!(breaker.allow());
+    }
+}
@@ -1,6 +1,7 @@
 //! Externalization plugin
 
 use std::collections::HashMap;
+use std::collections::HashSet;
 use std::ops::ControlFlow;
 use std::str::FromStr;
 use std::sync::Arc;
@@ -8,6 +9,7 @@ use std::time::Duration;
 use std::time::Instant;
 
 use bytes::Bytes;
+use derivative::Derivative;
 use futures::future::ready;
 use futures::stream::once;
 use futures::StreamExt;
@@ -16,28 +18,49 @@ use http::header;
 use http::HeaderMap;
 use http::HeaderName;
 use http::HeaderValue;
-use hyper::client::HttpConnector;
 use hyper::Body;
-use hyper_rustls::ConfigBuilderExt;
-use hyper_rustls::HttpsConnector;
 use schemars::JsonSchema;
+use serde::de::value::MapAccessDeserializer;
+use serde::de::value::SeqAccessDeserializer;
+use serde::de::MapAccess;
+use serde::de::SeqAccess;
+use serde::de::Visitor;
 use serde::Deserialize;
+use serde::Deserializer;
 use serde::Serialize;
-use tower::timeout::TimeoutLayer;
 use tower::util::MapFutureLayer;
 use tower::BoxError;
 use tower::Service;
 use tower::ServiceBuilder;
 use tower::ServiceExt;
 
+use crate::configuration::subgraph::SubgraphConfiguration;
+use crate::configuration::TlsClient;
 use crate::error::Error;
 use crate::layers::async_checkpoint::OneShotAsyncCheckpointLayer;
 use crate::layers::ServiceBuilderExt;
-use crate::plugin::Plugin;
 use crate::plugin::PluginInit;
-use crate::register_plugin;
+use crate::plugin::PluginPrivate;
+use crate::plugins::coprocessor::asynchronous::AsyncQueue;
+use crate::plugins::coprocessor::asynchronous::AsyncQueueConf;
+use crate::plugins::coprocessor::asynchronous::StageClient;
+use crate::plugins::coprocessor::asynchronous::StageQueues;
+use crate::plugins::coprocessor::failure::CircuitBreakerConf;
+use crate::plugins::coprocessor::failure::CircuitBreakerService;
+use crate::plugins::coprocessor::failure::OnError;
+use crate::plugins::coprocessor::protocol::ProtocolService;
+use crate::plugins::coprocessor::protocol::ProtocolVersion;
+use crate::plugins::coprocessor::signing::SigningConf;
+use crate::plugins::coprocessor::signing::SigningService;
+use crate::plugins::coprocessor::transport::Transport;
+use crate::plugins::coprocessor::transport::TransportKind;
+use crate::plugins::telemetry::config_new::conditions::Condition;
+use crate::plugins::telemetry::config_new::selectors::RouterSelector;
+use crate::plugins::telemetry::config_new::selectors::SubgraphSelector;
+use crate::register_private_plugin;
 use crate::services;
 use crate::services::external::externalize_header_map;
+use crate::services::external::ContextOperation;
 use crate::services::external::Control;
 use crate::services::external::Externalizable;
 use crate::services::external::PipelineStep;
@@ -45,76 +68,159 @@ use crate::services::external::DEFAULT_EXTERNALIZATION_TIMEOUT;
 use crate::services::external::EXTERNALIZABLE_VERSION;
 use crate::services::router;
 use crate::services::subgraph;
-use crate::services::trust_dns_connector::new_async_http_connector;
-use crate::services::trust_dns_connector::AsyncHyperResolver;
+use crate::Context;
 
 #[cfg(test)]
 mod test;
 
+mod asynchronous;
 mod execution;
+mod failure;
+mod protocol;
+mod query_planner;
+mod signing;
 mod supergraph;
+mod transport;
+
+pub(crate) use transport::StagePayload;
 
 pub(crate) const EXTERNAL_SPAN_NAME: &str = "external_plugin";
-const POOL_IDLE_TIMEOUT_DURATION: Option<Duration> = Some(Duration::from_secs(5));
 const COPROCESSOR_ERROR_EXTENSION: &str = "ERROR";
 const COPROCESSOR_DESERIALIZATION_ERROR_EXTENSION: &str = "EXTERNAL_DESERIALIZATION_ERROR";
 
-type HTTPClientService =
-    tower::timeout::Timeout<hyper::Client<HttpsConnector<HttpConnector<AsyncHyperResolver>>, Body>>;
+/// The client of a coprocessor, from the outermost layer
+type CoprocessorClient = ProtocolService<SigningService<CircuitBreakerService<Transport>>>;
+
+/// The configured coprocessors, in the order they are called
+struct Coprocessors {
+    chain: Vec<CoprocessorPlugin<CoprocessorClient>>,
+}
 
 #[async_trait::async_trait]
-impl Plugin for CoprocessorPlugin<HTTPClientService> {
-    type Config = Conf;
+impl PluginPrivate for Coprocessors {
+    type Config = CoprocessorsConf;
 
     async fn new(init: PluginInit<Self::Config>) -> Result<Self, BoxError> {
-        let mut http_connector = new_async_http_connector()?;
-        http_connector.set_nodelay(true);
-        http_connector.set_keepalive(Some(std::time::Duration::from_secs(60)));
-        http_connector.enforce_http(false);
-
-        let tls_config = rustls::ClientConfig::builder()
-            .with_safe_defaults()
-            .with_native_roots()
-            .with_no_client_auth();
-
-        let connector = hyper_rustls::HttpsConnectorBuilder::new()
-            .with_tls_config(tls_config)
-            .https_or_http()
-            .enable_http1()
-            .enable_http2()
-            .wrap_connector(http_connector);
-
-        let http_client = ServiceBuilder::new()
-            .layer(TimeoutLayer::new(init.config.timeout))
-            .service(
-                hyper::Client::builder()
-                    .pool_idle_timeout(POOL_IDLE_TIMEOUT_DURATION)
-                    .build(connector),
-            );
+        let configurations = match init.config {
+            CoprocessorsConf::Single(configuration) => vec![*configuration],
+            CoprocessorsConf::Chain(configurations) => {
+                let mut names = HashSet::new();
+                for configuration in &configurations {
+                    match &configuration.name {
+                        Some(name) if !names.insert(name.as_str()) => {
+                            return Err(format!("duplicate coprocessor name '{name}'").into());
+                        }
+                        Some(_) => {}
+                        None => {
+                            return Err(format!(
+                                "the coprocessor calling {} must have a name",
+                                configuration.url
+                            )
+                            .into());
+                        }
+                    }
+                }
+                configurations
+            }
+        };
 
-        CoprocessorPlugin::new(http_client, init.config, init.supergraph_sdl)
+        let chain = configurations
+            .into_iter()
+            .map(|mut configuration| {
+                if configuration.signing.is_some() && configuration.transport == TransportKind::Grpc
+                {
+                    return Err(format!(
+                        "the coprocessor calling {} cannot sign payloads sent over gRPC",
+                        configuration.url
+                    )
+                    .into());
+                }
+                let transport = Transport::new(
+                    &configuration.url,
+                    configuration.transport,
+                    configuration.timeout,
+                    configuration.tls.as_ref(),
+                )
+                .map_err(|e| match &configuration.name {
+                    Some(name) => format!("coprocessor '{name}': {e}").into(),
+                    None => e,
+                })?;
+                configuration.url = transport.request_url(&configuration.url);
+
+                CoprocessorPlugin::new(
+                    ProtocolService::new(
+                        SigningService::new(
+                            CircuitBreakerService::new(
+                                transport,
+                                configuration.circuit_breaker.clone(),
+                            ),
+                            configuration.signing.clone(),
+                        ),
+                        configuration.protocol,
+                    ),
+                    configuration,
+                    init.supergraph_sdl.clone(),
+                )
+            })
+            .collect::<Result<_, BoxError>>()?;
+
+        Ok(Coprocessors { chain })
     }
 
+    // The first coprocessor of the chain is the outermost layer: it sees requests first and responses last
+
     fn router_service(&self, service: router::BoxService) -> router::BoxService {
-        self.router_service(service)
+        self.chain
+            .iter()
+            .rev()
+            .fold(service, |service, coprocessor| {
+                coprocessor.router_service(service)
+            })
     }
 
     fn supergraph_service(
         &self,
         service: services::supergraph::BoxService,
     ) -> services::supergraph::BoxService {
-        self.supergraph_service(service)
+        self.chain
+            .iter()
+            .rev()
+            .fold(service, |service, coprocessor| {
+                coprocessor.supergraph_service(service)
+            })
+    }
+
+    fn query_planner_service(
+        &self,
+        service: services::query_planner::BoxService,
+    ) -> services::query_planner::BoxService {
+        self.chain
+            .iter()
+            .rev()
+            .fold(service, |service, coprocessor| {
+                coprocessor.query_planner_service(service)
+            })
     }
 
     fn execution_service(
         &self,
         service: services::execution::BoxService,
     ) -> services::execution::BoxService {
-        self.execution_service(service)
+        self.chain
+            .iter()
+            .rev()
+            .fold(service, |service, coprocessor| {
+                coprocessor.execution_service(service)
+            })
     }
 
     fn subgraph_service(&self, name: &str, service: subgraph::BoxService) -> subgraph::BoxService {
-        self.subgraph_service(name, service)
+        self.chain
+            .iter()
+            .rev()
+            .fold(service, |service, coprocessor| {
+                coprocessor.subgraph_service(name, service)
+            })
     }
 }
 
@@ -123,11 +229,7 @@ impl Plugin for CoprocessorPlugin<HTTPClientService> {
 //
 // In order to keep the plugin names consistent,
 // we use using the `Reverse domain name notation`
-register_plugin!(
-    "apollo",
-    "coprocessor",
-    CoprocessorPlugin<HTTPClientService>
-);
+register_private_plugin!("apollo", "coprocessor", Coprocessors);
 
 // -------------------------------------------------------------------------------------------------------
 
@@ -143,11 +245,119 @@ where
         + Send
         + Sync
         + 'static,
-    <C as tower::Service<http::Request<Body>>>::Future: Send + Sync + 'static,
+    <C as tower::Service<http::Request<Body>>>::Future: Send + 'static,
 {
     http_client: C,
     configuration: Conf,
     sdl: Arc<String>,
+    queues: AsyncQueues,
+}
+
+/// Queues of the asynchronous stages. They are created with the plugin, so that the services created for
+/// each request or subgraph fetch share them
+#[derive(Default)]
+struct AsyncQueues {
+    router: StageQueues,
+    supergraph: StageQueues,
+    query_planner: StageQueues,
+    execution: StageQueues,
+    subgraph_all: StageQueues,
+    subgraphs: HashMap<String, StageQueues>,
+}
+
+impl std::fmt::Debug for AsyncQueues {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AsyncQueues").finish_non_exhaustive()
+    }
+}
+
+impl AsyncQueues {
+    fn new<C>(http_client: &C, configuration: &Conf) -> Self
+    where
+        C: Service<hyper::Request<Body>, Response = hyper::Response<Body>, Error = BoxError>
+            + Clone
+            + Send
+            + 'static,
+        C::Future: Send + 'static,
+    {
+        let queues = |request: (PipelineStep, bool), response: (PipelineStep, bool)| {
+            let queue = |(stage, asynchronous): (PipelineStep, bool)| {
+                asynchronous.then(|| {
+                    AsyncQueue::new(
+                        http_client.clone(),
+                        stage,
+                        configuration.url.clone(),
+                        configuration.name.clone().unwrap_or_default(),
+                        &configuration.async_queue,
+                    )
+                })
+            };
+            StageQueues {
+                request: queue(request),
+                response: queue(response),
+            }
+        };
+        let subgraph = |stage: &SubgraphStage| {
+            queues(
+                (PipelineStep::SubgraphRequest, stage.request.is_async),
+                (PipelineStep::SubgraphResponse, stage.response.is_async),
+            )
+        };
+
+        Self {
+            router: queues(
+                (
+                    PipelineStep::RouterRequest,
+                    configuration.router.request.is_async,
+                ),
+                (
+                    PipelineStep::RouterResponse,
+                    configuration.router.response.is_async,
+                ),
+            ),
+            supergraph: queues(
+                (
+                    PipelineStep::SupergraphRequest,
+                    configuration.supergraph.request.is_async,
+                ),
+                (
+                    PipelineStep::SupergraphResponse,
+                    configuration.supergraph.response.is_async,
+                ),
+            ),
+            query_planner: queues(
+                (
+                    PipelineStep::QueryPlannerRequest,
+                    configuration.query_planner.request.is_async,
+                ),
+                (
+                    PipelineStep::QueryPlannerResponse,
+                    configuration.query_planner.response.is_async,
+                ),
+            ),
+            execution: queues(
+                (
+                    PipelineStep::ExecutionRequest,
+                    configuration.execution.request.is_async,
+                ),
+                (
+                    PipelineStep::ExecutionResponse,
+                    configuration.execution.response.is_async,
+                ),
+            ),
+            subgraph_all: subgraph(&configuration.subgraph.all),
+            subgraphs: configuration
+                .subgraph
+                .subgraphs
+                .iter()
+                .map(|(name, stage)| (name.clone(), subgraph(stage)))
+                .collect(),
+        }
+    }
+
+    fn subgraph(&self, name: &str) -> &StageQueues {
+        self.subgraphs.get(name).unwrap_or(&self.subgraph_all)
+    }
 }
 
 impl<C> CoprocessorPlugin<C>
@@ -157,13 +367,15 @@ where
         + Send
         + Sync
         + 'static,
-    <C as tower::Service<http::Request<Body>>>::Future: Send + Sync + 'static,
+    <C as tower::Service<http::Request<Body>>>::Future: Send + 'static,
 {
     fn new(http_client: C, configuration: Conf, sdl: Arc<String>) -> Result<Self, BoxError> {
+        let queues = AsyncQueues::new(&http_client, &configuration);
         Ok(Self {
             http_client,
             configuration,
             sdl,
+            queues,
         })
     }
 
@@ -172,6 +384,8 @@ where
             self.http_client.clone(),
             service,
             self.configuration.url.clone(),
+            self.configuration.name.clone().unwrap_or_default(),
+            &self.queues.router,
             self.sdl.clone(),
         )
     }
@@ -184,6 +398,22 @@ where
             self.http_client.clone(),
             service,
             self.configuration.url.clone(),
+            self.configuration.name.clone().unwrap_or_default(),
+            &self.queues.supergraph,
+            self.sdl.clone(),
+        )
+    }
+
+    fn query_planner_service(
+        &self,
+        service: services::query_planner::BoxService,
+    ) -> services::query_planner::BoxService {
+        self.configuration.query_planner.as_service(
+            self.http_client.clone(),
+            service,
+            self.configuration.url.clone(),
+            self.configuration.name.clone().unwrap_or_default(),
+            &self.queues.query_planner,
             self.sdl.clone(),
         )
     }
@@ -196,21 +426,26 @@ where
             self.http_client.clone(),
             service,
             self.configuration.url.clone(),
+            self.configuration.name.clone().unwrap_or_default(),
+            &self.queues.execution,
             self.sdl.clone(),
         )
     }
 
     fn subgraph_service(&self, name: &str, service: subgraph::BoxService) -> subgraph::BoxService {
-        self.configuration.subgraph.all.as_service(
+        self.configuration.subgraph.get(name).as_service(
             self.http_client.clone(),
             service,
             self.configuration.url.clone(),
+            self.configuration.name.clone().unwrap_or_default(),
+            self.queues.subgraph(name),
             name.to_string(),
         )
     }
 }
 /// What information is passed to a router request/response stage
-#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize, JsonSchema)]
+#[derive(Clone, Debug, Default, Deserialize, Derivative, Serialize, JsonSchema)]
+#[derivative(PartialEq)]
 #[serde(default, deny_unknown_fields)]
 pub(super) struct RouterRequestConf {
     /// Send the headers
@@ -225,10 +460,22 @@ pub(super) struct RouterRequestConf {
     pub(super) path: bool,
     /// Send the method
     pub(super) method: bool,
+    /// Only send the request to the coprocessor when this condition is true (default: always)
+    #[serde(skip_serializing)]
+    #[derivative(PartialEq = "ignore")]
+    pub(super) condition: Option<Condition<RouterSelector>>,
+    /// Queue the payload and continue without waiting for the coprocessor, whose control is ignored
+    #[serde(rename = "async")]
+    pub(super) is_async: bool,
+    /// What to do when the coprocessor call fails (default: fail the request)
+    #[serde(skip_serializing)]
+    #[derivative(PartialEq = "ignore")]
+    pub(super) on_error: Option<OnError>,
 }
 
 /// What information is passed to a router request/response stage
-#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize, JsonSchema)]
+#[derive(Clone, Debug, Default, Deserialize, Derivative, Serialize, JsonSchema)]
+#[derivative(PartialEq)]
 #[serde(default, deny_unknown_fields)]
 pub(super) struct RouterResponseConf {
     /// Send the headers
@@ -241,9 +488,21 @@ pub(super) struct RouterResponseConf {
     pub(super) sdl: bool,
     /// Send the HTTP status
     pub(super) status_code: bool,
+    /// Only send the response to the coprocessor when this condition is true (default: always)
+    #[serde(skip_serializing)]
+    #[derivative(PartialEq = "ignore")]
+    pub(super) condition: Option<Condition<RouterSelector>>,
+    /// Queue the payload and continue without waiting for the coprocessor, whose control is ignored
+    #[serde(rename = "async")]
+    pub(super) is_async: bool,
+    /// What to do when the coprocessor call fails (default: fail the request)
+    #[serde(skip_serializing)]
+    #[derivative(PartialEq = "ignore")]
+    pub(super) on_error: Option<OnError>,
 }
 /// What information is passed to a subgraph request/response stage
-#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize, JsonSchema)]
+#[derive(Clone, Debug, Default, Deserialize, Derivative, Serialize, JsonSchema)]
+#[derivative(PartialEq)]
 #[serde(default, deny_unknown_fields)]
 pub(super) struct SubgraphRequestConf {
     /// Send the headers
@@ -258,10 +517,22 @@ pub(super) struct SubgraphRequestConf {
     pub(super) method: bool,
     /// Send the service name
     pub(super) service_name: bool,
+    /// Only send the request to the coprocessor when this condition is true (default: always)
+    #[serde(skip_serializing)]
+    #[derivative(PartialEq = "ignore")]
+    pub(super) condition: Option<Condition<SubgraphSelector>>,
+    /// Queue the payload and continue without waiting for the coprocessor, whose control is ignored
+    #[serde(rename = "async")]
+    pub(super) is_async: bool,
+    /// What to do when the coprocessor call fails (default: fail the request)
+    #[serde(skip_serializing)]
+    #[derivative(PartialEq = "ignore")]
+    pub(super) on_error: Option<OnError>,
 }
 
 /// What information is passed to a subgraph request/response stage
-#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize, JsonSchema)]
+#[derive(Clone, Debug, Default, Deserialize, Derivative, Serialize, JsonSchema)]
+#[derivative(PartialEq)]
 #[serde(default, deny_unknown_fields)]
 pub(super) struct SubgraphResponseConf {
     /// Send the headers
@@ -274,14 +545,93 @@ pub(super) struct SubgraphResponseConf {
     pub(super) service_name: bool,
     /// Send the http status
     pub(super) status_code: bool,
+    /// Only send the response to the coprocessor when this condition is true (default: always)
+    #[serde(skip_serializing)]
+    #[derivative(PartialEq = "ignore")]
+    pub(super) condition: Option<Condition<SubgraphSelector>>,
+    /// Queue the payload and continue without waiting for the coprocessor, whose control is ignored
+    #[serde(rename = "async")]
+    pub(super) is_async: bool,
+    /// What to do when the coprocessor call fails (default: fail the request)
+    #[serde(skip_serializing)]
+    #[derivative(PartialEq = "ignore")]
+    pub(super) on_error: Option<OnError>,
 }
 
 /// Configures the externalization plugin
+#[derive(Clone, Debug, JsonSchema)]
+#[schemars(untagged)]
+enum CoprocessorsConf {
+    /// A single coprocessor
+    Single(Box<Conf>),
+    /// Named coprocessors, called in order
+    Chain(Vec<Conf>),
+}
+
+// Not `#[serde(untagged)]`, which would replace the error of the configuration by
+// "data did not match any variant"
+impl<'de> Deserialize<'de> for CoprocessorsConf {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct MapOrSeq;
+
+        impl<'de> Visitor<'de> for MapOrSeq {
+            type Value = CoprocessorsConf;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("a coprocessor or a list of named coprocessors")
+            }
+
+            fn visit_map<A>(self, map: A) -> Result<Self::Value, A::Error>
+            where
+                A: MapAccess<'de>,
+            {
+                Conf::deserialize(MapAccessDeserializer::new(map))
+                    .map(|configuration| CoprocessorsConf::Single(Box::new(configuration)))
+            }
+
+            fn visit_seq<A>(self, seq: A) -> Result<Self::Value, A::Error>
+            where
+                A: SeqAccess<'de>,
+            {
+                Vec::<Conf>::deserialize(SeqAccessDeserializer::new(seq))
+                    .map(CoprocessorsConf::Chain)
+            }
+        }
+
+        deserializer.deserialize_any(MapOrSeq)
+    }
+}
+
+/// Configures a coprocessor
 #[derive(Clone, Debug, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
 struct Conf {
-    /// The url you'd like to offload processing to
+    /// The name of the coprocessor, required when several coprocessors are configured
+    #[serde(default)]
+    name: Option<String>,
+    /// The url you'd like to offload processing to, `unix:///path/to/socket` for a Unix domain socket
     url: String,
+    /// The transport used to call the coprocessor (default: http)
+    #[serde(default)]
+    transport: TransportKind,
+    /// The version of the payloads exchanged with the coprocessor (default: v1)
+    #[serde(default)]
+    protocol: ProtocolVersion,
+    /// TLS client configuration for https URLs, including the client certificate for mutual TLS
+    #[serde(default)]
+    tls: Option<TlsClient>,
+    /// Signs the payloads with an HMAC (default: disabled)
+    #[serde(default)]
+    signing: Option<SigningConf>,
+    /// Fails the calls immediately after consecutive failures (default: disabled)
+    #[serde(default)]
+    circuit_breaker: Option<CircuitBreakerConf>,
+    /// The queues and batches of the asynchronous stages
+    #[serde(default)]
+    async_queue: AsyncQueueConf,
     /// The timeout for external requests
     #[serde(deserialize_with = "humantime_serde::deserialize")]
     #[schemars(with = "String", default = "default_timeout")]
@@ -293,12 +643,15 @@ struct Conf {
     /// The supergraph stage request/response configuration
     #[serde(default)]
     supergraph: supergraph::SupergraphStage,
+    /// The query planner stage request/response configuration
+    #[serde(default)]
+    query_planner: query_planner::QueryPlannerStage,
     /// The execution stage request/response configuration
     #[serde(default)]
     execution: execution::ExecutionStage,
-    /// The subgraph stage request/response configuration
+    /// The subgraph stage request/response configuration, for all subgraphs and overridden per subgraph
     #[serde(default)]
-    subgraph: SubgraphStages,
+    subgraph: SubgraphConfiguration<SubgraphStage>,
 }
 
 fn default_timeout() -> Duration {
@@ -320,6 +673,8 @@ impl RouterStage {
         http_client: C,
         service: router::BoxService,
         coprocessor_url: String,
+        coprocessor_name: String,
+        queues: &StageQueues,
         sdl: Arc<String>,
     ) -> router::BoxService
     where
@@ -330,23 +685,41 @@ impl RouterStage {
             + 'static,
         <C as tower::Service<http::Request<Body>>>::Future: Send + 'static,
     {
+        let service_span = external_service_span(coprocessor_name.clone());
         let request_layer = (self.request != Default::default()).then_some({
             let request_config = self.request.clone();
             let coprocessor_url = coprocessor_url.clone();
-            let http_client = http_client.clone();
+            let coprocessor_name = coprocessor_name.clone();
+            let http_client = StageClient::new(
+                http_client.clone(),
+                PipelineStep::RouterRequest,
+                request_config.on_error.clone(),
+                queues.request.as_ref(),
+                &coprocessor_name,
+            );
             let sdl = sdl.clone();
 
             OneShotAsyncCheckpointLayer::new(move |request: router::Request| {
                 let request_config = request_config.clone();
                 let coprocessor_url = coprocessor_url.clone();
+                let coprocessor_name = coprocessor_name.clone();
                 let http_client = http_client.clone();
                 let sdl = sdl.clone();
 
                 async move {
+                    if !request_config
+                        .condition
+                        .as_ref()
+                        .map_or(true, |condition| condition.evaluate_request(&request))
+                    {
+                        return Ok(ControlFlow::Continue(request));
+                    }
+
                     let mut succeeded = true;
                     let result = process_router_request_stage(
                         http_client,
                         coprocessor_url,
+                        coprocessor_name.clone(),
                         sdl,
                         request,
                         request_config,
@@ -361,6 +734,7 @@ impl RouterStage {
                     });
                     tracing::info!(
                         monotonic_counter.apollo.router.operations.coprocessor = 1u64,
+                        coprocessor.name = coprocessor_name.as_str(),
                         coprocessor.stage = %PipelineStep::RouterRequest,
                         coprocessor.succeeded = succeeded,
                         "Total operations with co-processors enabled"
@@ -372,19 +746,35 @@ impl RouterStage {
 
         let response_layer = (self.response != Default::default()).then_some({
             let response_config = self.response.clone();
+            let http_client = StageClient::new(
+                http_client,
+                PipelineStep::RouterResponse,
+                response_config.on_error.clone(),
+                queues.response.as_ref(),
+                &coprocessor_name,
+            );
             MapFutureLayer::new(move |fut| {
                 let sdl = sdl.clone();
                 let coprocessor_url = coprocessor_url.clone();
+                let coprocessor_name = coprocessor_name.clone();
                 let http_client = http_client.clone();
                 let response_config = response_config.clone();
 
                 async move {
                     let response: router::Response = fut.await?;
+                    if !response_config
+                        .condition
+                        .as_ref()
+                        .map_or(true, |condition| condition.evaluate_response(&response))
+                    {
+                        return Ok(response);
+                    }
 
                     let mut succeeded = true;
                     let result = process_router_response_stage(
                         http_client,
                         coprocessor_url,
+                        coprocessor_name.clone(),
                         sdl,
                         response,
                         response_config,
@@ -399,6 +789,7 @@ impl RouterStage {
                     });
                     tracing::info!(
                         monotonic_counter.apollo.router.operations.coprocessor = 1u64,
+                        coprocessor.name = coprocessor_name.as_str(),
                         coprocessor.stage = %PipelineStep::RouterResponse,
                         coprocessor.succeeded = succeeded,
                         "Total operations with co-processors enabled"
@@ -408,18 +799,21 @@ impl RouterStage {
             })
         });
 
-        fn external_service_span() -> impl Fn(&router::Request) -> tracing::Span + Clone {
+        fn external_service_span(
+            coprocessor_name: String,
+        ) -> impl Fn(&router::Request) -> tracing::Span + Clone {
             move |_request: &router::Request| {
                 tracing::info_span!(
                     EXTERNAL_SPAN_NAME,
                     "external service" = stringify!(router::Request),
-                    "otel.kind" = "INTERNAL"
+                    "otel.kind" = "INTERNAL",
+                    "coprocessor.name" = coprocessor_name.as_str()
                 )
             }
         }
 
         ServiceBuilder::new()
-            .instrument(external_service_span())
+            .instrument(service_span)
             .option_layer(request_layer)
             .option_layer(response_layer)
             .service(service)
@@ -429,14 +823,6 @@ impl RouterStage {
 
 // -----------------------------------------------------------------------------------------
 
-/// What information is passed to a subgraph request/response stage
-#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize, JsonSchema)]
-#[serde(default, deny_unknown_fields)]
-pub(super) struct SubgraphStages {
-    #[serde(default)]
-    pub(super) all: SubgraphStage,
-}
-
 /// What information is passed to a subgraph request/response stage
 #[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize, JsonSchema)]
 #[serde(default, deny_unknown_fields)]
@@ -453,6 +839,8 @@ impl SubgraphStage {
         http_client: C,
         service: subgraph::BoxService,
         coprocessor_url: String,
+        coprocessor_name: String,
+        queues: &StageQueues,
         service_name: String,
     ) -> subgraph::BoxService
     where
@@ -463,22 +851,40 @@ impl SubgraphStage {
             + 'static,
         <C as tower::Service<http::Request<Body>>>::Future: Send + 'static,
     {
+        let service_span = external_service_span(coprocessor_name.clone());
         let request_layer = (self.request != Default::default()).then_some({
             let request_config = self.request.clone();
-            let http_client = http_client.clone();
+            let http_client = StageClient::new(
+                http_client.clone(),
+                PipelineStep::SubgraphRequest,
+                request_config.on_error.clone(),
+                queues.request.as_ref(),
+                &coprocessor_name,
+            );
             let coprocessor_url = coprocessor_url.clone();
+            let coprocessor_name = coprocessor_name.clone();
             let service_name = service_name.clone();
             OneShotAsyncCheckpointLayer::new(move |request: subgraph::Request| {
                 let http_client = http_client.clone();
                 let coprocessor_url = coprocessor_url.clone();
+                let coprocessor_name = coprocessor_name.clone();
                 let service_name = service_name.clone();
                 let request_config = request_config.clone();
 
                 async move {
+                    if !request_config
+                        .condition
+                        .as_ref()
+                        .map_or(true, |condition| condition.evaluate_request(&request))
+                    {
+                        return Ok(ControlFlow::Continue(request));
+                    }
+
                     let mut succeeded = true;
                     let result = process_subgraph_request_stage(
                         http_client,
                         coprocessor_url,
+                        coprocessor_name.clone(),
                         service_name,
                         request,
                         request_config,
@@ -493,6 +899,7 @@ impl SubgraphStage {
                     });
                     tracing::info!(
                         monotonic_counter.apollo.router.operations.coprocessor = 1u64,
+                        coprocessor.name = coprocessor_name.as_str(),
                         coprocessor.stage = %PipelineStep::SubgraphRequest,
                         coprocessor.succeeded = succeeded,
                         "Total operations with co-processors enabled"
@@ -504,20 +911,36 @@ impl SubgraphStage {
 
         let response_layer = (self.response != Default::default()).then_some({
             let response_config = self.response.clone();
+            let http_client = StageClient::new(
+                http_client,
+                PipelineStep::SubgraphResponse,
+                response_config.on_error.clone(),
+                queues.response.as_ref(),
+                &coprocessor_name,
+            );
 
             MapFutureLayer::new(move |fut| {
                 let http_client = http_client.clone();
                 let coprocessor_url = coprocessor_url.clone();
+                let coprocessor_name = coprocessor_name.clone();
                 let response_config = response_config.clone();
                 let service_name = service_name.clone();
 
                 async move {
                     let response: subgraph::Response = fut.await?;
+                    if !response_config
+                        .condition
+                        .as_ref()
+                        .map_or(true, |condition| condition.evaluate_response(&response))
+                    {
+                        return Ok(response);
+                    }
 
                     let mut succeeded = true;
                     let result = process_subgraph_response_stage(
                         http_client,
                         coprocessor_url,
+                        coprocessor_name.clone(),
                         service_name,
                         response,
                         response_config,
@@ -532,6 +955,7 @@ impl SubgraphStage {
                     });
                     tracing::info!(
                         monotonic_counter.apollo.router.operations.coprocessor = 1u64,
+                        coprocessor.name = coprocessor_name.as_str(),
                         coprocessor.stage = %PipelineStep::SubgraphResponse,
                         coprocessor.succeeded = succeeded,
                         "Total operations with co-processors enabled"
@@ -541,18 +965,21 @@ impl SubgraphStage {
             })
         });
 
-        fn external_service_span() -> impl Fn(&subgraph::Request) -> tracing::Span + Clone {
+        fn external_service_span(
+            coprocessor_name: String,
+        ) -> impl Fn(&subgraph::Request) -> tracing::Span + Clone {
             move |_request: &subgraph::Request| {
                 tracing::info_span!(
                     EXTERNAL_SPAN_NAME,
                     "external service" = stringify!(subgraph::Request),
-                    "otel.kind" = "INTERNAL"
+                    "otel.kind" = "INTERNAL",
+                    "coprocessor.name" = coprocessor_name.as_str()
                 )
             }
         }
 
         ServiceBuilder::new()
-            .instrument(external_service_span())
+            .instrument(service_span)
             .option_layer(request_layer)
             .option_layer(response_layer)
             .service(service)
@@ -564,6 +991,7 @@ impl SubgraphStage {
 async fn process_router_request_stage<C>(
     http_client: C,
     coprocessor_url: String,
+    coprocessor_name: String,
     sdl: Arc<String>,
     mut request: router::Request,
     request_config: RouterRequestConf,
@@ -619,6 +1047,7 @@ where
     drop(guard);
     tracing::info!(
         histogram.apollo.router.operations.coprocessor.duration = duration,
+        coprocessor.name = coprocessor_name.as_str(),
         coprocessor.stage = %PipelineStep::RouterRequest,
     );
 
@@ -679,11 +1108,11 @@ where
             *res.response.headers_mut() = internalize_header_map(headers)?;
         }
 
-        if let Some(context) = co_processor_output.context {
-            for (key, value) in context.try_into_iter()? {
-                res.context.upsert_json_value(key, move |_current| value);
-            }
-        }
+        update_context(
+            &res.context,
+            co_processor_output.context,
+            co_processor_output.context_patch,
+        )?;
 
         return Ok(ControlFlow::Break(res));
     }
@@ -699,13 +1128,11 @@ where
 
     request.router_request = http::Request::from_parts(parts, new_body);
 
-    if let Some(context) = co_processor_output.context {
-        for (key, value) in context.try_into_iter()? {
-            request
-                .context
-                .upsert_json_value(key, move |_current| value);
-        }
-    }
+    update_context(
+        &request.context,
+        co_processor_output.context,
+        co_processor_output.context_patch,
+    )?;
 
     if let Some(headers) = co_processor_output.headers {
         *request.router_request.headers_mut() = internalize_header_map(headers)?;
@@ -717,6 +1144,7 @@ where
 async fn process_router_response_stage<C>(
     http_client: C,
     coprocessor_url: String,
+    coprocessor_name: String,
     sdl: Arc<String>,
     mut response: router::Response,
     response_config: RouterResponseConf,
@@ -783,6 +1211,7 @@ where
     drop(guard);
     tracing::info!(
         histogram.apollo.router.operations.coprocessor.duration = duration,
+        coprocessor.name = coprocessor_name.as_str(),
         coprocessor.stage = %PipelineStep::RouterResponse,
     );
 
@@ -807,13 +1236,11 @@ where
         *response.response.status_mut() = control.get_http_status()?
     }
 
-    if let Some(context) = co_processor_output.context {
-        for (key, value) in context.try_into_iter()? {
-            response
-                .context
-                .upsert_json_value(key, move |_current| value);
-        }
-    }
+    update_context(
+        &response.context,
+        co_processor_output.context,
+        co_processor_output.context_patch,
+    )?;
 
     if let Some(headers) = co_processor_output.headers {
         *response.response.headers_mut() = internalize_header_map(headers)?;
@@ -878,11 +1305,11 @@ where
                     None => bytes.into(),
                 };
 
-                if let Some(context) = co_processor_output.context {
-                    for (key, value) in context.try_into_iter()? {
-                        generator_map_context.upsert_json_value(key, move |_current| value);
-                    }
-                }
+                update_context(
+                    &generator_map_context,
+                    co_processor_output.context,
+                    co_processor_output.context_patch,
+                )?;
 
                 // We return the final_bytes into our stream of response chunks
                 Ok(final_bytes)
@@ -905,6 +1332,7 @@ where
 async fn process_subgraph_request_stage<C>(
     http_client: C,
     coprocessor_url: String,
+    coprocessor_name: String,
     service_name: String,
     mut request: subgraph::Request,
     request_config: SubgraphRequestConf,
@@ -955,6 +1383,7 @@ where
     drop(guard);
     tracing::info!(
         histogram.apollo.router.operations.coprocessor.duration = duration,
+        coprocessor.name = coprocessor_name.as_str(),
         coprocessor.stage = %PipelineStep::SubgraphRequest,
     );
 
@@ -1004,13 +1433,11 @@ where
                 context: request.context,
             };
 
-            if let Some(context) = co_processor_output.context {
-                for (key, value) in context.try_into_iter()? {
-                    subgraph_response
-                        .context
-                        .upsert_json_value(key, move |_current| value);
-                }
-            }
+            update_context(
+                &subgraph_response.context,
+                co_processor_output.context,
+                co_processor_output.context_patch,
+            )?;
 
             subgraph_response
         };
@@ -1028,13 +1455,11 @@ where
 
     request.subgraph_request = http::Request::from_parts(parts, new_body);
 
-    if let Some(context) = co_processor_output.context {
-        for (key, value) in context.try_into_iter()? {
-            request
-                .context
-                .upsert_json_value(key, move |_current| value);
-        }
-    }
+    update_context(
+        &request.context,
+        co_processor_output.context,
+        co_processor_output.context_patch,
+    )?;
 
     if let Some(headers) = co_processor_output.headers {
         *request.subgraph_request.headers_mut() = internalize_header_map(headers)?;
@@ -1050,6 +1475,7 @@ where
 async fn process_subgraph_response_stage<C>(
     http_client: C,
     coprocessor_url: String,
+    coprocessor_name: String,
     service_name: String,
     mut response: subgraph::Response,
     response_config: SubgraphResponseConf,
@@ -1100,6 +1526,7 @@ where
     drop(guard);
     tracing::info!(
         histogram.apollo.router.operations.coprocessor.duration = duration,
+        coprocessor.name = coprocessor_name.as_str(),
         coprocessor.stage = %PipelineStep::SubgraphResponse,
     );
 
@@ -1124,13 +1551,11 @@ where
         *response.response.status_mut() = control.get_http_status()?
     }
 
-    if let Some(context) = co_processor_output.context {
-        for (key, value) in context.try_into_iter()? {
-            response
-                .context
-                .upsert_json_value(key, move |_current| value);
-        }
-    }
+    update_context(
+        &response.context,
+        co_processor_output.context,
+        co_processor_output.context_patch,
+    )?;
 
     if let Some(headers) = co_processor_output.headers {
         *response.response.headers_mut() = internalize_header_map(headers)?;
@@ -1166,6 +1591,30 @@ fn validate_coprocessor_output<T>(
     Ok(())
 }
 
+/// Merge the context returned by the coprocessor, then apply its context operations
+pub(super) fn update_context(
+    context: &Context,
+    output: Option<Context>,
+    operations: Option<Vec<ContextOperation>>,
+) -> Result<(), BoxError> {
+    if let Some(output) = output {
+        for (key, value) in output.try_into_iter()? {
+            context.upsert_json_value(key, move |_current| value);
+        }
+    }
+    for operation in operations.into_iter().flatten() {
+        match operation {
+            ContextOperation::Add { key, value } => {
+                context.insert_json_value(key, value);
+            }
+            ContextOperation::Remove { key } => {
+                context.remove_json_value(key);
+            }
+        }
+    }
+    Ok(())
+}
+
 /// Convert a HashMap into a HeaderMap
 pub(super) fn internalize_header_map(
     input: HashMap<String, Vec<String>>,
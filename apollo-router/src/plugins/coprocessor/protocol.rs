@@ -0,0 +1,297 @@
+//! Coprocessor protocol versions
+//!
+//! Version 2 payloads are sent with `"version": 2`, and the coprocessor can respond with a JSON Patch (RFC 6902) of
+//! the body and a list of header operations instead of the entire body and header map. The patches are applied to
+//! the payload that was sent before the response reaches the stage, so the stages only handle version 1 responses.
+//! Context operations don't depend on the payload that was sent: they are applied to the request context by the stages.
+
+use std::collections::HashMap;
+use std::task::Poll;
+
+use bytes::Bytes;
+use futures::future::BoxFuture;
+use http::header::CONTENT_LENGTH;
+use hyper::Body;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json::Value;
+use tower::BoxError;
+use tower::Service;
+
+use crate::plugins::coprocessor::transport::StageBatch;
+use crate::plugins::coprocessor::transport::StagePayload;
+use crate::services::external::Externalizable;
+use crate::services::external::HeaderOperation;
+use crate::services::external::EXTERNALIZABLE_VERSION;
+
+const PATCH_VERSION: u8 = 2;
+
+/// Version of the payloads exchanged with the coprocessor
+#[derive(Clone, Copy, Debug, Default, Deserialize, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(super) enum ProtocolVersion {
+    /// The coprocessor responds with entire bodies and header maps
+    #[default]
+    V1,
+    /// The coprocessor can respond with patches of the body and headers
+    V2,
+}
+
+/// Client of a coprocessor, negotiating its protocol version
+#[derive(Clone)]
+pub(super) struct ProtocolService<C> {
+    inner: C,
+    version: ProtocolVersion,
+}
+
+impl<C> ProtocolService<C> {
+    pub(super) fn new(inner: C, version: ProtocolVersion) -> Self {
+        Self { inner, version }
+    }
+}
+
+impl<C> Service<hyper::Request<Body>> for ProtocolService<C>
+where
+    C: Service<hyper::Request<Body>, Response = hyper::Response<Body>, Error = BoxError>
+        + Clone
+        + Send
+        + 'static,
+    C::Future: Send + 'static,
+{
+    type Response = hyper::Response<Body>;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: hyper::Request<Body>) -> Self::Future {
+        if self.version == ProtocolVersion::V1 {
+            return Box::pin(self.inner.call(request));
+        }
+
+        // the request body is read before calling the coprocessor: take the service that was polled ready
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        Box::pin(async move {
+            let (mut parts, body) = request.into_parts();
+            let body = hyper::body::to_bytes(body).await?;
+            parts.headers.remove(CONTENT_LENGTH);
+            // the gRPC transport sends the stage payloads instead of the body
+            if let Some(payload) = parts.extensions.get_mut::<StagePayload>() {
+                payload.set_version(PATCH_VERSION);
+            }
+            if let Some(StageBatch(batch)) = parts.extensions.get_mut::<StageBatch>() {
+                for payload in batch {
+                    payload.set_version(PATCH_VERSION);
+                }
+            }
+
+            let mut sent: Value = serde_json::from_slice(&body)?;
+            // batches of asynchronous stages are arrays, and their responses are ignored
+            if let Value::Array(batch) = &mut sent {
+                batch.iter_mut().for_each(set_patch_version);
+                return inner
+                    .call(hyper::Request::from_parts(
+                        parts,
+                        Body::from(serde_json::to_vec(&sent)?),
+                    ))
+                    .await;
+            }
+            set_patch_version(&mut sent);
+
+            let response = inner
+                .call(hyper::Request::from_parts(
+                    parts,
+                    Body::from(serde_json::to_vec(&sent)?),
+                ))
+                .await?;
+            if !response.status().is_success() {
+                return Ok(response);
+            }
+
+            let (mut parts, body) = response.into_parts();
+            let received = apply_patches(sent, hyper::body::to_bytes(body).await?)?;
+            parts.headers.remove(CONTENT_LENGTH);
+            Ok(hyper::Response::from_parts(parts, Body::from(received)))
+        })
+    }
+}
+
+fn set_patch_version(payload: &mut Value) {
+    if let Some(payload) = payload.as_object_mut() {
+        payload.insert("version".to_string(), PATCH_VERSION.into());
+    }
+}
+
+/// Converts a version 2 response to a version 1 response
+fn apply_patches(mut sent: Value, received: Bytes) -> Result<Bytes, BoxError> {
+    // invalid responses are reported by the stage
+    let Ok(mut output) = serde_json::from_slice::<Externalizable<Value>>(&received) else {
+        return Ok(received);
+    };
+    if output.version != PATCH_VERSION {
+        return Err(format!(
+            "Coprocessor returned the wrong version: expected `{PATCH_VERSION}` found `{}`",
+            output.version
+        )
+        .into());
+    }
+    output.version = EXTERNALIZABLE_VERSION;
+
+    if let Some(patch) = output.body_patch.take() {
+        if output.body.is_some() {
+            return Err("Coprocessor returned both `body` and `bodyPatch`".into());
+        }
+        let mut body = sent
+            .get_mut("body")
+            .map(Value::take)
+            .ok_or("Coprocessor returned a `bodyPatch` but the body was not sent")?;
+        json_patch::patch(&mut body, &patch.0)?;
+        output.body = Some(body);
+    }
+
+    if let Some(operations) = output.headers_patch.take() {
+        if output.headers.is_some() {
+            return Err("Coprocessor returned both `headers` and `headersPatch`".into());
+        }
+        let mut headers: HashMap<String, Vec<String>> = sent
+            .get_mut("headers")
+            .map(Value::take)
+            .map(serde_json::from_value)
+            .transpose()?
+            .ok_or("Coprocessor returned a `headersPatch` but the headers were not sent")?;
+        for operation in operations {
+            match operation {
+                HeaderOperation::Add { name, value } => headers
+                    .entry(name.to_ascii_lowercase())
+                    .or_default()
+                    .push(value),
+                HeaderOperation::Remove { name } => {
+                    headers.remove(&name.to_ascii_lowercase());
+                }
+            }
+        }
+        output.headers = Some(headers);
+    }
+
+    if output.context.is_some() && output.context_patch.is_some() {
+        return Err("Coprocessor returned both `context` and `contextPatch`".into());
+    }
+
+    Ok(serde_json::to_vec(&output)?.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::future::BoxFuture;
+    use serde_json::json;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::plugin::test::MockHttpClientService;
+
+    fn patching_client() -> MockHttpClientService {
+        let mut mock = MockHttpClientService::new();
+        mock.expect_clone().returning(patching_client);
+        mock.expect_call()
+            .returning(|request: hyper::Request<Body>| {
+                Box::pin(async {
+                    let body = hyper::body::to_bytes(request.into_body()).await.unwrap();
+                    let sent: Value = serde_json::from_slice(&body).unwrap();
+                    assert_eq!(sent["version"], 2);
+                    let response = json!({
+                        "version": 2,
+                        "stage": "SupergraphResponse",
+                        "bodyPatch": [
+                            { "op": "remove", "path": "/data/me/email" },
+                            { "op": "add", "path": "/data/me/name", "value": "Ada" }
+                        ],
+                        "headersPatch": [
+                            { "op": "add", "name": "X-Coprocessor", "value": "v2" },
+                            { "op": "remove", "name": "set-cookie" }
+                        ],
+                        "contextPatch": [
+                            { "op": "remove", "key": "session" }
+                        ]
+                    });
+                    Ok(hyper::Response::new(Body::from(response.to_string())))
+                }) as BoxFuture<'static, _>
+            });
+        mock
+    }
+
+    #[tokio::test]
+    async fn version_2_responses_are_patches() {
+        let payload = json!({
+            "version": 1,
+            "stage": "SupergraphResponse",
+            "id": "1",
+            "headers": {
+                "content-type": ["application/json"],
+                "set-cookie": ["session=secret"]
+            },
+            "body": { "data": { "me": { "email": "ada@example.com" } } }
+        });
+        let response = ProtocolService::new(patching_client(), ProtocolVersion::V2)
+            .oneshot(hyper::Request::new(Body::from(payload.to_string())))
+            .await
+            .unwrap();
+
+        let received: Value =
+            serde_json::from_slice(&hyper::body::to_bytes(response.into_body()).await.unwrap())
+                .unwrap();
+        assert_eq!(received["version"], 1);
+        assert_eq!(
+            received["body"],
+            json!({ "data": { "me": { "name": "Ada" } } })
+        );
+        assert_eq!(
+            received["headers"],
+            json!({
+                "content-type": ["application/json"],
+                "x-coprocessor": ["v2"]
+            })
+        );
+        assert_eq!(
+            received["contextPatch"],
+            json!([{ "op": "remove", "key": "session" }])
+        );
+    }
+
+    #[test]
+    fn patches_require_the_sent_values() {
+        let sent = json!({
+            "version": 2,
+            "stage": "RouterRequest",
+            "id": "1",
+        });
+        let received = json!({
+            "version": 2,
+            "stage": "RouterRequest",
+            "control": "continue",
+            "bodyPatch": [{ "op": "replace", "path": "", "value": "{}" }]
+        });
+        assert!(apply_patches(sent, received.to_string().into()).is_err());
+    }
+
+    #[test]
+    fn context_and_context_patch_are_exclusive() {
+        let sent = json!({
+            "version": 2,
+            "stage": "RouterRequest",
+            "id": "1",
+            "context": { "entries": {} }
+        });
+        let received = json!({
+            "version": 2,
+            "stage": "RouterRequest",
+            "control": "continue",
+            "context": { "entries": { "user": "ada" } },
+            "contextPatch": [{ "op": "remove", "key": "session" }]
+        });
+        assert!(apply_patches(sent, received.to_string().into()).is_err());
+    }
+}
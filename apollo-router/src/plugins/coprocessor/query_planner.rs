@@ -0,0 +1,563 @@
+use std::ops::ControlFlow;
+use std::sync::Arc;
+
+use derivative::Derivative;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
+use tower::BoxError;
+use tower_service::Service;
+
+use super::*;
+use crate::error::QueryPlannerError;
+use crate::graphql;
+use crate::plugins::progressive_override::LABELS_TO_OVERRIDE_KEY;
+use crate::services::query_planner;
+use crate::services::QueryPlannerContent;
+use crate::Context;
+
+/// What information is passed to a query planner request stage
+#[derive(Clone, Debug, Default, Deserialize, Derivative, Serialize, JsonSchema)]
+#[derivative(PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub(super) struct QueryPlannerRequestConf {
+    /// Send the body (query and operation name)
+    pub(super) body: bool,
+    /// Send the SDL
+    pub(super) sdl: bool,
+    /// Send the progressive override labels chosen for this request
+    pub(super) override_labels: bool,
+    /// Queue the payload and continue without waiting for the coprocessor, whose control is ignored
+    #[serde(rename = "async")]
+    pub(super) is_async: bool,
+    /// What to do when the coprocessor call fails (default: fail the request)
+    #[serde(skip_serializing)]
+    #[derivative(PartialEq = "ignore")]
+    pub(super) on_error: Option<OnError>,
+}
+
+/// What information is passed to a query planner response stage
+#[derive(Clone, Debug, Default, Deserialize, Derivative, Serialize, JsonSchema)]
+#[derivative(PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub(super) struct QueryPlannerResponseConf {
+    /// Send the SDL
+    pub(super) sdl: bool,
+    /// Send the query plan
+    pub(super) query_plan: bool,
+    /// Queue the payload and continue without waiting for the coprocessor, whose control is ignored
+    #[serde(rename = "async")]
+    pub(super) is_async: bool,
+    /// What to do when the coprocessor call fails (default: fail the request)
+    #[serde(skip_serializing)]
+    #[derivative(PartialEq = "ignore")]
+    pub(super) on_error: Option<OnError>,
+}
+
+/// The query planner stage runs once per cached query plan, not once per request, so it only receives the
+/// data that the plan depends on. Rejected operations are not cached
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize, JsonSchema)]
+#[serde(default)]
+pub(super) struct QueryPlannerStage {
+    /// The request configuration
+    pub(super) request: QueryPlannerRequestConf,
+    /// The response configuration
+    pub(super) response: QueryPlannerResponseConf,
+}
+
+impl QueryPlannerStage {
+    pub(crate) fn as_service<C>(
+        &self,
+        http_client: C,
+        service: query_planner::BoxService,
+        coprocessor_url: String,
+        coprocessor_name: String,
+        queues: &StageQueues,
+        sdl: Arc<String>,
+    ) -> query_planner::BoxService
+    where
+        C: Service<hyper::Request<Body>, Response = hyper::Response<Body>, Error = BoxError>
+            + Clone
+            + Send
+            + Sync
+            + 'static,
+        <C as tower::Service<http::Request<Body>>>::Future: Send + 'static,
+    {
+        if self.request == Default::default() && self.response == Default::default() {
+            return service;
+        }
+
+        // The checkpoint layers only work with BoxError, so the query planner is called by hand
+        let service = ServiceBuilder::new().buffered().service(service);
+        let request_config = self.request.clone();
+        let response_config = self.response.clone();
+        let request_client = StageClient::new(
+            http_client.clone(),
+            PipelineStep::QueryPlannerRequest,
+            request_config.on_error.clone(),
+            queues.request.as_ref(),
+            &coprocessor_name,
+        );
+        let response_client = StageClient::new(
+            http_client,
+            PipelineStep::QueryPlannerResponse,
+            response_config.on_error.clone(),
+            queues.response.as_ref(),
+            &coprocessor_name,
+        );
+
+        tower::service_fn(move |request: query_planner::Request| {
+            let service = service.clone();
+            let request_config = request_config.clone();
+            let response_config = response_config.clone();
+            let coprocessor_url = coprocessor_url.clone();
+            let coprocessor_name = coprocessor_name.clone();
+            let request_client = request_client.clone();
+            let response_client = response_client.clone();
+            let sdl = sdl.clone();
+
+            async move {
+                let context = request.context.clone();
+
+                let request = if request_config != Default::default() {
+                    let result = process_query_planner_request_stage(
+                        request_client,
+                        coprocessor_url.clone(),
+                        coprocessor_name.clone(),
+                        sdl.clone(),
+                        request,
+                        request_config,
+                    )
+                    .await;
+                    let succeeded = result.is_ok();
+                    u64_counter!(
+                        "apollo.router.operations.coprocessor",
+                        "Total operations with co-processors enabled",
+                        1,
+                        "coprocessor.name" = coprocessor_name.clone(),
+                        "coprocessor.stage" = PipelineStep::QueryPlannerRequest,
+                        "coprocessor.succeeded" = succeeded
+                    );
+                    match result {
+                        Ok(ControlFlow::Continue(request)) => request,
+                        Ok(ControlFlow::Break(response)) => return Ok(response),
+                        Err(error) => {
+                            tracing::error!(
+                                "external extensibility: query planner request stage error: {error}"
+                            );
+                            return Ok(failure(context, error));
+                        }
+                    }
+                } else {
+                    request
+                };
+
+                let response = service
+                    .oneshot(request)
+                    .await
+                    .map_err(query_planner_error)?;
+
+                if response_config == Default::default()
+                    || !matches!(response.content, Some(QueryPlannerContent::Plan { .. }))
+                {
+                    return Ok(response);
+                }
+
+                let result = process_query_planner_response_stage(
+                    response_client,
+                    coprocessor_url,
+                    coprocessor_name.clone(),
+                    sdl,
+                    response,
+                    response_config,
+                )
+                .await;
+                u64_counter!(
+                    "apollo.router.operations.coprocessor",
+                    "Total operations with co-processors enabled",
+                    1,
+                    "coprocessor.name" = coprocessor_name.clone(),
+                    "coprocessor.stage" = PipelineStep::QueryPlannerResponse,
+                    "coprocessor.succeeded" = result.is_ok()
+                );
+                Ok::<_, QueryPlannerError>(result.unwrap_or_else(|error| {
+                    tracing::error!(
+                        "external extensibility: query planner response stage error: {error}"
+                    );
+                    failure(context, error)
+                }))
+            }
+        })
+        .boxed()
+    }
+}
+
+/// The buffer boxes the query planner errors. Its own errors mean that the query planner stopped
+fn query_planner_error(error: BoxError) -> QueryPlannerError {
+    match error.downcast::<QueryPlannerError>() {
+        Ok(error) => *error,
+        Err(error) => QueryPlannerError::JoinError(error.to_string()),
+    }
+}
+
+/// Errors are returned in the response instead of failing the query planner, so that they are not cached
+fn failure(context: Context, error: BoxError) -> query_planner::Response {
+    query_planner::Response::builder()
+        .context(context)
+        .errors(vec![Error::builder()
+            .message(format!("external extensibility error: {error}"))
+            .extension_code(COPROCESSOR_ERROR_EXTENSION)
+            .build()])
+        .build()
+}
+
+/// Turns the body of a coprocessor `break` into a rejected query planner response
+fn rejection(context: Context, body: Option<serde_json::Value>) -> query_planner::Response {
+    let graphql_response: graphql::Response =
+        serde_json::from_value(body.unwrap_or(serde_json::Value::Null)).unwrap_or_else(|error| {
+            graphql::Response::builder()
+                .errors(vec![Error::builder()
+                    .message(format!(
+                        "couldn't deserialize coprocessor output body: {error}"
+                    ))
+                    .extension_code(COPROCESSOR_DESERIALIZATION_ERROR_EXTENSION)
+                    .build()])
+                .build()
+        });
+
+    let mut errors = graphql_response.errors;
+    // the supergraph service only stops when there are errors
+    if errors.is_empty() {
+        errors.push(
+            Error::builder()
+                .message("operation rejected by the coprocessor")
+                .extension_code(COPROCESSOR_ERROR_EXTENSION)
+                .build(),
+        );
+    }
+
+    query_planner::Response::builder()
+        .context(context)
+        .errors(errors)
+        .build()
+}
+
+async fn process_query_planner_request_stage<C>(
+    http_client: C,
+    coprocessor_url: String,
+    coprocessor_name: String,
+    sdl: Arc<String>,
+    request: query_planner::Request,
+    request_config: QueryPlannerRequestConf,
+) -> Result<ControlFlow<query_planner::Response, query_planner::Request>, BoxError>
+where
+    C: Service<hyper::Request<Body>, Response = hyper::Response<Body>, Error = BoxError>
+        + Clone
+        + Send
+        + Sync
+        + 'static,
+    <C as tower::Service<http::Request<Body>>>::Future: Send + 'static,
+{
+    let body_to_send = request_config.body.then(|| {
+        serde_json::json!({
+            "query": request.query,
+            "operationName": request.operation_name,
+        })
+    });
+    let sdl_to_send = request_config.sdl.then(|| sdl.clone().to_string());
+    let override_labels = request_config.override_labels.then(|| {
+        request
+            .context
+            .get::<_, Vec<String>>(LABELS_TO_OVERRIDE_KEY)
+            .ok()
+            .flatten()
+            .unwrap_or_default()
+    });
+
+    let payload = Externalizable::query_planner_builder()
+        .stage(PipelineStep::QueryPlannerRequest)
+        .control(Control::default())
+        .id(request.context.id.clone())
+        .and_body(body_to_send)
+        .and_sdl(sdl_to_send)
+        .and_override_labels(override_labels)
+        .build();
+
+    tracing::debug!(?payload, "externalized output");
+    let start = Instant::now();
+    let co_processor_result = payload.call(http_client, &coprocessor_url).await;
+    let duration = start.elapsed().as_secs_f64();
+    tracing::info!(
+        histogram.apollo.router.operations.coprocessor.duration = duration,
+        coprocessor.name = coprocessor_name.as_str(),
+        coprocessor.stage = %PipelineStep::QueryPlannerRequest,
+    );
+
+    tracing::debug!(?co_processor_result, "co-processor returned");
+    let co_processor_output = co_processor_result?;
+    validate_coprocessor_output(&co_processor_output, PipelineStep::QueryPlannerRequest)?;
+    // unwrap is safe here because validate_coprocessor_output made sure control is available
+    let control = co_processor_output.control.expect("validated above; qed");
+
+    if matches!(control, Control::Break(_)) {
+        return Ok(ControlFlow::Break(rejection(
+            request.context,
+            co_processor_output.body,
+        )));
+    }
+
+    // The query and operation name are read only: they were already used to compute the query plan cache key.
+    // The context is not sent nor updated: it belongs to the request that caused the cache miss
+    Ok(ControlFlow::Continue(request))
+}
+
+async fn process_query_planner_response_stage<C>(
+    http_client: C,
+    coprocessor_url: String,
+    coprocessor_name: String,
+    sdl: Arc<String>,
+    response: query_planner::Response,
+    response_config: QueryPlannerResponseConf,
+) -> Result<query_planner::Response, BoxError>
+where
+    C: Service<hyper::Request<Body>, Response = hyper::Response<Body>, Error = BoxError>
+        + Clone
+        + Send
+        + Sync
+        + 'static,
+    <C as tower::Service<http::Request<Body>>>::Future: Send + 'static,
+{
+    let sdl_to_send = response_config.sdl.then(|| sdl.clone().to_string());
+    let query_plan = match &response.content {
+        Some(QueryPlannerContent::Plan { plan }) if response_config.query_plan => {
+            Some(plan.clone())
+        }
+        _ => None,
+    };
+
+    let payload = Externalizable::<serde_json::Value>::query_planner_builder()
+        .stage(PipelineStep::QueryPlannerResponse)
+        .id(response.context.id.clone())
+        .and_sdl(sdl_to_send)
+        .and_query_plan(query_plan)
+        .build();
+
+    tracing::debug!(?payload, "externalized output");
+    let start = Instant::now();
+    let co_processor_result = payload.call(http_client, &coprocessor_url).await;
+    let duration = start.elapsed().as_secs_f64();
+    tracing::info!(
+        histogram.apollo.router.operations.coprocessor.duration = duration,
+        coprocessor.name = coprocessor_name.as_str(),
+        coprocessor.stage = %PipelineStep::QueryPlannerResponse,
+    );
+
+    tracing::debug!(?co_processor_result, "co-processor returned");
+    let co_processor_output = co_processor_result?;
+    validate_coprocessor_output(&co_processor_output, PipelineStep::QueryPlannerResponse)?;
+
+    // The response stage may answer with a control field to reject the query plan
+    if matches!(co_processor_output.control, Some(Control::Break(_))) {
+        return Ok(rejection(response.context, co_processor_output.body));
+    }
+
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use futures::future::BoxFuture;
+    use hyper::Body;
+    use tower::BoxError;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::plugin::test::MockHttpClientService;
+    use crate::query_planner::QueryPlan;
+
+    #[allow(clippy::type_complexity)]
+    fn mock_with_callback(
+        callback: fn(
+            hyper::Request<Body>,
+        ) -> BoxFuture<'static, Result<hyper::Response<Body>, BoxError>>,
+    ) -> MockHttpClientService {
+        // the client is cloned once per call and once more when both stages are enabled
+        let mut mock_http_client = MockHttpClientService::new();
+        mock_http_client
+            .expect_clone()
+            .returning(move || mock_with_callback(callback));
+        mock_http_client.expect_call().returning(callback);
+
+        mock_http_client
+    }
+
+    fn planner() -> query_planner::BoxService {
+        tower::service_fn(|request: query_planner::Request| async move {
+            Ok::<_, QueryPlannerError>(
+                query_planner::Response::builder()
+                    .content(QueryPlannerContent::Plan {
+                        plan: Arc::new(QueryPlan::fake_builder().build()),
+                    })
+                    .context(request.context)
+                    .build(),
+            )
+        })
+        .boxed()
+    }
+
+    fn request() -> query_planner::Request {
+        query_planner::Request::builder()
+            .query("{ me { name } }")
+            .context(Context::new())
+            .build()
+    }
+
+    #[tokio::test]
+    async fn external_plugin_query_planner_request() {
+        let stage = QueryPlannerStage {
+            request: QueryPlannerRequestConf {
+                body: true,
+                sdl: false,
+                override_labels: true,
+                is_async: false,
+                on_error: None,
+            },
+            response: Default::default(),
+        };
+
+        let mock_http_client = mock_with_callback(move |req: hyper::Request<Body>| {
+            Box::pin(async {
+                let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+                let payload: serde_json::Value = serde_json::from_slice(&body).unwrap();
+                assert_eq!(payload["stage"], "QueryPlannerRequest");
+                assert_eq!(payload["body"]["query"], "{ me { name } }");
+                assert_eq!(payload["overrideLabels"], serde_json::json!([]));
+                // the context belongs to the request that caused the query plan cache miss
+                assert!(payload.get("context").is_none());
+
+                Ok(hyper::Response::builder()
+                    .body(Body::from(
+                        r#"{
+                            "version": 1,
+                            "stage": "QueryPlannerRequest",
+                            "control": "continue",
+                            "context": {
+                                "entries": {
+                                    "this-is-a-test-context": 42
+                                }
+                            }
+                        }"#,
+                    ))
+                    .unwrap())
+            })
+        });
+
+        let service = stage.as_service(
+            mock_http_client,
+            planner(),
+            "http://test".to_string(),
+            "test".to_string(),
+            &Default::default(),
+            Arc::new("".to_string()),
+        );
+
+        let response = service.oneshot(request()).await.unwrap();
+        assert!(response.errors.is_empty());
+        assert!(matches!(
+            response.content,
+            Some(QueryPlannerContent::Plan { .. })
+        ));
+        assert!(response
+            .context
+            .get::<&str, u8>("this-is-a-test-context")
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn external_plugin_query_planner_response_rejects_plan() {
+        let stage = QueryPlannerStage {
+            request: Default::default(),
+            response: QueryPlannerResponseConf {
+                sdl: false,
+                query_plan: true,
+                is_async: false,
+                on_error: None,
+            },
+        };
+
+        let mock_http_client = mock_with_callback(move |req: hyper::Request<Body>| {
+            Box::pin(async {
+                let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+                let payload: serde_json::Value = serde_json::from_slice(&body).unwrap();
+                assert_eq!(payload["stage"], "QueryPlannerResponse");
+                assert!(payload["queryPlan"].is_object());
+
+                Ok(hyper::Response::builder()
+                    .body(Body::from(
+                        r#"{
+                            "version": 1,
+                            "stage": "QueryPlannerResponse",
+                            "control": { "break": 400 },
+                            "body": {
+                                "errors": [{
+                                    "message": "too many fetches",
+                                    "extensions": { "code": "PLAN_TOO_EXPENSIVE" }
+                                }]
+                            }
+                        }"#,
+                    ))
+                    .unwrap())
+            })
+        });
+
+        let service = stage.as_service(
+            mock_http_client,
+            planner(),
+            "http://test".to_string(),
+            "test".to_string(),
+            &Default::default(),
+            Arc::new("".to_string()),
+        );
+
+        let response = service.oneshot(request()).await.unwrap();
+        assert!(response.content.is_none());
+        assert_eq!(response.errors.len(), 1);
+        assert_eq!(response.errors[0].message, "too many fetches");
+    }
+
+    #[tokio::test]
+    async fn external_plugin_query_planner_keeps_planner_errors() {
+        let stage = QueryPlannerStage {
+            request: Default::default(),
+            response: QueryPlannerResponseConf {
+                sdl: false,
+                query_plan: true,
+                is_async: false,
+                on_error: None,
+            },
+        };
+        let planner = tower::service_fn(|_: query_planner::Request| async {
+            Err::<query_planner::Response, _>(QueryPlannerError::UnhandledPlannerResult)
+        })
+        .boxed();
+
+        let service = stage.as_service(
+            mock_with_callback(|_| {
+                Box::pin(async { panic!("the coprocessor is not called without a query plan") })
+            }),
+            planner,
+            "http://test".to_string(),
+            "test".to_string(),
+            &Default::default(),
+            Arc::new("".to_string()),
+        );
+
+        assert!(matches!(
+            service.oneshot(request()).await,
+            Err(QueryPlannerError::UnhandledPlannerResult)
+        ));
+    }
+}
@@ -0,0 +1,254 @@
+//! Signing of coprocessor payloads
+//!
+//! Each payload is signed with an HMAC-SHA256 of `{timestamp}.{body}`: the signature is sent hex encoded in the
+//! `x-coprocessor-signature` header, and the UNIX timestamp in seconds in the `x-coprocessor-timestamp` header.
+//! Coprocessor responses can be required to be signed the same way.
+
+use std::fmt;
+use std::sync::Arc;
+use std::task::Poll;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use futures::future::BoxFuture;
+use hmac::Hmac;
+use hmac::Mac;
+use http::HeaderMap;
+use http::HeaderValue;
+use hyper::Body;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use sha2::Sha256;
+use tower::BoxError;
+use tower::Service;
+
+type HmacSha256 = Hmac<Sha256>;
+
+const SIGNATURE_HEADER: &str = "x-coprocessor-signature";
+const TIMESTAMP_HEADER: &str = "x-coprocessor-timestamp";
+const DEFAULT_MAX_AGE: Duration = Duration::from_secs(300);
+
+/// Signs the payloads sent to the coprocessor with an HMAC, and optionally verifies the signature of its responses
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(super) struct SigningConf {
+    /// The secret shared with the coprocessor
+    secret: String,
+    /// Reject coprocessor responses that are not signed with the secret (default: false)
+    #[serde(default)]
+    verify_responses: bool,
+    /// The maximum difference between the timestamp of a signed response and the router's clock (default: 5m)
+    #[serde(
+        deserialize_with = "humantime_serde::deserialize",
+        default = "default_max_age"
+    )]
+    #[schemars(with = "String", default = "default_max_age")]
+    max_age: Duration,
+}
+
+fn default_max_age() -> Duration {
+    DEFAULT_MAX_AGE
+}
+
+impl SigningConf {
+    fn signature(&self, timestamp: &str, body: &[u8]) -> Result<HmacSha256, BoxError> {
+        let mut mac = HmacSha256::new_from_slice(self.secret.as_bytes())?;
+        mac.update(timestamp.as_bytes());
+        mac.update(b".");
+        mac.update(body);
+        Ok(mac)
+    }
+
+    fn sign(&self, headers: &mut HeaderMap, body: &[u8]) -> Result<(), BoxError> {
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)?
+            .as_secs()
+            .to_string();
+        let signature = hex::encode(self.signature(&timestamp, body)?.finalize().into_bytes());
+        headers.insert(TIMESTAMP_HEADER, HeaderValue::from_str(&timestamp)?);
+        headers.insert(SIGNATURE_HEADER, HeaderValue::from_str(&signature)?);
+        Ok(())
+    }
+
+    fn verify(&self, headers: &HeaderMap, body: &[u8]) -> Result<(), BoxError> {
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .ok_or(InvalidSignatureError("missing signature"))
+        };
+        let timestamp = header(TIMESTAMP_HEADER)?;
+        let signature = hex::decode(header(SIGNATURE_HEADER)?)
+            .map_err(|_| InvalidSignatureError("invalid signature"))?;
+
+        let signed_at = timestamp
+            .parse::<u64>()
+            .map(|seconds| SystemTime::UNIX_EPOCH + Duration::from_secs(seconds))
+            .map_err(|_| InvalidSignatureError("invalid timestamp"))?;
+        let now = SystemTime::now();
+        let age = now
+            .duration_since(signed_at)
+            .or_else(|_| signed_at.duration_since(now))
+            .unwrap_or_default();
+        if age > self.max_age {
+            return Err(InvalidSignatureError("expired signature").into());
+        }
+
+        // constant time comparison
+        self.signature(timestamp, body)?
+            .verify_slice(&signature)
+            .map_err(|_| InvalidSignatureError("invalid signature").into())
+    }
+}
+
+#[derive(Debug)]
+struct InvalidSignatureError(&'static str);
+
+impl fmt::Display for InvalidSignatureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "coprocessor response rejected: {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidSignatureError {}
+
+/// Client of a coprocessor, signing its payloads
+#[derive(Clone)]
+pub(super) struct SigningService<C> {
+    inner: C,
+    signing: Option<Arc<SigningConf>>,
+}
+
+impl<C> SigningService<C> {
+    pub(super) fn new(inner: C, signing: Option<SigningConf>) -> Self {
+        Self {
+            inner,
+            signing: signing.map(Arc::new),
+        }
+    }
+}
+
+impl<C> Service<hyper::Request<Body>> for SigningService<C>
+where
+    C: Service<hyper::Request<Body>, Response = hyper::Response<Body>, Error = BoxError>
+        + Clone
+        + Send
+        + 'static,
+    C::Future: Send + 'static,
+{
+    type Response = hyper::Response<Body>;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: hyper::Request<Body>) -> Self::Future {
+        let signing = match self.signing.clone() {
+            Some(signing) => signing,
+            None => return Box::pin(self.inner.call(request)),
+        };
+
+        // the request body is read before calling the coprocessor: take the service that was polled ready
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        Box::pin(async move {
+            let (mut parts, body) = request.into_parts();
+            let body = hyper::body::to_bytes(body).await?;
+            signing.sign(&mut parts.headers, &body)?;
+
+            let response = inner
+                .call(hyper::Request::from_parts(parts, Body::from(body)))
+                .await?;
+            if !signing.verify_responses || !response.status().is_success() {
+                return Ok(response);
+            }
+
+            let (parts, body) = response.into_parts();
+            let body = hyper::body::to_bytes(body).await?;
+            signing.verify(&parts.headers, &body)?;
+            Ok(hyper::Response::from_parts(parts, Body::from(body)))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::future::BoxFuture;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::plugin::test::MockHttpClientService;
+
+    fn conf() -> SigningConf {
+        SigningConf {
+            secret: "secret".to_string(),
+            verify_responses: true,
+            max_age: DEFAULT_MAX_AGE,
+        }
+    }
+
+    // echoes the payload, signed with the given secret
+    fn signing_client(secret: &'static str) -> MockHttpClientService {
+        let mut mock = MockHttpClientService::new();
+        mock.expect_clone()
+            .returning(move || signing_client(secret));
+        mock.expect_call()
+            .returning(move |request: hyper::Request<Body>| {
+                Box::pin(async move {
+                    let (parts, body) = request.into_parts();
+                    let body = hyper::body::to_bytes(body).await.unwrap();
+                    conf().verify(&parts.headers, &body).unwrap();
+
+                    let mut response = hyper::Response::new(Body::from(body.clone()));
+                    SigningConf {
+                        secret: secret.to_string(),
+                        ..conf()
+                    }
+                    .sign(response.headers_mut(), &body)
+                    .unwrap();
+                    Ok(response)
+                }) as BoxFuture<'static, _>
+            });
+        mock
+    }
+
+    #[tokio::test]
+    async fn payloads_are_signed_and_verified() {
+        let response = SigningService::new(signing_client("secret"), Some(conf()))
+            .oneshot(hyper::Request::new(Body::from("{}")))
+            .await
+            .unwrap();
+        assert_eq!(
+            hyper::body::to_bytes(response.into_body()).await.unwrap(),
+            "{}"
+        );
+    }
+
+    #[tokio::test]
+    async fn responses_with_invalid_signatures_are_rejected() {
+        let error = SigningService::new(signing_client("other"), Some(conf()))
+            .oneshot(hyper::Request::new(Body::from("{}")))
+            .await
+            .unwrap_err();
+        assert!(error.is::<InvalidSignatureError>());
+    }
+
+    #[test]
+    fn expired_signatures_are_rejected() {
+        let conf = conf();
+        let timestamp = "1700000000";
+        let mut headers = HeaderMap::new();
+        headers.insert(TIMESTAMP_HEADER, HeaderValue::from_static(timestamp));
+        let signature = hex::encode(
+            conf.signature(timestamp, b"{}")
+                .unwrap()
+                .finalize()
+                .into_bytes(),
+        );
+        headers.insert(SIGNATURE_HEADER, HeaderValue::from_str(&signature).unwrap());
+        assert!(conf.verify(&headers, b"{}").is_err());
+    }
+}
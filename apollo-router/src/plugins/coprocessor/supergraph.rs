@@ -1,6 +1,7 @@
 use std::ops::ControlFlow;
 use std::sync::Arc;
 
+use derivative::Derivative;
 use futures::future;
 use futures::stream;
 use schemars::JsonSchema;
@@ -16,11 +17,14 @@ use crate::graphql;
 use crate::layers::async_checkpoint::OneShotAsyncCheckpointLayer;
 use crate::layers::ServiceBuilderExt;
 use crate::plugins::coprocessor::EXTERNAL_SPAN_NAME;
+use crate::plugins::telemetry::config_new::conditions::Condition;
+use crate::plugins::telemetry::config_new::selectors::SupergraphSelector;
 use crate::response;
 use crate::services::supergraph;
 
 /// What information is passed to a router request/response stage
-#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize, JsonSchema)]
+#[derive(Clone, Debug, Default, Deserialize, Derivative, Serialize, JsonSchema)]
+#[derivative(PartialEq)]
 #[serde(default, deny_unknown_fields)]
 pub(super) struct SupergraphRequestConf {
     /// Send the headers
@@ -33,10 +37,22 @@ pub(super) struct SupergraphRequestConf {
     pub(super) sdl: bool,
     /// Send the method
     pub(super) method: bool,
+    /// Only send the request to the coprocessor when this condition is true (default: always)
+    #[serde(skip_serializing)]
+    #[derivative(PartialEq = "ignore")]
+    pub(super) condition: Option<Condition<SupergraphSelector>>,
+    /// Queue the payload and continue without waiting for the coprocessor, whose control is ignored
+    #[serde(rename = "async")]
+    pub(super) is_async: bool,
+    /// What to do when the coprocessor call fails (default: fail the request)
+    #[serde(skip_serializing)]
+    #[derivative(PartialEq = "ignore")]
+    pub(super) on_error: Option<OnError>,
 }
 
 /// What information is passed to a router request/response stage
-#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize, JsonSchema)]
+#[derive(Clone, Debug, Deserialize, Derivative, Serialize, JsonSchema)]
+#[derivative(PartialEq)]
 #[serde(default, deny_unknown_fields)]
 pub(super) struct SupergraphResponseConf {
     /// Send the headers
@@ -49,6 +65,35 @@ pub(super) struct SupergraphResponseConf {
     pub(super) sdl: bool,
     /// Send the HTTP status
     pub(super) status_code: bool,
+    /// Send the subsequent chunks of deferred responses and subscription events (default: true)
+    pub(super) stream: bool,
+    /// Only send the response to the coprocessor when this condition is true (default: always)
+    #[serde(skip_serializing)]
+    #[derivative(PartialEq = "ignore")]
+    pub(super) condition: Option<Condition<SupergraphSelector>>,
+    /// Queue the payload and continue without waiting for the coprocessor, whose control is ignored
+    #[serde(rename = "async")]
+    pub(super) is_async: bool,
+    /// What to do when the coprocessor call fails (default: fail the request)
+    #[serde(skip_serializing)]
+    #[derivative(PartialEq = "ignore")]
+    pub(super) on_error: Option<OnError>,
+}
+
+impl Default for SupergraphResponseConf {
+    fn default() -> Self {
+        Self {
+            headers: false,
+            context: false,
+            body: false,
+            sdl: false,
+            status_code: false,
+            stream: true,
+            condition: None,
+            is_async: false,
+            on_error: None,
+        }
+    }
 }
 
 #[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize, JsonSchema)]
@@ -66,6 +111,8 @@ impl SupergraphStage {
         http_client: C,
         service: supergraph::BoxService,
         coprocessor_url: String,
+        coprocessor_name: String,
+        queues: &StageQueues,
         sdl: Arc<String>,
     ) -> supergraph::BoxService
     where
@@ -76,23 +123,41 @@ impl SupergraphStage {
             + 'static,
         <C as tower::Service<http::Request<Body>>>::Future: Send + 'static,
     {
+        let service_span = external_service_span(coprocessor_name.clone());
         let request_layer = (self.request != Default::default()).then_some({
             let request_config = self.request.clone();
             let coprocessor_url = coprocessor_url.clone();
-            let http_client = http_client.clone();
+            let coprocessor_name = coprocessor_name.clone();
+            let http_client = StageClient::new(
+                http_client.clone(),
+                PipelineStep::SupergraphRequest,
+                request_config.on_error.clone(),
+                queues.request.as_ref(),
+                &coprocessor_name,
+            );
             let sdl = sdl.clone();
 
             OneShotAsyncCheckpointLayer::new(move |request: supergraph::Request| {
                 let request_config = request_config.clone();
                 let coprocessor_url = coprocessor_url.clone();
+                let coprocessor_name = coprocessor_name.clone();
                 let http_client = http_client.clone();
                 let sdl = sdl.clone();
 
                 async move {
+                    if !request_config
+                        .condition
+                        .as_ref()
+                        .map_or(true, |condition| condition.evaluate_request(&request))
+                    {
+                        return Ok(ControlFlow::Continue(request));
+                    }
+
                     let mut succeeded = true;
                     let result = process_supergraph_request_stage(
                         http_client,
                         coprocessor_url,
+                        coprocessor_name.clone(),
                         sdl,
                         request,
                         request_config,
@@ -107,6 +172,7 @@ impl SupergraphStage {
                     });
                     tracing::info!(
                         monotonic_counter.apollo.router.operations.coprocessor = 1u64,
+                        coprocessor.name = coprocessor_name.as_str(),
                         coprocessor.stage = %PipelineStep::SupergraphRequest,
                         coprocessor.succeeded = succeeded,
                         "Total operations with co-processors enabled"
@@ -118,20 +184,36 @@ impl SupergraphStage {
 
         let response_layer = (self.response != Default::default()).then_some({
             let response_config = self.response.clone();
+            let http_client = StageClient::new(
+                http_client,
+                PipelineStep::SupergraphResponse,
+                response_config.on_error.clone(),
+                queues.response.as_ref(),
+                &coprocessor_name,
+            );
 
             MapFutureLayer::new(move |fut| {
                 let coprocessor_url = coprocessor_url.clone();
+                let coprocessor_name = coprocessor_name.clone();
                 let sdl: Arc<String> = sdl.clone();
                 let http_client = http_client.clone();
                 let response_config = response_config.clone();
 
                 async move {
                     let response: supergraph::Response = fut.await?;
+                    if !response_config
+                        .condition
+                        .as_ref()
+                        .map_or(true, |condition| condition.evaluate_response(&response))
+                    {
+                        return Ok(response);
+                    }
 
                     let mut succeeded = true;
                     let result = process_supergraph_response_stage(
                         http_client,
                         coprocessor_url,
+                        coprocessor_name.clone(),
                         sdl,
                         response,
                         response_config,
@@ -146,6 +228,7 @@ impl SupergraphStage {
                     });
                     tracing::info!(
                         monotonic_counter.apollo.router.operations.coprocessor = 1u64,
+                        coprocessor.name = coprocessor_name.as_str(),
                         coprocessor.stage = %PipelineStep::SupergraphResponse,
                         coprocessor.succeeded = succeeded,
                         "Total operations with co-processors enabled"
@@ -155,18 +238,21 @@ impl SupergraphStage {
             })
         });
 
-        fn external_service_span() -> impl Fn(&supergraph::Request) -> tracing::Span + Clone {
+        fn external_service_span(
+            coprocessor_name: String,
+        ) -> impl Fn(&supergraph::Request) -> tracing::Span + Clone {
             move |_request: &supergraph::Request| {
                 tracing::info_span!(
                     EXTERNAL_SPAN_NAME,
                     "external service" = stringify!(supergraph::Request),
-                    "otel.kind" = "INTERNAL"
+                    "otel.kind" = "INTERNAL",
+                    "coprocessor.name" = coprocessor_name.as_str()
                 )
             }
         }
 
         ServiceBuilder::new()
-            .instrument(external_service_span())
+            .instrument(service_span)
             .option_layer(request_layer)
             .option_layer(response_layer)
             .service(service)
@@ -177,6 +263,7 @@ impl SupergraphStage {
 async fn process_supergraph_request_stage<C>(
     http_client: C,
     coprocessor_url: String,
+    coprocessor_name: String,
     sdl: Arc<String>,
     mut request: supergraph::Request,
     request_config: SupergraphRequestConf,
@@ -227,6 +314,7 @@ where
     drop(guard);
     tracing::info!(
         histogram.apollo.router.operations.coprocessor.duration = duration,
+        coprocessor.name = coprocessor_name.as_str(),
         coprocessor.stage = %PipelineStep::SupergraphRequest,
     );
 
@@ -269,13 +357,11 @@ where
                 context: request.context,
             };
 
-            if let Some(context) = co_processor_output.context {
-                for (key, value) in context.try_into_iter()? {
-                    supergraph_response
-                        .context
-                        .upsert_json_value(key, move |_current| value);
-                }
-            }
+            update_context(
+                &supergraph_response.context,
+                co_processor_output.context,
+                co_processor_output.context_patch,
+            )?;
 
             supergraph_response
         };
@@ -293,13 +379,11 @@ where
 
     request.supergraph_request = http::Request::from_parts(parts, new_body);
 
-    if let Some(context) = co_processor_output.context {
-        for (key, value) in context.try_into_iter()? {
-            request
-                .context
-                .upsert_json_value(key, move |_current| value);
-        }
-    }
+    update_context(
+        &request.context,
+        co_processor_output.context,
+        co_processor_output.context_patch,
+    )?;
 
     if let Some(headers) = co_processor_output.headers {
         *request.supergraph_request.headers_mut() = internalize_header_map(headers)?;
@@ -315,6 +399,7 @@ where
 async fn process_supergraph_response_stage<C>(
     http_client: C,
     coprocessor_url: String,
+    coprocessor_name: String,
     sdl: Arc<String>,
     response: supergraph::Response,
     response_config: SupergraphResponseConf,
@@ -362,6 +447,7 @@ where
         .and_status_code(status_to_send)
         .and_sdl(sdl_to_send.clone())
         .and_has_next(first.has_next)
+        .sequence(0)
         .build();
 
     // Second, call our co-processor and get a reply.
@@ -373,6 +459,7 @@ where
     drop(guard);
     tracing::info!(
         histogram.apollo.router.operations.coprocessor.duration = duration,
+        coprocessor.name = coprocessor_name.as_str(),
         coprocessor.stage = %PipelineStep::SupergraphResponse,
     );
 
@@ -394,13 +481,11 @@ where
         parts.status = control.get_http_status()?
     }
 
-    if let Some(context) = co_processor_output.context {
-        for (key, value) in context.try_into_iter()? {
-            response
-                .context
-                .upsert_json_value(key, move |_current| value);
-        }
-    }
+    update_context(
+        &response.context,
+        co_processor_output.context,
+        co_processor_output.context_patch,
+    )?;
 
     if let Some(headers) = co_processor_output.headers {
         parts.headers = internalize_header_map(headers)?;
@@ -412,7 +497,8 @@ where
 
     // Map the rest of our body to process subsequent chunks of response
     let mapped_stream = rest
-        .then(move |deferred_response| {
+        .enumerate()
+        .then(move |(index, deferred_response)| {
             let generator_client = http_client.clone();
             let generator_coprocessor_url = coprocessor_url.clone();
             let generator_map_context = map_context.clone();
@@ -420,6 +506,10 @@ where
             let generator_id = map_context.id.clone();
 
             async move {
+                if !response_config.stream {
+                    return Ok(deferred_response);
+                }
+
                 let body_to_send = response_config.body.then(|| {
                     serde_json::to_value(&deferred_response).expect("serialization will not fail")
                 });
@@ -437,6 +527,7 @@ where
                     .and_context(context_to_send)
                     .and_sdl(generator_sdl_to_send)
                     .and_has_next(deferred_response.has_next)
+                    .sequence(index as u64 + 1)
                     .build();
 
                 // Second, call our co-processor and get a reply.
@@ -464,11 +555,11 @@ where
                         None => deferred_response,
                     };
 
-                if let Some(context) = co_processor_output.context {
-                    for (key, value) in context.try_into_iter()? {
-                        generator_map_context.upsert_json_value(key, move |_current| value);
-                    }
-                }
+                update_context(
+                    &generator_map_context,
+                    co_processor_output.context,
+                    co_processor_output.context_patch,
+                )?;
 
                 // We return the deferred_response into our stream of response chunks
                 Ok(new_deferred_response)
@@ -571,6 +662,9 @@ mod tests {
                 body: true,
                 sdl: false,
                 method: false,
+                condition: None,
+                is_async: false,
+                on_error: None,
             },
             response: Default::default(),
         };
@@ -674,6 +768,8 @@ mod tests {
             mock_http_client,
             mock_supergraph_service.boxed(),
             "http://test".to_string(),
+            "test".to_string(),
+            &Default::default(),
             Arc::new("".to_string()),
         );
 
@@ -704,6 +800,9 @@ mod tests {
                 body: true,
                 sdl: false,
                 method: false,
+                condition: None,
+                is_async: false,
+                on_error: None,
             },
             response: Default::default(),
         };
@@ -742,6 +841,8 @@ mod tests {
             mock_http_client,
             mock_supergraph_service.boxed(),
             "http://test".to_string(),
+            "test".to_string(),
+            &Default::default(),
             Arc::new("".to_string()),
         );
 
@@ -775,6 +876,10 @@ mod tests {
                 body: true,
                 sdl: true,
                 status_code: false,
+                stream: true,
+                condition: None,
+                is_async: false,
+                on_error: None,
             },
             request: Default::default(),
         };
@@ -867,6 +972,8 @@ mod tests {
             mock_http_client,
             mock_supergraph_service.boxed(),
             "http://test".to_string(),
+            "test".to_string(),
+            &Default::default(),
             Arc::new("".to_string()),
         );
 
@@ -906,6 +1013,10 @@ mod tests {
                 body: true,
                 sdl: true,
                 status_code: false,
+                stream: true,
+                condition: None,
+                is_async: false,
+                on_error: None,
             },
             request: Default::default(),
         };
@@ -950,8 +1061,10 @@ mod tests {
                     deserialized_response.stage
                 );
 
-                // Copy the has_next from the body into the data for checking later
-                deserialized_response
+                // Copy the has_next and the sequence from the body into the data for checking later
+                let has_next = deserialized_response.has_next.unwrap_or_default();
+                let sequence = deserialized_response.sequence.unwrap();
+                let data = deserialized_response
                     .body
                     .as_mut()
                     .unwrap()
@@ -960,11 +1073,9 @@ mod tests {
                     .get_mut("data")
                     .unwrap()
                     .as_object_mut()
-                    .unwrap()
-                    .insert(
-                        "has_next".to_string(),
-                        serde_json::Value::from(deserialized_response.has_next.unwrap_or_default()),
-                    );
+                    .unwrap();
+                data.insert("has_next".to_string(), serde_json::Value::from(has_next));
+                data.insert("sequence".to_string(), serde_json::Value::from(sequence));
 
                 Ok(hyper::Response::builder()
                     .body(Body::from(
@@ -978,6 +1089,8 @@ mod tests {
             mock_http_client,
             mock_supergraph_service.boxed(),
             "http://test".to_string(),
+            "test".to_string(),
+            &Default::default(),
             Arc::new("".to_string()),
         );
 
@@ -991,17 +1104,17 @@ mod tests {
         let body = res.response.body_mut().next().await.unwrap();
         assert_eq!(
             serde_json::to_value(&body).unwrap(),
-            json!({ "data": { "test": 1, "has_next": true }, "hasNext": true }),
+            json!({ "data": { "test": 1, "has_next": true, "sequence": 0 }, "hasNext": true }),
         );
         let body = res.response.body_mut().next().await.unwrap();
         assert_eq!(
             serde_json::to_value(&body).unwrap(),
-            json!({ "data": { "test": 2, "has_next": true }, "hasNext": true }),
+            json!({ "data": { "test": 2, "has_next": true, "sequence": 1 }, "hasNext": true }),
         );
         let body = res.response.body_mut().next().await.unwrap();
         assert_eq!(
             serde_json::to_value(&body).unwrap(),
-            json!({ "data": { "test": 3, "has_next": false }, "hasNext": false }),
+            json!({ "data": { "test": 3, "has_next": false, "sequence": 2 }, "hasNext": false }),
         );
     }
 }
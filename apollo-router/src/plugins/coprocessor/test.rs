@@ -1,6 +1,7 @@
 #[cfg(test)]
 mod tests {
     use std::collections::HashMap;
+    use std::str::FromStr;
     use std::sync::Arc;
 
     use futures::future::BoxFuture;
@@ -64,6 +65,147 @@ mod tests {
             .is_err());
     }
 
+    #[tokio::test]
+    async fn load_named_coprocessors() {
+        let config = json!({
+            "coprocessor": [
+                {
+                    "name": "auth",
+                    "url": "http://127.0.0.1:8081",
+                    "timeout": "100ms",
+                    "router": {
+                        "request": {
+                            "headers": true
+                        }
+                    }
+                },
+                {
+                    "name": "audit",
+                    "url": "http://127.0.0.1:8082",
+                    "supergraph": {
+                        "response": {
+                            "body": true
+                        }
+                    }
+                }
+            ]
+        });
+        let _test_harness = crate::TestHarness::builder()
+            .configuration_json(config)
+            .unwrap()
+            .build_router()
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn named_coprocessors_require_unique_names() {
+        let unnamed = json!({
+            "coprocessor": [
+                { "name": "auth", "url": "http://127.0.0.1:8081" },
+                { "url": "http://127.0.0.1:8082" }
+            ]
+        });
+        assert!(crate::TestHarness::builder()
+            .configuration_json(unnamed)
+            .unwrap()
+            .build_router()
+            .await
+            .is_err());
+
+        let duplicated = json!({
+            "coprocessor": [
+                { "name": "auth", "url": "http://127.0.0.1:8081" },
+                { "name": "auth", "url": "http://127.0.0.1:8082" }
+            ]
+        });
+        assert!(crate::TestHarness::builder()
+            .configuration_json(duplicated)
+            .unwrap()
+            .build_router()
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn configuration_errors_are_reported() {
+        let single = serde_json::from_value::<CoprocessorsConf>(json!({
+            "url": "http://127.0.0.1:8081",
+            "timout": "100ms"
+        }))
+        .unwrap_err();
+        assert!(
+            single.to_string().starts_with("unknown field `timout`"),
+            "{single}"
+        );
+
+        let chain = serde_json::from_value::<CoprocessorsConf>(json!([
+            { "name": "auth", "url": "http://127.0.0.1:8081" },
+            { "name": "audit", "url": "http://127.0.0.1:8082", "transport": "smtp" }
+        ]))
+        .unwrap_err();
+        assert!(
+            chain.to_string().starts_with("unknown variant `smtp`"),
+            "{chain}"
+        );
+
+        let url =
+            serde_json::from_value::<CoprocessorsConf>(json!("http://127.0.0.1:8081")).unwrap_err();
+        assert!(
+            url.to_string()
+                .contains("expected a coprocessor or a list of named coprocessors"),
+            "{url}"
+        );
+    }
+
+    #[test]
+    fn subgraph_unknown_fields_are_denied() {
+        for subgraph in [
+            json!({ "request": { "headers": true } }),
+            json!({ "all": { "request": { "headers": true }, "thisFieldDoesntExist": true } }),
+            json!({ "subgraphs": { "products": { "thisFieldDoesntExist": true } } }),
+        ] {
+            let config = serde_yaml::to_string(&json!({
+                "coprocessor": {
+                    "url": "http://127.0.0.1:8081",
+                    "subgraph": subgraph
+                }
+            }))
+            .unwrap();
+            assert!(crate::Configuration::from_str(&config).is_err(), "{config}");
+        }
+    }
+
+    #[test]
+    fn context_operations_are_applied_after_the_returned_context() {
+        let context = Context::new();
+        context.insert("session", "secret".to_string()).unwrap();
+        context.insert("user", "ada".to_string()).unwrap();
+
+        let output: Externalizable<serde_json::Value> = serde_json::from_value(json!({
+            "version": 1,
+            "stage": "RouterRequest",
+            "control": "continue",
+            "context": { "entries": { "user": "grace" } },
+            "contextPatch": [
+                { "op": "remove", "key": "session" },
+                { "op": "add", "key": "role", "value": "admin" }
+            ]
+        }))
+        .unwrap();
+        update_context(&context, output.context, output.context_patch).unwrap();
+
+        assert!(!context.contains_key("session"));
+        assert_eq!(
+            context.get::<_, String>("user").unwrap(),
+            Some("grace".to_string())
+        );
+        assert_eq!(
+            context.get::<_, String>("role").unwrap(),
+            Some("admin".to_string())
+        );
+    }
+
     #[tokio::test]
     async fn external_plugin_with_stages_wont_load_without_graph_ref() {
         let config = json!({
@@ -99,6 +241,9 @@ mod tests {
                 sdl: true,
                 path: false,
                 method: false,
+                condition: None,
+                is_async: false,
+                on_error: None,
             },
             response: Default::default(),
         };
@@ -133,6 +278,8 @@ mod tests {
             mock_http_client,
             mock_router_service.boxed(),
             "http://test".to_string(),
+            "test".to_string(),
+            &Default::default(),
             Arc::new("".to_string()),
         );
 
@@ -158,6 +305,9 @@ mod tests {
                 sdl: true,
                 path: false,
                 method: false,
+                condition: None,
+                is_async: false,
+                on_error: None,
             },
             response: Default::default(),
         };
@@ -192,6 +342,8 @@ mod tests {
             mock_http_client,
             mock_router_service.boxed(),
             "http://test".to_string(),
+            "test".to_string(),
+            &Default::default(),
             Arc::new("".to_string()),
         );
 
@@ -217,6 +369,9 @@ mod tests {
                 sdl: true,
                 path: false,
                 method: false,
+                condition: None,
+                is_async: false,
+                on_error: None,
             },
             response: Default::default(),
         };
@@ -250,6 +405,8 @@ mod tests {
             mock_http_client,
             mock_router_service.boxed(),
             "http://test".to_string(),
+            "test".to_string(),
+            &Default::default(),
             Arc::new("".to_string()),
         );
 
@@ -275,6 +432,9 @@ mod tests {
                 uri: false,
                 method: false,
                 service_name: false,
+                condition: None,
+                is_async: false,
+                on_error: None,
             },
             response: Default::default(),
         };
@@ -308,6 +468,8 @@ mod tests {
             mock_http_client,
             mock_subgraph_service.boxed(),
             "http://test".to_string(),
+            "test".to_string(),
+            &Default::default(),
             "my_subgraph_service_name".to_string(),
         );
 
@@ -337,6 +499,9 @@ mod tests {
                 uri: false,
                 method: false,
                 service_name: false,
+                condition: None,
+                is_async: false,
+                on_error: None,
             },
             response: Default::default(),
         };
@@ -438,6 +603,8 @@ mod tests {
             mock_http_client,
             mock_subgraph_service.boxed(),
             "http://test".to_string(),
+            "test".to_string(),
+            &Default::default(),
             "my_subgraph_service_name".to_string(),
         );
 
@@ -466,6 +633,9 @@ mod tests {
                 uri: false,
                 method: false,
                 service_name: false,
+                condition: None,
+                is_async: false,
+                on_error: None,
             },
             response: Default::default(),
         };
@@ -504,6 +674,8 @@ mod tests {
             mock_http_client,
             mock_subgraph_service.boxed(),
             "http://test".to_string(),
+            "test".to_string(),
+            &Default::default(),
             "my_subgraph_service_name".to_string(),
         );
 
@@ -534,6 +706,9 @@ mod tests {
                 uri: false,
                 method: false,
                 service_name: false,
+                condition: None,
+                is_async: false,
+                on_error: None,
             },
             response: Default::default(),
         };
@@ -562,6 +737,8 @@ mod tests {
             mock_http_client,
             mock_subgraph_service.boxed(),
             "http://test".to_string(),
+            "test".to_string(),
+            &Default::default(),
             "my_subgraph_service_name".to_string(),
         );
 
@@ -597,6 +774,9 @@ mod tests {
                 body: true,
                 service_name: false,
                 status_code: false,
+                condition: None,
+                is_async: false,
+                on_error: None,
             },
         };
 
@@ -670,6 +850,8 @@ mod tests {
             mock_http_client,
             mock_subgraph_service.boxed(),
             "http://test".to_string(),
+            "test".to_string(),
+            &Default::default(),
             "my_subgraph_service_name".to_string(),
         );
 
@@ -708,6 +890,9 @@ mod tests {
                 sdl: true,
                 path: true,
                 method: true,
+                condition: None,
+                is_async: false,
+                on_error: None,
             },
             response: Default::default(),
         };
@@ -808,6 +993,8 @@ mod tests {
             mock_http_client,
             mock_router_service.boxed(),
             "http://test".to_string(),
+            "test".to_string(),
+            &Default::default(),
             Arc::new("".to_string()),
         );
 
@@ -816,6 +1003,49 @@ mod tests {
         service.oneshot(request.try_into().unwrap()).await.unwrap();
     }
 
+    #[tokio::test]
+    async fn external_plugin_router_request_condition() {
+        let router_stage = RouterStage {
+            request: serde_json::from_value(json!({
+                "headers": true,
+                "condition": {
+                    "exists": {
+                        "request_header": "x-partner-id"
+                    }
+                }
+            }))
+            .unwrap(),
+            response: Default::default(),
+        };
+
+        let mock_router_service = router::service::from_supergraph_mock_callback(move |req| {
+            Ok(supergraph::Response::builder()
+                .data(json!({ "test": 1234_u32 }))
+                .context(req.context)
+                .build()
+                .unwrap())
+        })
+        .await;
+
+        let mock_http_client = mock_with_callback(move |_: hyper::Request<Body>| {
+            panic!("the coprocessor should only be called when the x-partner-id header is present")
+        });
+
+        let service = router_stage.as_service(
+            mock_http_client,
+            mock_router_service.boxed(),
+            "http://test".to_string(),
+            "test".to_string(),
+            &Default::default(),
+            Arc::new("".to_string()),
+        );
+
+        let request = supergraph::Request::canned_builder().build().unwrap();
+
+        let response = service.oneshot(request.try_into().unwrap()).await.unwrap();
+        assert_eq!(response.response.status(), StatusCode::OK);
+    }
+
     #[tokio::test]
     async fn external_plugin_router_request_http_get() {
         let router_stage = RouterStage {
@@ -826,6 +1056,9 @@ mod tests {
                 sdl: true,
                 path: true,
                 method: true,
+                condition: None,
+                is_async: false,
+                on_error: None,
             },
             response: Default::default(),
         };
@@ -933,6 +1166,8 @@ mod tests {
             mock_http_client,
             mock_router_service.boxed(),
             "http://test".to_string(),
+            "test".to_string(),
+            &Default::default(),
             Arc::new("".to_string()),
         );
 
@@ -954,6 +1189,9 @@ mod tests {
                 sdl: true,
                 path: true,
                 method: true,
+                condition: None,
+                is_async: false,
+                on_error: None,
             },
             response: Default::default(),
         };
@@ -1003,6 +1241,8 @@ mod tests {
             mock_http_client,
             mock_router_service.boxed(),
             "http://test".to_string(),
+            "test".to_string(),
+            &Default::default(),
             Arc::new("".to_string()),
         );
 
@@ -1042,6 +1282,9 @@ mod tests {
                 sdl: true,
                 path: true,
                 method: true,
+                condition: None,
+                is_async: false,
+                on_error: None,
             },
             response: Default::default(),
         };
@@ -1081,6 +1324,8 @@ mod tests {
             mock_http_client,
             mock_router_service.boxed(),
             "http://test".to_string(),
+            "test".to_string(),
+            &Default::default(),
             Arc::new("".to_string()),
         );
 
@@ -1120,6 +1365,9 @@ mod tests {
                 body: true,
                 sdl: true,
                 status_code: false,
+                condition: None,
+                is_async: false,
+                on_error: None,
             },
             request: Default::default(),
         };
@@ -1207,6 +1455,8 @@ mod tests {
             mock_http_client,
             mock_router_service.boxed(),
             "http://test".to_string(),
+            "test".to_string(),
+            &Default::default(),
             Arc::new("".to_string()),
         );
 
@@ -1241,6 +1491,45 @@ mod tests {
         );
     }
 
+    #[test]
+    fn subgraph_stage_overrides() {
+        let conf: Conf = serde_json::from_value(json!({
+            "url": "http://127.0.0.1:8081",
+            "subgraph": {
+                "all": {
+                    "response": {
+                        "status_code": true
+                    }
+                },
+                "subgraphs": {
+                    "payments": {
+                        "request": {
+                            "headers": true,
+                            "body": true
+                        }
+                    },
+                    "products": {
+                        "response": {}
+                    }
+                }
+            }
+        }))
+        .unwrap();
+
+        let all = conf.subgraph.get("accounts");
+        assert_eq!(all.request, SubgraphRequestConf::default());
+        assert!(all.response.status_code);
+
+        let payments = conf.subgraph.get("payments");
+        assert!(payments.request.headers);
+        assert!(payments.request.body);
+        assert!(!payments.request.uri);
+        assert!(payments.response.status_code);
+
+        let products = conf.subgraph.get("products");
+        assert_eq!(products, &SubgraphStage::default());
+    }
+
     #[test]
     fn it_externalizes_headers() {
         // Build our expected HashMap
@@ -1308,6 +1597,69 @@ mod tests {
         assert_eq!(expected, actual);
     }
 
+    #[tokio::test]
+    async fn asynchronous_subgraph_stages_share_their_queue() {
+        let configuration: Conf = serde_json::from_value(json!({
+            "url": "http://test",
+            // long enough for the payloads of the same queue to be sent in one batch
+            "async_queue": { "batch_timeout": "10s" },
+            "subgraph": {
+                "all": { "request": { "service_name": true, "async": true } },
+                "subgraphs": {
+                    "b": { "request": { "service_name": true, "async": true } }
+                }
+            }
+        }))
+        .unwrap();
+
+        let (batches, mut received) = tokio::sync::mpsc::unbounded_channel();
+        let http_client = tower::service_fn(move |request: hyper::Request<Body>| {
+            let batches = batches.clone();
+            async move {
+                let body = hyper::body::to_bytes(request.into_body()).await?;
+                let batch: Vec<serde_json::Value> = serde_json::from_slice(&body)?;
+                batches.send(
+                    batch
+                        .iter()
+                        .map(|payload| payload["serviceName"].as_str().unwrap().to_string())
+                        .collect::<Vec<_>>(),
+                )?;
+                Ok::<_, BoxError>(hyper::Response::new(Body::empty()))
+            }
+        });
+        let plugin =
+            CoprocessorPlugin::new(http_client, configuration, Arc::new(String::new())).unwrap();
+
+        // the services are created for each fetch, the queues with the plugin
+        for name in ["a", "b", "c"] {
+            let mut mock_subgraph_service = MockSubgraphService::new();
+            mock_subgraph_service
+                .expect_call()
+                .returning(|_| Ok(subgraph::Response::fake_builder().build()));
+            plugin
+                .subgraph_service(name, mock_subgraph_service.boxed())
+                .oneshot(subgraph::Request::fake_builder().build())
+                .await
+                .unwrap();
+        }
+        // the queues send their last batch when they are dropped
+        drop(plugin);
+
+        let mut sent = vec![
+            received.recv().await.unwrap(),
+            received.recv().await.unwrap(),
+        ];
+        sent.sort();
+        assert_eq!(
+            sent,
+            vec![
+                vec!["a".to_string(), "c".to_string()],
+                vec!["b".to_string()]
+            ]
+        );
+        assert!(received.recv().await.is_none());
+    }
+
     #[allow(clippy::type_complexity)]
     fn mock_with_callback(
         callback: fn(
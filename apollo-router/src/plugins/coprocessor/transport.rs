@@ -0,0 +1,743 @@
+//! Transports used to call coprocessors
+//!
+//! Stages always build a JSON over HTTP request. The transport decides how it reaches the coprocessor: over TCP,
+//! over a Unix domain socket, or with gRPC. The gRPC transport doesn't read the JSON body: it builds the protobuf
+//! messages of `proto/coprocessor.proto` from the stage payload added to the request extensions.
+
+use std::sync::Arc;
+use std::task::Poll;
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use http::uri::PathAndQuery;
+use http::Method;
+use hyper::client::HttpConnector;
+use hyper::Body;
+use hyper_rustls::ConfigBuilderExt;
+use hyper_rustls::HttpsConnector;
+use prost_types::value::Kind;
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json_bytes::Value as JsonValue;
+use tonic::codec::Codec;
+use tonic::codec::DecodeBuf;
+use tonic::codec::Decoder;
+use tonic::codec::EncodeBuf;
+use tonic::codec::Encoder;
+use tonic::metadata::MetadataMap;
+use tonic::transport::Channel;
+use tonic::transport::Endpoint;
+use tonic::Status;
+use tower::timeout::Timeout;
+use tower::timeout::TimeoutLayer;
+use tower::BoxError;
+use tower::Service;
+use tower::ServiceBuilder;
+
+use crate::configuration::TlsClient;
+use crate::services::external::Control;
+use crate::services::external::Externalizable;
+use crate::services::subgraph_service::generate_tls_client_config;
+use crate::services::trust_dns_connector::new_async_http_connector;
+use crate::services::trust_dns_connector::AsyncHyperResolver;
+use crate::Context;
+
+const POOL_IDLE_TIMEOUT_DURATION: Option<Duration> = Some(Duration::from_secs(5));
+const UNIX_SCHEME: &str = "unix://";
+/// Requests sent over a Unix domain socket still need an absolute URI
+const UNIX_REQUEST_URL: &str = "http://localhost/";
+const GRPC_PROCESS_PATH: &str = "/coprocessor.Coprocessor/Process";
+/// Largest integer represented exactly by the numbers of protobuf values
+const MAX_SAFE_INTEGER: f64 = 9_007_199_254_740_992.0;
+
+#[allow(unreachable_pub)]
+pub(crate) mod proto {
+    #![allow(clippy::derive_partial_eq_without_eq)]
+    tonic::include_proto!("coprocessor");
+}
+
+pub(super) type HTTPClientService =
+    Timeout<hyper::Client<HttpsConnector<HttpConnector<AsyncHyperResolver>>, Body>>;
+
+#[cfg(unix)]
+type UnixClientService = Timeout<hyper::Client<unix::UnixConnector, Body>>;
+
+/// How requests are sent to the coprocessor
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub(super) enum TransportKind {
+    /// JSON over HTTP, `unix://` URLs are called over a Unix domain socket
+    #[default]
+    Http,
+    /// Protobuf over gRPC, using the `Coprocessor` service
+    Grpc,
+}
+
+/// Client for the configured coprocessor transport
+#[derive(Clone)]
+pub(super) enum Transport {
+    Http(HTTPClientService),
+    #[cfg(unix)]
+    Unix(UnixClientService),
+    Grpc(Channel),
+}
+
+impl Transport {
+    pub(super) fn new(
+        url: &str,
+        kind: TransportKind,
+        timeout: Duration,
+        tls: Option<&TlsClient>,
+    ) -> Result<Self, BoxError> {
+        match kind {
+            TransportKind::Http => match url.strip_prefix(UNIX_SCHEME) {
+                Some(path) => Self::new_unix(path, timeout),
+                None => Ok(Transport::Http(
+                    ServiceBuilder::new()
+                        .layer(TimeoutLayer::new(timeout))
+                        .service(
+                            hyper::Client::builder()
+                                .pool_idle_timeout(POOL_IDLE_TIMEOUT_DURATION)
+                                .build(https_connector(tls, true)?),
+                        ),
+                )),
+            },
+            TransportKind::Grpc => {
+                if url.starts_with(UNIX_SCHEME) {
+                    return Err(
+                        "the gRPC coprocessor transport does not support unix:// URLs".into(),
+                    );
+                }
+                let endpoint = Endpoint::from_shared(url.to_string())?.timeout(timeout);
+                Ok(Transport::Grpc(
+                    endpoint.connect_with_connector_lazy(https_connector(tls, false)?),
+                ))
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    fn new_unix(path: &str, timeout: Duration) -> Result<Self, BoxError> {
+        Ok(Transport::Unix(
+            ServiceBuilder::new()
+                .layer(TimeoutLayer::new(timeout))
+                .service(
+                    hyper::Client::builder()
+                        .pool_idle_timeout(POOL_IDLE_TIMEOUT_DURATION)
+                        .build(unix::UnixConnector::new(path)),
+                ),
+        ))
+    }
+
+    #[cfg(not(unix))]
+    fn new_unix(_path: &str, _timeout: Duration) -> Result<Self, BoxError> {
+        Err("unix:// coprocessor URLs are only supported on Unix platforms".into())
+    }
+
+    /// URL of the HTTP requests built by the stages for this transport
+    pub(super) fn request_url(&self, url: &str) -> String {
+        match self {
+            #[cfg(unix)]
+            Transport::Unix(_) => UNIX_REQUEST_URL.to_string(),
+            _ => url.to_string(),
+        }
+    }
+}
+
+fn https_connector(
+    tls: Option<&TlsClient>,
+    enable_http1: bool,
+) -> Result<HttpsConnector<HttpConnector<AsyncHyperResolver>>, BoxError> {
+    let mut http_connector = new_async_http_connector()?;
+    http_connector.set_nodelay(true);
+    http_connector.set_keepalive(Some(std::time::Duration::from_secs(60)));
+    http_connector.enforce_http(false);
+
+    let tls_config = match tls {
+        Some(tls) => generate_tls_client_config(
+            tls.create_certificate_store().transpose()?,
+            tls.client_authentication.as_ref(),
+        )?,
+        None => rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_native_roots()
+            .with_no_client_auth(),
+    };
+
+    let builder = hyper_rustls::HttpsConnectorBuilder::new()
+        .with_tls_config(tls_config)
+        .https_or_http();
+    // gRPC requires HTTP/2, negotiated with ALPN over TLS
+    Ok(if enable_http1 {
+        builder
+            .enable_http1()
+            .enable_http2()
+            .wrap_connector(http_connector)
+    } else {
+        builder.enable_http2().wrap_connector(http_connector)
+    })
+}
+
+impl Service<hyper::Request<Body>> for Transport {
+    type Response = hyper::Response<Body>;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        match self {
+            Transport::Http(client) => client.poll_ready(cx),
+            #[cfg(unix)]
+            Transport::Unix(client) => client.poll_ready(cx),
+            // the channel is driven by the gRPC client in `call`
+            Transport::Grpc(_) => Poll::Ready(Ok(())),
+        }
+    }
+
+    fn call(&mut self, request: hyper::Request<Body>) -> Self::Future {
+        match self {
+            Transport::Http(client) => Box::pin(client.call(request)),
+            #[cfg(unix)]
+            Transport::Unix(client) => Box::pin(client.call(request)),
+            Transport::Grpc(channel) => Box::pin(call_grpc(channel.clone(), request)),
+        }
+    }
+}
+
+/// Data of a stage payload, the gRPC transport builds its messages from it
+///
+/// It is added to the extensions of the JSON requests built by the stages.
+#[derive(Clone)]
+pub(crate) struct StagePayload {
+    payload: Arc<dyn ToProto + Send + Sync>,
+    /// Overrides the version of the payload, once the protocol version is negotiated
+    version: Option<u8>,
+}
+
+impl StagePayload {
+    pub(crate) fn new<T>(payload: Externalizable<T>) -> Self
+    where
+        T: Serialize + Send + Sync + 'static,
+    {
+        Self {
+            payload: Arc::new(payload),
+            version: None,
+        }
+    }
+
+    pub(super) fn set_version(&mut self, version: u8) {
+        self.version = Some(version);
+    }
+
+    fn to_proto(&self) -> Result<proto::Externalizable, BoxError> {
+        let mut message = self.payload.to_proto()?;
+        if let Some(version) = self.version {
+            message.version = version.into();
+        }
+        Ok(message)
+    }
+}
+
+/// Data of the payloads of a batch sent by an asynchronous stage
+pub(super) struct StageBatch(pub(super) Vec<StagePayload>);
+
+trait ToProto {
+    fn to_proto(&self) -> Result<proto::Externalizable, BoxError>;
+}
+
+impl<T> ToProto for Externalizable<T>
+where
+    T: Serialize,
+{
+    fn to_proto(&self) -> Result<proto::Externalizable, BoxError> {
+        Ok(proto::Externalizable {
+            version: self.version.into(),
+            stage: self.stage.clone(),
+            control: self.control.as_ref().map(|control| proto::Control {
+                kind: Some(match control {
+                    Control::Continue => proto::control::Kind::Continue(true),
+                    Control::Break(status) => proto::control::Kind::Break((*status).into()),
+                }),
+            }),
+            id: self.id.clone(),
+            headers: self.headers.as_ref().map(|headers| proto::Headers {
+                entries: headers
+                    .iter()
+                    .map(|(name, values)| {
+                        (
+                            name.clone(),
+                            proto::HeaderValues {
+                                values: values.clone(),
+                            },
+                        )
+                    })
+                    .collect(),
+            }),
+            body: to_json(&self.body)?,
+            context: self.context.as_ref().map(|context| proto::Context {
+                entries: context
+                    .iter()
+                    .map(|entry| (entry.key().clone(), to_proto_value(entry.value())))
+                    .collect(),
+            }),
+            sdl: self.sdl.clone(),
+            uri: self.uri.clone(),
+            method: self
+                .method
+                .as_deref()
+                .map(|method| {
+                    METHODS
+                        .iter()
+                        .find(|(_, name)| name == method)
+                        .map(|(method, _)| *method as i32)
+                        .ok_or_else(|| {
+                            format!("the gRPC coprocessor transport does not support the {method} method")
+                        })
+                })
+                .transpose()?,
+            path: self.path.clone(),
+            service_name: self.service_name.clone(),
+            status_code: self.status_code.map(u32::from),
+            has_next: self.has_next,
+            query_plan: to_json(&self.query_plan)?,
+            override_labels: self.override_labels.clone().unwrap_or_default(),
+            sequence: self.sequence,
+            body_patch: to_json(&self.body_patch)?,
+            headers_patch: to_json(&self.headers_patch)?,
+            metrics: to_json(&self.metrics)?,
+            context_patch: to_json(&self.context_patch)?,
+        })
+    }
+}
+
+const METHODS: [(proto::Method, Method); 9] = [
+    (proto::Method::Get, Method::GET),
+    (proto::Method::Head, Method::HEAD),
+    (proto::Method::Post, Method::POST),
+    (proto::Method::Put, Method::PUT),
+    (proto::Method::Delete, Method::DELETE),
+    (proto::Method::Connect, Method::CONNECT),
+    (proto::Method::Options, Method::OPTIONS),
+    (proto::Method::Trace, Method::TRACE),
+    (proto::Method::Patch, Method::PATCH),
+];
+
+fn to_json<V: Serialize>(value: &Option<V>) -> Result<Option<String>, BoxError> {
+    Ok(value.as_ref().map(serde_json::to_string).transpose()?)
+}
+
+fn from_json<V: DeserializeOwned>(value: Option<String>) -> Result<Option<V>, BoxError> {
+    Ok(value
+        .map(|value| serde_json::from_str(&value))
+        .transpose()?)
+}
+
+fn to_proto_value(value: &JsonValue) -> prost_types::Value {
+    let kind = match value {
+        JsonValue::Null => Kind::NullValue(prost_types::NullValue::NullValue as i32),
+        JsonValue::Bool(value) => Kind::BoolValue(*value),
+        JsonValue::Number(value) => Kind::NumberValue(value.as_f64().unwrap_or_default()),
+        JsonValue::String(value) => Kind::StringValue(value.as_str().to_string()),
+        JsonValue::Array(values) => Kind::ListValue(prost_types::ListValue {
+            values: values.iter().map(to_proto_value).collect(),
+        }),
+        JsonValue::Object(fields) => Kind::StructValue(prost_types::Struct {
+            fields: fields
+                .iter()
+                .map(|(name, value)| (name.as_str().to_string(), to_proto_value(value)))
+                .collect(),
+        }),
+    };
+    prost_types::Value { kind: Some(kind) }
+}
+
+fn from_proto_value(value: prost_types::Value) -> JsonValue {
+    match value.kind {
+        None | Some(Kind::NullValue(_)) => JsonValue::Null,
+        Some(Kind::BoolValue(value)) => value.into(),
+        // numbers without a fractional part are sent as integers by most JSON encoders
+        Some(Kind::NumberValue(value))
+            if value.fract() == 0.0 && value.abs() < MAX_SAFE_INTEGER =>
+        {
+            (value as i64).into()
+        }
+        Some(Kind::NumberValue(value)) => serde_json::Number::from_f64(value)
+            .map(JsonValue::Number)
+            .unwrap_or_default(),
+        Some(Kind::StringValue(value)) => value.into(),
+        Some(Kind::ListValue(list)) => {
+            JsonValue::Array(list.values.into_iter().map(from_proto_value).collect())
+        }
+        Some(Kind::StructValue(fields)) => JsonValue::Object(
+            fields
+                .fields
+                .into_iter()
+                .map(|(name, value)| (name.into(), from_proto_value(value)))
+                .collect(),
+        ),
+    }
+}
+
+async fn call_grpc(
+    channel: Channel,
+    request: hyper::Request<Body>,
+) -> Result<hyper::Response<Body>, BoxError> {
+    let (
+        http::request::Parts {
+            headers,
+            mut extensions,
+            ..
+        },
+        _,
+    ) = request.into_parts();
+    // keeps the trace propagation headers, reserved gRPC headers are removed by tonic
+    let metadata = MetadataMap::from_headers(headers);
+    let mut client = tonic::client::Grpc::new(channel);
+
+    // batches of asynchronous stages are sent with one call per payload
+    let response = match extensions.remove::<StageBatch>() {
+        Some(StageBatch(payloads)) => {
+            let mut responses = Vec::with_capacity(payloads.len());
+            for payload in payloads {
+                responses.push(process_grpc(&mut client, &payload, metadata.clone()).await?);
+            }
+            serde_json::to_vec(&responses)?
+        }
+        None => {
+            let payload = extensions
+                .remove::<StagePayload>()
+                .ok_or("the gRPC coprocessor transport only sends the payloads of stages")?;
+            serde_json::to_vec(&process_grpc(&mut client, &payload, metadata).await?)?
+        }
+    };
+
+    Ok(hyper::Response::new(Body::from(response)))
+}
+
+async fn process_grpc(
+    client: &mut tonic::client::Grpc<Channel>,
+    payload: &StagePayload,
+    metadata: MetadataMap,
+) -> Result<Externalizable<serde_json::Value>, BoxError> {
+    let mut grpc_request = tonic::Request::new(payload.to_proto()?);
+    *grpc_request.metadata_mut() = metadata;
+
+    client.ready().await?;
+    let response = client
+        .unary(
+            grpc_request,
+            PathAndQuery::from_static(GRPC_PROCESS_PATH),
+            ExternalizableCodec,
+        )
+        .await?;
+
+    from_proto(response.into_inner())
+}
+
+fn from_proto(
+    message: proto::Externalizable,
+) -> Result<Externalizable<serde_json::Value>, BoxError> {
+    let control = match message.control.and_then(|control| control.kind) {
+        Some(proto::control::Kind::Continue(_)) => Some(Control::Continue),
+        Some(proto::control::Kind::Break(status)) => Some(Control::Break(u16::try_from(status)?)),
+        None => None,
+    };
+    let method = message
+        .method
+        .map(|method| {
+            let method = proto::Method::try_from(method)?;
+            METHODS
+                .iter()
+                .find(|(known, _)| *known == method)
+                .map(|(_, name)| name.to_string())
+                .ok_or_else(|| BoxError::from("the coprocessor returned an unspecified method"))
+        })
+        .transpose()?;
+
+    Ok(Externalizable {
+        version: u8::try_from(message.version)?,
+        stage: message.stage,
+        control,
+        id: message.id,
+        headers: message.headers.map(|headers| {
+            headers
+                .entries
+                .into_iter()
+                .map(|(name, values)| (name, values.values))
+                .collect()
+        }),
+        body: from_json(message.body)?,
+        context: message.context.map(|entries| {
+            let context = Context::new();
+            for (key, value) in entries.entries {
+                context.insert_json_value(key, from_proto_value(value));
+            }
+            context
+        }),
+        sdl: message.sdl,
+        uri: message.uri,
+        method,
+        path: message.path,
+        service_name: message.service_name,
+        status_code: message.status_code.map(u16::try_from).transpose()?,
+        has_next: message.has_next,
+        // the sequence number and the query plan are read only
+        sequence: None,
+        query_plan: None,
+        override_labels: (!message.override_labels.is_empty()).then_some(message.override_labels),
+        body_patch: from_json(message.body_patch)?,
+        headers_patch: from_json(message.headers_patch)?,
+        context_patch: from_json(message.context_patch)?,
+        metrics: from_json(message.metrics)?,
+    })
+}
+
+/// Protobuf codec using the router's prost version
+struct ExternalizableCodec;
+
+impl Codec for ExternalizableCodec {
+    type Encode = proto::Externalizable;
+    type Decode = proto::Externalizable;
+    type Encoder = ExternalizableCodec;
+    type Decoder = ExternalizableCodec;
+
+    fn encoder(&mut self) -> Self::Encoder {
+        ExternalizableCodec
+    }
+
+    fn decoder(&mut self) -> Self::Decoder {
+        ExternalizableCodec
+    }
+}
+
+impl Encoder for ExternalizableCodec {
+    type Item = proto::Externalizable;
+    type Error = Status;
+
+    fn encode(&mut self, item: Self::Item, dst: &mut EncodeBuf<'_>) -> Result<(), Self::Error> {
+        prost::Message::encode(&item, dst).map_err(|error| Status::internal(error.to_string()))
+    }
+}
+
+impl Decoder for ExternalizableCodec {
+    type Item = proto::Externalizable;
+    type Error = Status;
+
+    fn decode(&mut self, src: &mut DecodeBuf<'_>) -> Result<Option<Self::Item>, Self::Error> {
+        <proto::Externalizable as prost::Message>::decode(src)
+            .map(Some)
+            .map_err(|error| Status::internal(error.to_string()))
+    }
+}
+
+#[cfg(unix)]
+mod unix {
+    use std::io;
+    use std::path::PathBuf;
+    use std::pin::Pin;
+    use std::sync::Arc;
+    use std::task::Context;
+    use std::task::Poll;
+
+    use futures::future::BoxFuture;
+    use http::Uri;
+    use hyper::client::connect::Connected;
+    use hyper::client::connect::Connection;
+    use tokio::io::AsyncRead;
+    use tokio::io::AsyncWrite;
+    use tokio::io::ReadBuf;
+    use tokio::net::UnixStream;
+    use tower::Service;
+
+    /// Connects to a Unix domain socket whatever the request URI
+    #[derive(Clone)]
+    pub(crate) struct UnixConnector {
+        path: Arc<PathBuf>,
+    }
+
+    impl UnixConnector {
+        pub(crate) fn new(path: &str) -> Self {
+            Self {
+                path: Arc::new(PathBuf::from(path)),
+            }
+        }
+    }
+
+    impl Service<Uri> for UnixConnector {
+        type Response = UnixConnection;
+        type Error = io::Error;
+        type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _uri: Uri) -> Self::Future {
+            let path = self.path.clone();
+            Box::pin(async move {
+                UnixStream::connect(path.as_path())
+                    .await
+                    .map(UnixConnection)
+            })
+        }
+    }
+
+    pub(crate) struct UnixConnection(UnixStream);
+
+    impl Connection for UnixConnection {
+        fn connected(&self) -> Connected {
+            Connected::new()
+        }
+    }
+
+    impl AsyncRead for UnixConnection {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.0).poll_read(cx, buf)
+        }
+    }
+
+    impl AsyncWrite for UnixConnection {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            Pin::new(&mut self.0).poll_write(cx, buf)
+        }
+
+        fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.0).poll_flush(cx)
+        }
+
+        fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.0).poll_shutdown(cx)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::services::external::PipelineStep;
+    use crate::Context;
+
+    #[test]
+    fn protobuf_round_trip() {
+        let context = Context::new();
+        context.insert("key", 42).unwrap();
+        let payload = Externalizable::supergraph_builder()
+            .stage(PipelineStep::SupergraphResponse)
+            .control(Control::Break(403))
+            .id("id".to_string())
+            .headers(
+                [(
+                    "x-header".to_string(),
+                    vec!["a".to_string(), "b".to_string()],
+                )]
+                .into(),
+            )
+            .body(json!({ "data": { "me": { "name": "Ada" } } }))
+            .context(context)
+            .status_code(200)
+            .sdl("type Query { me: User }".to_string())
+            .has_next(true)
+            .build();
+
+        let round_trip =
+            from_proto(StagePayload::new(payload.clone()).to_proto().unwrap()).unwrap();
+        assert_eq!(
+            serde_json::to_value(&round_trip).unwrap(),
+            serde_json::to_value(&payload).unwrap()
+        );
+    }
+
+    #[test]
+    fn protobuf_fields_are_typed() {
+        let context = Context::new();
+        context
+            .insert("nested", json!({ "list": [1, 1.5, "a", null, true] }))
+            .unwrap();
+        let payload: Externalizable<serde_json::Value> = Externalizable::supergraph_builder()
+            .stage(PipelineStep::SupergraphResponse)
+            .id(String::new())
+            .context(context)
+            .method("POST".to_string())
+            .has_next(false)
+            .build();
+
+        let mut stage_payload = StagePayload::new(payload.clone());
+        stage_payload.set_version(2);
+        let message = stage_payload.to_proto().unwrap();
+        assert_eq!(message.version, 2);
+        assert_eq!(message.method, Some(proto::Method::Post as i32));
+        // empty and false values are not confused with absent values
+        assert_eq!(message.id, Some(String::new()));
+        assert_eq!(message.has_next, Some(false));
+        assert_eq!(message.status_code, None);
+        assert!(message.context.as_ref().unwrap().entries["nested"]
+            .kind
+            .is_some());
+
+        let round_trip = from_proto(message).unwrap();
+        assert_eq!(round_trip.version, 2);
+        assert_eq!(
+            serde_json::to_value(&round_trip.context).unwrap(),
+            serde_json::to_value(&payload.context).unwrap()
+        );
+        assert_eq!(round_trip.method.as_deref(), Some("POST"));
+        assert_eq!(round_trip.has_next, Some(false));
+        assert_eq!(round_trip.status_code, None);
+
+        let unsupported = Externalizable::<serde_json::Value>::router_builder()
+            .stage(PipelineStep::RouterRequest)
+            .id("id".to_string())
+            .method("PROPFIND".to_string())
+            .build();
+        assert!(StagePayload::new(unsupported).to_proto().is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn unix_socket_transport() {
+        use tower::ServiceExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("coprocessor.sock");
+        let listener = tokio::net::UnixListener::bind(&path).unwrap();
+        let server = hyper::Server::builder(hyper::server::accept::from_stream(
+            tokio_stream::wrappers::UnixListenerStream::new(listener),
+        ))
+        .serve(hyper::service::make_service_fn(|_| async {
+            Ok::<_, hyper::Error>(hyper::service::service_fn(
+                |request: hyper::Request<Body>| async move {
+                    // echoes the payload
+                    Ok::<_, hyper::Error>(hyper::Response::new(request.into_body()))
+                },
+            ))
+        }));
+        tokio::spawn(server);
+
+        let url = format!("unix://{}", path.display());
+        let transport =
+            Transport::new(&url, TransportKind::Http, Duration::from_secs(1), None).unwrap();
+        assert_eq!(transport.request_url(&url), UNIX_REQUEST_URL);
+
+        let response = transport
+            .oneshot(
+                hyper::Request::post(UNIX_REQUEST_URL)
+                    .body(Body::from(r#"{"version":1}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(&body[..], br#"{"version":1}"#);
+    }
+}
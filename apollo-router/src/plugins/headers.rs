@@ -839,6 +839,7 @@ mod test {
             connection_closed_signal: None,
             query_hash: Default::default(),
             authorization: Default::default(),
+            fetch: None,
         };
         service.modify_request(&mut request);
         let headers = request
@@ -910,6 +911,7 @@ mod test {
             connection_closed_signal: None,
             query_hash: Default::default(),
             authorization: Default::default(),
+            fetch: None,
         };
         service.modify_request(&mut request);
         let headers = request
@@ -971,6 +973,7 @@ mod test {
             connection_closed_signal: None,
             query_hash: Default::default(),
             authorization: Default::default(),
+            fetch: None,
         }
     }
 
@@ -23,7 +23,7 @@ macro_rules! schemar_fn {
 pub(crate) mod authentication;
 pub(crate) mod authorization;
 pub(crate) mod cache;
-mod coprocessor;
+pub(crate) mod coprocessor;
 pub(crate) mod csrf;
 mod expose_query_plan;
 pub(crate) mod file_uploads;
@@ -34,7 +34,10 @@ use tower::BoxError;
 use uuid::Uuid;
 
 use super::execution;
+use super::http_client::HttpClient;
+use super::query_planner;
 use super::router;
+use super::store::Store;
 use super::subgraph;
 use super::supergraph;
 use super::Rhai;
@@ -43,9 +46,14 @@ use crate::configuration::expansion;
 use crate::graphql::Request;
 use crate::graphql::Response;
 use crate::http_ext;
+use crate::metrics::custom;
+use crate::metrics::custom::CustomMetricKind;
+use crate::plugins::authentication::verify_jwt;
 use crate::plugins::authentication::APOLLO_AUTHENTICATION_JWT_CLAIMS;
 use crate::plugins::cache::entity::CONTEXT_CACHE_KEY;
+use crate::plugins::progressive_override::LABELS_TO_OVERRIDE_KEY;
 use crate::plugins::subscription::SUBSCRIPTION_WS_CUSTOM_CONNECTION_PARAMS;
+use crate::services::QueryPlannerContent;
 use crate::Context;
 
 const CANNOT_ACCESS_HEADERS_ON_A_DEFERRED_RESPONSE: &str =
@@ -173,6 +181,181 @@ mod router_json {
     }
 }
 
+#[derive(Clone)]
+#[allow(unreachable_pub)]
+pub enum CryptoEncoding {
+    Hex,
+    Base64,
+}
+
+fn encode_digest(digest: &[u8], encoding: &CryptoEncoding) -> String {
+    match encoding {
+        CryptoEncoding::Hex => hex::encode(digest),
+        CryptoEncoding::Base64 => BASE64_STANDARD.encode(digest),
+    }
+}
+
+#[export_module]
+#[allow(unreachable_pub)]
+mod router_crypto {
+    use hmac::Hmac;
+    use hmac::Mac;
+    use sha2::Digest;
+    use sha2::Sha256;
+    use sha2::Sha512;
+
+    pub type Encoding = CryptoEncoding;
+    pub const HEX: Encoding = Encoding::Hex;
+    pub const BASE64: Encoding = Encoding::Base64;
+
+    #[rhai_fn(pure)]
+    pub(crate) fn sha256(input: &mut ImmutableString) -> String {
+        sha256_encoding(input, Encoding::Hex)
+    }
+
+    #[rhai_fn(pure, name = "sha256")]
+    pub(crate) fn sha256_encoding(input: &mut ImmutableString, encoding: Encoding) -> String {
+        encode_digest(&Sha256::digest(input.as_bytes()), &encoding)
+    }
+
+    #[rhai_fn(pure)]
+    pub(crate) fn sha512(input: &mut ImmutableString) -> String {
+        sha512_encoding(input, Encoding::Hex)
+    }
+
+    #[rhai_fn(pure, name = "sha512")]
+    pub(crate) fn sha512_encoding(input: &mut ImmutableString, encoding: Encoding) -> String {
+        encode_digest(&Sha512::digest(input.as_bytes()), &encoding)
+    }
+
+    #[rhai_fn(return_raw)]
+    pub(crate) fn hmac_sha256(
+        key: ImmutableString,
+        message: ImmutableString,
+    ) -> Result<String, Box<EvalAltResult>> {
+        hmac_sha256_encoding(key, message, Encoding::Hex)
+    }
+
+    #[rhai_fn(name = "hmac_sha256", return_raw)]
+    pub(crate) fn hmac_sha256_encoding(
+        key: ImmutableString,
+        message: ImmutableString,
+        encoding: Encoding,
+    ) -> Result<String, Box<EvalAltResult>> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes()).map_err(|e| e.to_string())?;
+        mac.update(message.as_bytes());
+        Ok(encode_digest(&mac.finalize().into_bytes(), &encoding))
+    }
+
+    // Compares strings in a time that only depends on their length, to check signatures
+    pub(crate) fn constant_time_eq(a: ImmutableString, b: ImmutableString) -> bool {
+        a.len() == b.len()
+            && a.as_bytes()
+                .iter()
+                .zip(b.as_bytes())
+                .fold(0, |difference, (x, y)| difference | (x ^ y))
+                == 0
+    }
+}
+
+#[export_module]
+mod router_jwt {
+    // Returns the claims of the token without checking its signature
+    #[rhai_fn(return_raw)]
+    pub(crate) fn decode(token: ImmutableString) -> Result<Dynamic, Box<EvalAltResult>> {
+        let payload = token.split('.').nth(1).ok_or("the token is not a JWT")?;
+        let claims: serde_json::Value = serde_json::from_slice(
+            &BASE64_URL_SAFE_NO_PAD
+                .decode(payload)
+                .map_err(|e| e.to_string())?,
+        )
+        .map_err(|e| e.to_string())?;
+        to_dynamic(claims)
+    }
+
+    // Returns the claims of the token if it is signed by a key of the JWKS of the JWT authentication
+    // that handled the request
+    #[rhai_fn(return_raw)]
+    pub(crate) fn verify(
+        context: Context,
+        token: ImmutableString,
+    ) -> Result<Dynamic, Box<EvalAltResult>> {
+        let claims = verify_jwt(&context, &token).map_err(|e| e.to_string())?;
+        to_dynamic(claims)
+    }
+}
+
+fn record_metric(
+    kind: CustomMetricKind,
+    name: &str,
+    value: Dynamic,
+    attributes: Map,
+) -> Result<(), Box<EvalAltResult>> {
+    let value = value
+        .as_float()
+        .or_else(|_| value.as_int().map(|value| value as f64))
+        .map_err(|type_name| {
+            format!("the value of a metric must be a number, found {type_name}")
+        })?;
+    let attributes = attributes
+        .into_iter()
+        .map(|(key, value)| match attribute_value(&value) {
+            Some(value) => Ok(opentelemetry::KeyValue::new(key.to_string(), value)),
+            None => Err(format!(
+                "invalid value for attribute '{key}': expected a string, number or boolean, found {}",
+                value.type_name()
+            )),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    custom::record(kind, name, value, attributes).map_err(|e| e.to_string().into())
+}
+
+fn attribute_value(value: &Dynamic) -> Option<opentelemetry::Value> {
+    if value.is_string() {
+        Some(value.clone().into_string().ok()?.into())
+    } else if let Ok(value) = value.as_int() {
+        Some(value.into())
+    } else if let Ok(value) = value.as_float() {
+        Some(value.into())
+    } else {
+        value.as_bool().ok().map(Into::into)
+    }
+}
+
+#[export_module]
+mod router_metrics {
+    #[rhai_fn(return_raw)]
+    pub(crate) fn counter(name: ImmutableString, value: Dynamic) -> Result<(), Box<EvalAltResult>> {
+        record_metric(CustomMetricKind::Counter, &name, value, Map::new())
+    }
+
+    #[rhai_fn(name = "counter", return_raw)]
+    pub(crate) fn counter_with_attributes(
+        name: ImmutableString,
+        value: Dynamic,
+        attributes: Map,
+    ) -> Result<(), Box<EvalAltResult>> {
+        record_metric(CustomMetricKind::Counter, &name, value, attributes)
+    }
+
+    #[rhai_fn(return_raw)]
+    pub(crate) fn histogram(
+        name: ImmutableString,
+        value: Dynamic,
+    ) -> Result<(), Box<EvalAltResult>> {
+        record_metric(CustomMetricKind::Histogram, &name, value, Map::new())
+    }
+
+    #[rhai_fn(name = "histogram", return_raw)]
+    pub(crate) fn histogram_with_attributes(
+        name: ImmutableString,
+        value: Dynamic,
+        attributes: Map,
+    ) -> Result<(), Box<EvalAltResult>> {
+        record_metric(CustomMetricKind::Histogram, &name, value, attributes)
+    }
+}
+
 #[export_module]
 mod router_expansion {
     pub(crate) type Expansion = expansion::Expansion;
@@ -548,6 +731,22 @@ mod router_context {
     }
 }
 
+fn plan_services(plan: &crate::query_planner::QueryPlan) -> Array {
+    let mut services: Vec<String> = plan.root.service_usage().map(str::to_string).collect();
+    services.sort();
+    services.dedup();
+    services.into_iter().map(Dynamic::from).collect()
+}
+
+fn query_planner_response_plan(
+    response: &query_planner::Response,
+) -> Option<Arc<crate::query_planner::QueryPlan>> {
+    match &response.content {
+        Some(QueryPlannerContent::Plan { plan }) => Some(plan.clone()),
+        _ => None,
+    }
+}
+
 // We have to keep the modules that we export using `export_module` inline because
 // error[E0658]: non-inline modules in proc macro input are unstable
 #[export_module]
@@ -1199,6 +1398,137 @@ mod router_plugin {
                 .unwrap_or_default()
         })
     }
+
+    // Structured, read only copies of the query plan: changes made by scripts are not executed
+    #[rhai_fn(get = "plan", pure, return_raw)]
+    pub(crate) fn execution_request_plan_get(
+        obj: &mut SharedMut<execution::Request>,
+    ) -> Result<Dynamic, Box<EvalAltResult>> {
+        obj.with_mut(|request| to_dynamic(&request.query_plan.root))
+    }
+
+    #[rhai_fn(get = "services", pure)]
+    pub(crate) fn execution_request_services_get(obj: &mut SharedMut<execution::Request>) -> Array {
+        obj.with_mut(|request| plan_services(&request.query_plan))
+    }
+
+    // Subgraph request accessors
+    #[rhai_fn(get = "subgraph_name", pure)]
+    pub(crate) fn subgraph_request_subgraph_name_get(
+        obj: &mut SharedMut<subgraph::Request>,
+    ) -> Dynamic {
+        obj.with_mut(|request| {
+            request
+                .subgraph_name
+                .clone()
+                .map_or(Dynamic::UNIT, Dynamic::from)
+        })
+    }
+
+    #[rhai_fn(get = "operation_kind", pure)]
+    pub(crate) fn subgraph_request_operation_kind_get(
+        obj: &mut SharedMut<subgraph::Request>,
+    ) -> String {
+        obj.with_mut(|request| request.operation_kind.as_str().to_ascii_lowercase())
+    }
+
+    // The fetch node that sent the request, unit for requests which are not sent by the query plan
+    #[rhai_fn(get = "fetch", pure)]
+    pub(crate) fn subgraph_request_fetch_get(obj: &mut SharedMut<subgraph::Request>) -> Dynamic {
+        obj.with_mut(|request| {
+            request.fetch.as_ref().map_or(Dynamic::UNIT, |fetch| {
+                let mut map = Map::new();
+                map.insert("path".into(), fetch.path.to_string().into());
+                map.insert(
+                    "representations".into(),
+                    fetch
+                        .representations
+                        .map_or(Dynamic::UNIT, |count| (count as i64).into()),
+                );
+                map.into()
+            })
+        })
+    }
+
+    // Query planner request accessors
+    #[rhai_fn(get = "query", pure)]
+    pub(crate) fn query_planner_request_query_get(
+        obj: &mut SharedMut<query_planner::Request>,
+    ) -> String {
+        obj.with_mut(|request| request.query.clone())
+    }
+
+    #[rhai_fn(get = "operation_name", pure)]
+    pub(crate) fn query_planner_request_operation_name_get(
+        obj: &mut SharedMut<query_planner::Request>,
+    ) -> Dynamic {
+        obj.with_mut(|request| {
+            request
+                .operation_name
+                .clone()
+                .map_or(Dynamic::UNIT, Dynamic::from)
+        })
+    }
+
+    #[rhai_fn(get = "override_labels", pure)]
+    pub(crate) fn query_planner_request_override_labels_get(
+        obj: &mut SharedMut<query_planner::Request>,
+    ) -> Array {
+        obj.with_mut(|request| {
+            request
+                .context
+                .get::<_, Vec<String>>(LABELS_TO_OVERRIDE_KEY)
+                .ok()
+                .flatten()
+                .unwrap_or_default()
+                .into_iter()
+                .map(Dynamic::from)
+                .collect()
+        })
+    }
+
+    // Query planner response accessors, they are only set when the query planner produced a plan
+    #[rhai_fn(get = "query_plan", pure)]
+    pub(crate) fn query_planner_response_query_plan_get(
+        obj: &mut SharedMut<query_planner::Response>,
+    ) -> Dynamic {
+        obj.with_mut(|response| {
+            query_planner_response_plan(response).map_or(Dynamic::UNIT, |plan| {
+                Dynamic::from(plan.formatted_query_plan.clone().unwrap_or_default())
+            })
+        })
+    }
+
+    #[rhai_fn(get = "plan", pure, return_raw)]
+    pub(crate) fn query_planner_response_plan_get(
+        obj: &mut SharedMut<query_planner::Response>,
+    ) -> Result<Dynamic, Box<EvalAltResult>> {
+        obj.with_mut(|response| {
+            query_planner_response_plan(response)
+                .map_or(Ok(Dynamic::UNIT), |plan| to_dynamic(&plan.root))
+        })
+    }
+
+    #[rhai_fn(get = "subgraph_fetches", pure)]
+    pub(crate) fn query_planner_response_subgraph_fetches_get(
+        obj: &mut SharedMut<query_planner::Response>,
+    ) -> i64 {
+        obj.with_mut(|response| {
+            query_planner_response_plan(response)
+                .map_or(0, |plan| plan.root.subgraph_fetches() as i64)
+        })
+    }
+
+    #[rhai_fn(get = "services", pure)]
+    pub(crate) fn query_planner_response_services_get(
+        obj: &mut SharedMut<query_planner::Response>,
+    ) -> Array {
+        obj.with_mut(|response| {
+            query_planner_response_plan(response)
+                .map(|plan| plan_services(&plan))
+                .unwrap_or_default()
+        })
+    }
 }
 
 #[derive(Default)]
@@ -1555,6 +1885,55 @@ macro_rules! register_rhai_interface {
     };
 }
 
+macro_rules! register_rhai_query_planner_interface {
+    ($engine: ident, $($base: ident), *) => {
+        $(
+            // Context stuff
+            $engine.register_get(
+                "context",
+                |obj: &mut SharedMut<$base::Request>| -> Result<Context, Box<EvalAltResult>> {
+                    Ok(obj.with_mut(|request| request.context.clone()))
+                }
+            )
+            .register_get(
+                "context",
+                |obj: &mut SharedMut<$base::Response>| -> Result<Context, Box<EvalAltResult>> {
+                    Ok(obj.with_mut(|response| response.context.clone()))
+                }
+            );
+
+            $engine.register_set(
+                "context",
+                |obj: &mut SharedMut<$base::Request>, context: Context| {
+                    obj.with_mut(|request| request.context = context);
+                    Ok(())
+                }
+            )
+            .register_set(
+                "context",
+                |obj: &mut SharedMut<$base::Response>, context: Context| {
+                    obj.with_mut(|response| response.context = context);
+                    Ok(())
+                }
+            );
+
+            // Id
+            $engine.register_get(
+                "id",
+                |obj: &mut SharedMut<$base::Request>| -> String {
+                    obj.with_mut(|request| request.context.id.clone())
+                }
+            )
+            .register_get(
+                "id",
+                |obj: &mut SharedMut<$base::Response>| -> String {
+                    obj.with_mut(|response| response.context.id.clone())
+                }
+            );
+        )*
+    };
+}
+
 #[derive(Clone, Debug)]
 pub(crate) struct RhaiService {
     pub(super) scope: Arc<Mutex<Scope<'static>>>,
@@ -1607,7 +1986,13 @@ impl Rhai {
         Ok(())
     }
 
-    pub(super) fn new_rhai_engine(path: Option<PathBuf>, sdl: String, main: PathBuf) -> Engine {
+    pub(super) fn new_rhai_engine(
+        path: Option<PathBuf>,
+        sdl: String,
+        main: PathBuf,
+        http_client: Option<Arc<HttpClient>>,
+        store: Arc<Store>,
+    ) -> Engine {
         let mut engine = Engine::new();
         // If we pass in a path, use it to configure our engine
         // with a FileModuleResolver which allows import to work
@@ -1625,9 +2010,15 @@ impl Rhai {
 
         let base64_module = exported_module!(router_base64);
         let json_module = exported_module!(router_json);
+        let crypto_module = exported_module!(router_crypto);
+        let jwt_module = exported_module!(router_jwt);
+        let metrics_module = exported_module!(router_metrics);
 
         let expansion_module = exported_module!(router_expansion);
 
+        let http_module = HttpClient::module(http_client);
+        let store_module = Store::module(store);
+
         // Share main so we can move copies into each closure as required for logging
         let shared_main = Arc::new(main.display().to_string());
 
@@ -1651,9 +2042,19 @@ impl Rhai {
             .register_static_module("base64", base64_module.into())
             // Register our json module (not global)
             .register_static_module("json", json_module.into())
+            // Register our crypto module (not global)
+            .register_static_module("crypto", crypto_module.into())
+            // Register our jwt module (not global)
+            .register_static_module("jwt", jwt_module.into())
+            // Register our metrics module (not global)
+            .register_static_module("metrics", metrics_module.into())
             // Register our expansion module (not global)
             // Hide the fact that it is an expansion module by calling it "env"
             .register_static_module("env", expansion_module.into())
+            // Register our outbound HTTP module (not global)
+            .register_static_module("http", http_module.into())
+            // Register our key-value store module (not global)
+            .register_static_module("store", store_module.into())
             // Register HeaderMap as an iterator so we can loop over contents
             .register_iterator::<HeaderMap>()
             // Register a series of logging functions
@@ -1676,6 +2077,8 @@ impl Rhai {
         register_rhai_router_interface!(engine, router);
         // Add common getter/setters for different types
         register_rhai_interface!(engine, supergraph, execution, subgraph);
+        // The query planner request and response only carry a context
+        register_rhai_query_planner_interface!(engine, query_planner);
 
         // Since constants in Rhai don't give us the behaviour we expect, let's create some global
         // variables which we use in a variable resolver when we create our engine.
@@ -0,0 +1,230 @@
+//! Outbound HTTP calls from Rhai scripts
+//!
+//! Rhai callbacks are synchronous, so a call blocks the script until the response is received. It runs with
+//! `block_in_place`, which moves the other tasks of the Tokio worker to another thread while the script waits.
+
+use std::future::Future;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use http::header::CONTENT_TYPE;
+use http::HeaderName;
+use http::HeaderValue;
+use http::Method;
+use http::Uri;
+use hyper::client::HttpConnector;
+use hyper::Body;
+use hyper_rustls::ConfigBuilderExt;
+use hyper_rustls::HttpsConnector;
+use rhai::Dynamic;
+use rhai::EvalAltResult;
+use rhai::ImmutableString;
+use rhai::Map;
+use rhai::Module;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use tokio::runtime::Handle;
+use tokio::runtime::RuntimeFlavor;
+use tower::BoxError;
+
+use crate::services::trust_dns_connector::new_async_http_connector;
+use crate::services::trust_dns_connector::AsyncHyperResolver;
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+const POOL_IDLE_TIMEOUT_DURATION: Option<Duration> = Some(Duration::from_secs(5));
+
+/// Outbound HTTP calls from Rhai scripts
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct HttpConf {
+    /// The hosts that scripts can call, `*.example.com` matches the subdomains of example.com
+    allowed_hosts: Vec<String>,
+    /// The timeout of each call (default: 5s)
+    #[serde(
+        deserialize_with = "humantime_serde::deserialize",
+        default = "default_timeout"
+    )]
+    #[schemars(with = "String", default = "default_timeout")]
+    timeout: Duration,
+}
+
+fn default_timeout() -> Duration {
+    DEFAULT_TIMEOUT
+}
+
+/// HTTP client shared by the scripts, kept across script reloads to reuse its connections
+pub(crate) struct HttpClient {
+    client: hyper::Client<HttpsConnector<HttpConnector<AsyncHyperResolver>>, Body>,
+    allowed_hosts: Vec<String>,
+    timeout: Duration,
+}
+
+impl HttpClient {
+    pub(crate) fn new(conf: HttpConf) -> Result<Self, BoxError> {
+        let mut http_connector = new_async_http_connector()?;
+        http_connector.set_nodelay(true);
+        http_connector.set_keepalive(Some(Duration::from_secs(60)));
+        http_connector.enforce_http(false);
+
+        let tls_config = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_native_roots()
+            .with_no_client_auth();
+        let connector = hyper_rustls::HttpsConnectorBuilder::new()
+            .with_tls_config(tls_config)
+            .https_or_http()
+            .enable_http1()
+            .enable_http2()
+            .wrap_connector(http_connector);
+
+        Ok(Self {
+            client: hyper::Client::builder()
+                .pool_idle_timeout(POOL_IDLE_TIMEOUT_DURATION)
+                .build(connector),
+            allowed_hosts: conf
+                .allowed_hosts
+                .into_iter()
+                .map(|host| host.to_ascii_lowercase())
+                .collect(),
+            timeout: conf.timeout,
+        })
+    }
+
+    fn is_allowed(&self, host: &str) -> bool {
+        let host = host.to_ascii_lowercase();
+        self.allowed_hosts
+            .iter()
+            .any(|allowed| match allowed.strip_prefix('*') {
+                Some(suffix) => host.ends_with(suffix),
+                None => *allowed == host,
+            })
+    }
+
+    fn call(
+        &self,
+        method: Method,
+        url: &str,
+        body: Option<Dynamic>,
+        options: Map,
+    ) -> Result<Map, Box<EvalAltResult>> {
+        let uri = Uri::from_str(url).map_err(|e| format!("invalid url {url}: {e}"))?;
+        match uri.host() {
+            Some(host) if self.is_allowed(host) => {}
+            _ => return Err(format!("calls to {url} are not allowed").into()),
+        }
+
+        let mut request = http::Request::builder().method(method).uri(uri);
+        let body = match body {
+            None => Body::empty(),
+            Some(body) if body.is_string() => {
+                Body::from(body.cast::<ImmutableString>().to_string())
+            }
+            Some(body) => {
+                request = request.header(CONTENT_TYPE, "application/json");
+                Body::from(serde_json::to_vec(&body).map_err(|e| e.to_string())?)
+            }
+        };
+        if let Some(headers) = options.get("headers") {
+            let headers = headers
+                .read_lock::<Map>()
+                .ok_or("the headers option must be a map")?;
+            for (name, value) in headers.iter() {
+                let name = HeaderName::from_str(name.as_str()).map_err(|e| e.to_string())?;
+                let value = HeaderValue::from_str(&value.to_string()).map_err(|e| e.to_string())?;
+                request = request.header(name, value);
+            }
+        }
+        let request = request.body(body).map_err(|e| e.to_string())?;
+
+        let client = self.client.clone();
+        let timeout = self.timeout;
+        let (parts, body) = block_on(async move {
+            let response = tokio::time::timeout(timeout, client.request(request))
+                .await
+                .map_err(|_| format!("call to {url} timed out"))?
+                .map_err(|e| format!("call to {url} failed: {e}"))?;
+            let (parts, body) = response.into_parts();
+            let body = hyper::body::to_bytes(body)
+                .await
+                .map_err(|e| format!("call to {url} failed: {e}"))?;
+            Ok::<_, String>((parts, body))
+        })?;
+
+        let mut headers = Map::new();
+        for name in parts.headers.keys() {
+            let values: Vec<&str> = parts
+                .headers
+                .get_all(name)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .collect();
+            headers.insert(name.as_str().into(), values.join(", ").into());
+        }
+        let mut response = Map::new();
+        response.insert("status".into(), (parts.status.as_u16() as i64).into());
+        response.insert("headers".into(), headers.into());
+        response.insert(
+            "body".into(),
+            String::from_utf8_lossy(&body).into_owned().into(),
+        );
+        Ok(response)
+    }
+
+    /// The `http` module of the scripts
+    pub(crate) fn module(client: Option<Arc<HttpClient>>) -> Module {
+        let client = move || -> Result<Arc<HttpClient>, Box<EvalAltResult>> {
+            client.clone().ok_or_else(|| {
+                "outbound HTTP calls are not enabled in the rhai configuration".into()
+            })
+        };
+
+        let mut module = Module::new();
+        let get = client.clone();
+        module.set_native_fn("get", move |url: ImmutableString| {
+            get()?.call(Method::GET, &url, None, Map::new())
+        });
+        let get = client.clone();
+        module.set_native_fn("get", move |url: ImmutableString, options: Map| {
+            get()?.call(Method::GET, &url, None, options)
+        });
+        let post = client.clone();
+        module.set_native_fn("post", move |url: ImmutableString, body: Dynamic| {
+            post()?.call(Method::POST, &url, Some(body), Map::new())
+        });
+        let post = client;
+        module.set_native_fn(
+            "post",
+            move |url: ImmutableString, body: Dynamic, options: Map| {
+                post()?.call(Method::POST, &url, Some(body), options)
+            },
+        );
+        module
+    }
+}
+
+/// Waits for the future without blocking the other tasks of the runtime, for the synchronous callbacks of the scripts
+pub(super) fn block_on<F>(future: F) -> F::Output
+where
+    F: Future + Send,
+    F::Output: Send,
+{
+    match Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+            tokio::task::block_in_place(|| handle.block_on(future))
+        }
+        // a single threaded runtime can't run the future while the script waits: use a runtime on another thread
+        _ => std::thread::scope(|scope| {
+            scope
+                .spawn(|| {
+                    tokio::runtime::Builder::new_current_thread()
+                        .enable_all()
+                        .build()
+                        .expect("could not create the rhai HTTP runtime")
+                        .block_on(future)
+                })
+                .join()
+                .expect("the rhai HTTP call panicked")
+        }),
+    }
+}
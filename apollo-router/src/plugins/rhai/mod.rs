@@ -1,7 +1,11 @@
 //! Customization via Rhai.
 
+use std::collections::hash_map::DefaultHasher;
 use std::fmt;
+use std::hash::Hash;
+use std::hash::Hasher;
 use std::ops::ControlFlow;
+use std::path::Path;
 use std::path::PathBuf;
 use std::sync::atomic::AtomicBool;
 use std::sync::atomic::Ordering;
@@ -40,18 +44,27 @@ use tower::ServiceExt;
 
 use self::engine::RhaiService;
 use self::engine::SharedMut;
+use self::http_client::HttpClient;
+use self::http_client::HttpConf;
+use self::store::Store;
+use self::store::StoreConf;
 use crate::error::Error;
 use crate::layers::ServiceBuilderExt;
-use crate::plugin::Plugin;
 use crate::plugin::PluginInit;
+use crate::plugin::PluginPrivate;
 use crate::plugins::rhai::engine::OptionDance;
-use crate::register_plugin;
+use crate::register_private_plugin;
 
 mod engine;
+mod http_client;
+mod store;
+pub(crate) mod test_runner;
 
 pub(crate) const RHAI_SPAN_NAME: &str = "rhai_plugin";
+const VALIDATE_FUNCTION: &str = "validate";
 
 mod execution;
+mod query_planner;
 mod router;
 mod subgraph;
 mod supergraph;
@@ -67,15 +80,24 @@ impl EngineBlock {
         scripts: Option<PathBuf>,
         main: PathBuf,
         sdl: Arc<String>,
+        http_client: Option<Arc<HttpClient>>,
+        store: Arc<Store>,
     ) -> Result<Self, BoxError> {
         let engine = Arc::new(Rhai::new_rhai_engine(
             scripts,
             sdl.to_string(),
             main.clone(),
+            http_client,
+            store,
         ));
         let ast = engine
             .compile_file(main.clone())
             .map_err(|err| format!("in Rhai script {}: {}", main.display(), err))?;
+        // Imported modules are only compiled when their import runs: resolve them now to
+        // find their errors before using the scripts.
+        engine
+            .compile_into_self_contained(&Scope::new(), std::fs::read_to_string(&main)?)
+            .map_err(|err| format!("in Rhai modules imported by {}: {}", main.display(), err))?;
         let mut scope = Scope::new();
         // Keep these two lower cases ones as mistakes until 2.0
         // At 2.0 (or maybe before), replace with upper case
@@ -88,6 +110,30 @@ impl EngineBlock {
         // defined in scripts into scope.
         engine.run_ast_with_scope(&mut scope, &ast)?;
 
+        // The scripts can check their own setup with a `validate()` function, which rejects
+        // them by throwing an error or returning false.
+        if ast
+            .iter_functions()
+            .any(|function| function.name == VALIDATE_FUNCTION && function.params.is_empty())
+        {
+            let valid = engine
+                .call_fn::<Dynamic>(&mut scope, &ast, VALIDATE_FUNCTION, ())
+                .map_err(|err| {
+                    format!(
+                        "in Rhai script {}: {VALIDATE_FUNCTION}() failed: {}",
+                        main.display(),
+                        err
+                    )
+                })?;
+            if valid.as_bool() == Ok(false) {
+                return Err(format!(
+                    "in Rhai script {}: {VALIDATE_FUNCTION}() returned false",
+                    main.display()
+                )
+                .into());
+            }
+        }
+
         Ok(EngineBlock {
             ast,
             engine,
@@ -96,6 +142,87 @@ impl EngineBlock {
     }
 }
 
+/// Replaces the engine if the scripts compile and validate, and keeps the current engine otherwise.
+/// Returns whether the engine was replaced.
+fn reload_engine(
+    block: &ArcSwap<EngineBlock>,
+    scripts: &Path,
+    main: &Path,
+    sdl: &Arc<String>,
+    http_client: &Option<Arc<HttpClient>>,
+    store: &Arc<Store>,
+) -> bool {
+    // Scripts modified while they are compiled may be half written: they are reloaded on the
+    // next change event, once the writes are complete.
+    let fingerprint = scripts_fingerprint(scripts);
+    let result = EngineBlock::try_new(
+        Some(scripts.to_path_buf()),
+        main.to_path_buf(),
+        sdl.clone(),
+        http_client.clone(),
+        store.clone(),
+    )
+    .and_then(|engine_block| {
+        if scripts_fingerprint(scripts) == fingerprint {
+            Ok(engine_block)
+        } else {
+            Err("the scripts were modified while they were loaded".into())
+        }
+    });
+
+    match result {
+        Ok(engine_block) => {
+            tracing::info!("updating rhai execution engine");
+            block.store(Arc::new(engine_block));
+            u64_counter!(
+                "apollo.router.rhai.reload",
+                "Number of reloads of the Rhai scripts",
+                1,
+                result = "success"
+            );
+            true
+        }
+        Err(e) => {
+            tracing::error!(
+                "could not reload the rhai scripts, the previous scripts are still used: {}",
+                e
+            );
+            u64_counter!(
+                "apollo.router.rhai.reload",
+                "Number of reloads of the Rhai scripts",
+                1,
+                result = "failure"
+            );
+            false
+        }
+    }
+}
+
+/// Hash of the paths and contents of the scripts in the directory and its subdirectories
+fn scripts_fingerprint(scripts: &Path) -> u64 {
+    fn visit(directory: &Path, hasher: &mut DefaultHasher) {
+        let Ok(entries) = std::fs::read_dir(directory) else {
+            return;
+        };
+        let mut paths: Vec<PathBuf> = entries
+            .filter_map(|entry| Some(entry.ok()?.path()))
+            .collect();
+        paths.sort();
+        for path in paths {
+            if path.is_dir() {
+                visit(&path, hasher);
+            } else if path.extension().map_or(false, |ext| ext == "rhai") {
+                path.hash(hasher);
+                std::fs::read(&path).ok().hash(hasher);
+            }
+        }
+    }
+
+    let mut hasher = DefaultHasher::new();
+    visit(scripts, &mut hasher);
+    hasher.finish()
+}
+
 /// Plugin which implements Rhai functionality
 /// Note: We use ArcSwap here in preference to a shared RwLock. Updates to
 /// the engine block will be infrequent in relation to the accesses of it.
@@ -115,10 +242,15 @@ pub(crate) struct Conf {
     scripts: Option<PathBuf>,
     /// The main entry point for Rhai script evaluation
     main: Option<String>,
+    /// Outbound HTTP calls from scripts (default: disabled)
+    http: Option<HttpConf>,
+    /// Key-value store shared by the scripts (default: in memory)
+    #[serde(default)]
+    store: StoreConf,
 }
 
 #[async_trait::async_trait]
-impl Plugin for Rhai {
+impl PluginPrivate for Rhai {
     type Config = Conf;
 
     async fn new(init: PluginInit<Self::Config>) -> Result<Self, BoxError> {
@@ -139,10 +271,22 @@ impl Plugin for Rhai {
         let watched_main = main.clone();
         let watched_sdl = sdl.clone();
 
+        let http_client = init
+            .config
+            .http
+            .map(HttpClient::new)
+            .transpose()?
+            .map(Arc::new);
+        let watched_http_client = http_client.clone();
+        let store = Arc::new(Store::new(init.config.store).await?);
+        let watched_store = store.clone();
+
         let block = Arc::new(ArcSwap::from_pointee(EngineBlock::try_new(
             Some(scripts_path),
             main,
             sdl,
+            http_client,
+            store,
         )?));
         let watched_block = block.clone();
 
@@ -178,22 +322,14 @@ impl Plugin for Rhai {
                                 }
 
                                 if proceed {
-                                    match EngineBlock::try_new(
-                                        Some(watching_path.clone()),
-                                        watched_main.clone(),
-                                        watched_sdl.clone(),
-                                    ) {
-                                        Ok(eb) => {
-                                            tracing::info!("updating rhai execution engine");
-                                            watched_block.store(Arc::new(eb))
-                                        }
-                                        Err(e) => {
-                                            tracing::warn!(
-                                                "could not create new rhai execution engine: {}",
-                                                e
-                                            );
-                                        }
-                                    }
+                                    reload_engine(
+                                        &watched_block,
+                                        &watching_path,
+                                        &watched_main,
+                                        &watched_sdl,
+                                        &watched_http_client,
+                                        &watched_store,
+                                    );
                                 }
                             }
                         }
@@ -258,6 +394,27 @@ impl Plugin for Rhai {
         shared_service.take_unwrap()
     }
 
+    fn query_planner_service(
+        &self,
+        service: query_planner::BoxService,
+    ) -> query_planner::BoxService {
+        const FUNCTION_NAME_SERVICE: &str = "query_planner_service";
+        if !self.ast_has_function(FUNCTION_NAME_SERVICE) {
+            return service;
+        }
+        tracing::debug!("query_planner_service function found");
+        let shared_service = Arc::new(Mutex::new(Some(service)));
+        if let Err(error) = self.run_rhai_service(
+            FUNCTION_NAME_SERVICE,
+            None,
+            ServiceStep::QueryPlanner(shared_service.clone()),
+            self.block.load().scope.clone(),
+        ) {
+            tracing::error!("service callback failed: {error}");
+        }
+        shared_service.take_unwrap()
+    }
+
     fn execution_service(&self, service: execution::BoxService) -> execution::BoxService {
         const FUNCTION_NAME_SERVICE: &str = "execution_service";
         if !self.ast_has_function(FUNCTION_NAME_SERVICE) {
@@ -309,6 +466,7 @@ impl Drop for Rhai {
 pub(crate) enum ServiceStep {
     Router(SharedMut<router::BoxService>),
     Supergraph(SharedMut<supergraph::BoxService>),
+    QueryPlanner(SharedMut<query_planner::BoxService>),
     Execution(SharedMut<execution::BoxService>),
     Subgraph(SharedMut<subgraph::BoxService>),
 }
@@ -711,6 +869,9 @@ impl ServiceStep {
             ServiceStep::Supergraph(service) => {
                 gen_map_request!(supergraph, service, rhai_service, callback);
             }
+            ServiceStep::QueryPlanner(service) => {
+                gen_map_request!(query_planner, service, rhai_service, callback);
+            }
             ServiceStep::Execution(service) => {
                 gen_map_request!(execution, service, rhai_service, callback);
             }
@@ -728,6 +889,9 @@ impl ServiceStep {
             ServiceStep::Supergraph(service) => {
                 gen_map_deferred_response!(supergraph, service, rhai_service, callback);
             }
+            ServiceStep::QueryPlanner(service) => {
+                gen_map_response!(query_planner, service, rhai_service, callback);
+            }
             ServiceStep::Execution(service) => {
                 gen_map_deferred_response!(execution, service, rhai_service, callback);
             }
@@ -825,7 +989,7 @@ fn execute(
     }
 }
 
-register_plugin!("apollo", "rhai", Rhai);
+register_private_plugin!("apollo", "rhai", Rhai);
 
 #[cfg(test)]
 mod tests;
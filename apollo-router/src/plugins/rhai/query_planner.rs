@@ -0,0 +1,29 @@
+//! query_planner module
+
+use std::ops::ControlFlow;
+
+use super::ErrorDetails;
+use crate::error::QueryPlannerError;
+use crate::graphql::Error;
+pub(crate) use crate::services::query_planner::*;
+use crate::Context;
+
+pub(super) fn request_failure(
+    context: Context,
+    error_details: ErrorDetails,
+) -> Result<ControlFlow<Response, Request>, QueryPlannerError> {
+    Ok(ControlFlow::Break(response_failure(context, error_details)))
+}
+
+/// The status code is ignored: the supergraph service answers rejected operations with a 400
+pub(super) fn response_failure(context: Context, error_details: ErrorDetails) -> Response {
+    let errors = match error_details.body {
+        Some(body) if !body.errors.is_empty() => body.errors,
+        _ => vec![Error {
+            message: error_details.message.unwrap_or_default(),
+            ..Default::default()
+        }],
+    };
+
+    Response::builder().context(context).errors(errors).build()
+}
@@ -0,0 +1,277 @@
+//! Key-value store shared by the scripts
+//!
+//! Values are stored as JSON. Without Redis, the store is kept in memory, evicts the least recently used entries
+//! when it is full, and is emptied when the router configuration is reloaded. Expired entries are removed when
+//! they are read or evicted.
+
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use lru::LruCache;
+use rhai::serde::from_dynamic;
+use rhai::serde::to_dynamic;
+use rhai::Dynamic;
+use rhai::EvalAltResult;
+use rhai::ImmutableString;
+use rhai::Module;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json::Value;
+use tower::BoxError;
+
+use super::http_client::block_on;
+use crate::cache::redis::RedisCacheStorage;
+use crate::cache::redis::RedisKey;
+use crate::cache::redis::RedisValue;
+use crate::configuration::InMemoryCache;
+use crate::configuration::RedisCache;
+
+/// Key-value store shared by the scripts
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct StoreConf {
+    /// In memory store, used when Redis is not configured
+    #[serde(default)]
+    in_memory: InMemoryCache,
+    /// Redis store, shared by the router instances
+    redis: Option<RedisCache>,
+}
+
+pub(super) struct Entry {
+    value: Value,
+    expires_at: Option<Instant>,
+}
+
+impl Entry {
+    fn is_expired(&self) -> bool {
+        self.expires_at
+            .map_or(false, |expires_at| expires_at <= Instant::now())
+    }
+}
+
+/// Store shared by the scripts, kept across script reloads
+pub(crate) enum Store {
+    InMemory(Mutex<LruCache<String, Entry>>),
+    Redis(RedisCacheStorage),
+}
+
+impl Store {
+    pub(crate) async fn new(conf: StoreConf) -> Result<Self, BoxError> {
+        if let Some(redis) = conf.redis {
+            let required_to_start = redis.required_to_start;
+            match RedisCacheStorage::new(redis).await {
+                Ok(storage) => return Ok(Store::Redis(storage)),
+                Err(e) => {
+                    tracing::error!(e, "could not open connection to Redis for the rhai store");
+                    if required_to_start {
+                        return Err(e);
+                    }
+                }
+            }
+        }
+        Ok(Self::in_memory(conf.in_memory.limit))
+    }
+
+    pub(crate) fn in_memory(limit: NonZeroUsize) -> Self {
+        Store::InMemory(Mutex::new(LruCache::new(limit)))
+    }
+
+    fn get(&self, key: &str) -> Option<Value> {
+        match self {
+            Store::InMemory(entries) => {
+                let mut entries = entries.lock().expect("lock poisoned");
+                match entries.get(key) {
+                    Some(entry) if entry.is_expired() => {
+                        entries.pop(key);
+                        None
+                    }
+                    Some(entry) => Some(entry.value.clone()),
+                    None => None,
+                }
+            }
+            Store::Redis(storage) => {
+                block_on(storage.get::<_, Value>(RedisKey(key.to_string()))).map(|value| value.0)
+            }
+        }
+    }
+
+    fn set(
+        &self,
+        key: &str,
+        value: Value,
+        ttl: Option<Duration>,
+    ) -> Result<(), Box<EvalAltResult>> {
+        match self {
+            Store::InMemory(entries) => {
+                entries.lock().expect("lock poisoned").put(
+                    key.to_string(),
+                    Entry {
+                        value,
+                        expires_at: ttl.map(|ttl| Instant::now() + ttl),
+                    },
+                );
+            }
+            Store::Redis(storage) => {
+                block_on(storage.insert(RedisKey(key.to_string()), RedisValue(value), ttl))
+            }
+        }
+        Ok(())
+    }
+
+    fn increment(
+        &self,
+        key: &str,
+        delta: i64,
+        ttl: Option<Duration>,
+    ) -> Result<i64, Box<EvalAltResult>> {
+        match self {
+            Store::InMemory(entries) => {
+                let mut entries = entries.lock().expect("lock poisoned");
+                let (current, expires_at) = match entries.get(key) {
+                    Some(entry) if !entry.is_expired() => (
+                        entry
+                            .value
+                            .as_i64()
+                            .ok_or_else(|| format!("the value of {key} is not an integer"))?,
+                        entry.expires_at,
+                    ),
+                    _ => (0, None),
+                };
+                let value = current
+                    .checked_add(delta)
+                    .ok_or_else(|| format!("the value of {key} would overflow"))?;
+                entries.put(
+                    key.to_string(),
+                    Entry {
+                        value: value.into(),
+                        expires_at: expires_at.or_else(|| ttl.map(|ttl| Instant::now() + ttl)),
+                    },
+                );
+                Ok(value)
+            }
+            Store::Redis(storage) => {
+                block_on(storage.increment(RedisKey(key.to_string()), delta, ttl))
+                    .map_err(|e| e.to_string().into())
+            }
+        }
+    }
+
+    fn compare_and_swap(
+        &self,
+        key: &str,
+        expected: Option<Value>,
+        value: Value,
+        ttl: Option<Duration>,
+    ) -> Result<bool, Box<EvalAltResult>> {
+        match self {
+            Store::InMemory(entries) => {
+                let mut entries = entries.lock().expect("lock poisoned");
+                let current = entries
+                    .get(key)
+                    .filter(|entry| !entry.is_expired())
+                    .map(|entry| &entry.value);
+                if current != expected.as_ref() {
+                    return Ok(false);
+                }
+                entries.put(
+                    key.to_string(),
+                    Entry {
+                        value,
+                        expires_at: ttl.map(|ttl| Instant::now() + ttl),
+                    },
+                );
+                Ok(true)
+            }
+            Store::Redis(storage) => block_on(storage.compare_and_swap(
+                RedisKey(key.to_string()),
+                expected.map(RedisValue),
+                RedisValue(value),
+                ttl,
+            ))
+            .map_err(|e| e.to_string().into()),
+        }
+    }
+
+    /// The `store` module of the scripts
+    pub(crate) fn module(store: Arc<Store>) -> Module {
+        let mut module = Module::new();
+        let get = store.clone();
+        module.set_native_fn("get", move |key: ImmutableString| {
+            get.get(&key).map_or(Ok(Dynamic::UNIT), to_dynamic)
+        });
+        let set = store.clone();
+        module.set_native_fn("set", move |key: ImmutableString, value: Dynamic| {
+            set.set(&key, to_json(&value)?, None)
+        });
+        let set = store.clone();
+        module.set_native_fn(
+            "set",
+            move |key: ImmutableString, value: Dynamic, ttl: i64| {
+                set.set(&key, to_json(&value)?, Some(to_ttl(ttl)?))
+            },
+        );
+        let increment = store.clone();
+        module.set_native_fn("increment", move |key: ImmutableString| {
+            increment.increment(&key, 1, None)
+        });
+        let increment = store.clone();
+        module.set_native_fn("increment", move |key: ImmutableString, delta: i64| {
+            increment.increment(&key, delta, None)
+        });
+        let increment = store.clone();
+        module.set_native_fn(
+            "increment",
+            move |key: ImmutableString, delta: i64, ttl: i64| {
+                increment.increment(&key, delta, Some(to_ttl(ttl)?))
+            },
+        );
+        let compare_and_swap = store.clone();
+        module.set_native_fn(
+            "compare_and_swap",
+            move |key: ImmutableString, expected: Dynamic, value: Dynamic| {
+                compare_and_swap.compare_and_swap(
+                    &key,
+                    to_expected(&expected)?,
+                    to_json(&value)?,
+                    None,
+                )
+            },
+        );
+        let compare_and_swap = store;
+        module.set_native_fn(
+            "compare_and_swap",
+            move |key: ImmutableString, expected: Dynamic, value: Dynamic, ttl: i64| {
+                compare_and_swap.compare_and_swap(
+                    &key,
+                    to_expected(&expected)?,
+                    to_json(&value)?,
+                    Some(to_ttl(ttl)?),
+                )
+            },
+        );
+        module
+    }
+}
+
+fn to_json(value: &Dynamic) -> Result<Value, Box<EvalAltResult>> {
+    from_dynamic(value)
+}
+
+// `()` is the expected value of keys that do not exist
+fn to_expected(value: &Dynamic) -> Result<Option<Value>, Box<EvalAltResult>> {
+    if value.is_unit() {
+        Ok(None)
+    } else {
+        to_json(value).map(Some)
+    }
+}
+
+fn to_ttl(seconds: i64) -> Result<Duration, Box<EvalAltResult>> {
+    match u64::try_from(seconds) {
+        Ok(seconds) if seconds > 0 => Ok(Duration::from_secs(seconds)),
+        _ => Err(format!("the TTL must be a positive number of seconds, found {seconds}").into()),
+    }
+}
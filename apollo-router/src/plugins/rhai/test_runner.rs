@@ -0,0 +1,554 @@
+//! Offline tests of Rhai scripts
+//!
+//! Fixtures are YAML or JSON files listing test cases. Each case sends a request through the callbacks of one
+//! service, replaces the next service with the response of the fixture, then compares the request received by the
+//! next service and the response returned by the callbacks with the expectations of the case.
+//! Expected objects are subsets: only their fields are compared, and `null` matches missing fields.
+
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use futures::future::ready;
+use futures::stream::once;
+use futures::StreamExt;
+use http::HeaderMap;
+use http::HeaderName;
+use http::HeaderValue;
+use http::StatusCode;
+use serde::Deserialize;
+use serde_json::Map;
+use serde_json::Value;
+use tower::BoxError;
+use tower::ServiceExt;
+
+use super::execution;
+use super::router;
+use super::subgraph;
+use super::supergraph;
+use super::Conf;
+use super::Rhai;
+use crate::graphql;
+use crate::plugin::PluginInit;
+use crate::plugin::PluginPrivate;
+use crate::Context;
+
+const DEFAULT_URI: &str = "http://localhost/";
+
+type Received = Arc<Mutex<Option<Value>>>;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TestCase {
+    name: String,
+    service: TestService,
+    /// The name of the subgraph, for the subgraph service
+    subgraph: Option<String>,
+    /// The request sent to the callbacks
+    #[serde(default)]
+    request: Message,
+    /// The response of the next service
+    #[serde(default)]
+    response: Message,
+    #[serde(default)]
+    expect: Expectations,
+}
+
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum TestService {
+    Router,
+    Supergraph,
+    Execution,
+    Subgraph,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Message {
+    uri: Option<String>,
+    status: Option<u16>,
+    /// Header values are strings, or lists of strings
+    #[serde(default)]
+    headers: Map<String, Value>,
+    #[serde(default)]
+    context: Map<String, Value>,
+    body: Option<Value>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Expectations {
+    /// The request received by the next service
+    request: Option<Value>,
+    /// The response returned by the callbacks
+    response: Option<Value>,
+}
+
+/// Results of the test cases
+#[derive(Debug, Default)]
+pub(crate) struct TestReport {
+    pub(crate) passed: Vec<String>,
+    /// The failed test cases, with the differences to their expectations
+    pub(crate) failed: Vec<(String, Vec<String>)>,
+}
+
+impl TestReport {
+    pub(crate) fn is_success(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
+
+impl fmt::Display for TestReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for name in &self.passed {
+            writeln!(f, "test {name} ... ok")?;
+        }
+        for (name, _) in &self.failed {
+            writeln!(f, "test {name} ... FAILED")?;
+        }
+        if !self.failed.is_empty() {
+            writeln!(f, "\nfailures:")?;
+            for (name, failures) in &self.failed {
+                writeln!(f, "\n{name}")?;
+                for failure in failures {
+                    writeln!(f, "    {failure}")?;
+                }
+            }
+        }
+        write!(
+            f,
+            "\ntest result: {}. {} passed; {} failed",
+            if self.is_success() { "ok" } else { "FAILED" },
+            self.passed.len(),
+            self.failed.len()
+        )
+    }
+}
+
+/// Runs the test cases of the fixture files against the scripts of the rhai configuration
+pub(crate) async fn run(
+    conf: Value,
+    supergraph_sdl: Arc<String>,
+    fixtures: &[PathBuf],
+) -> Result<TestReport, BoxError> {
+    let conf: Conf = serde_json::from_value(conf)?;
+    let rhai = Rhai::new(
+        PluginInit::fake_builder()
+            .config(conf)
+            .supergraph_sdl(supergraph_sdl)
+            .build(),
+    )
+    .await?;
+
+    let mut report = TestReport::default();
+    for fixture in fixtures {
+        let contents = std::fs::read_to_string(fixture)
+            .map_err(|e| format!("could not read {}: {e}", fixture.display()))?;
+        // YAML is a superset of JSON
+        let cases: Vec<TestCase> = serde_yaml::from_str(&contents)
+            .map_err(|e| format!("invalid fixtures in {}: {e}", fixture.display()))?;
+        for case in cases {
+            let name = format!("{}: {}", fixture.display(), case.name);
+            let failures = run_case(&rhai, case)
+                .await
+                .unwrap_or_else(|e| vec![format!("the test could not run: {e}")]);
+            if failures.is_empty() {
+                report.passed.push(name);
+            } else {
+                report.failed.push((name, failures));
+            }
+        }
+    }
+    Ok(report)
+}
+
+async fn run_case(rhai: &Rhai, case: TestCase) -> Result<Vec<String>, BoxError> {
+    let received = Received::default();
+    let TestCase {
+        service,
+        subgraph,
+        request,
+        response,
+        expect,
+        ..
+    } = case;
+
+    let response = match service {
+        TestService::Router => {
+            let next = router_service(response, received.clone())?;
+            let router::Response { response, context } = rhai
+                .router_service(next)
+                .oneshot(router::Request {
+                    router_request: http_request(
+                        &request,
+                        hyper::Body::from(raw_body(request.body.as_ref())?),
+                    )?,
+                    context: request_context(&request)?,
+                })
+                .await?;
+            let (parts, body) = response.into_parts();
+            let body = parse_body(&hyper::body::to_bytes(body).await?);
+            observe(Some(parts.status), &parts.headers, &context, body)
+        }
+        TestService::Supergraph => {
+            let next = supergraph_service(response, received.clone())?;
+            let response = rhai
+                .supergraph_service(next)
+                .oneshot(supergraph::Request {
+                    supergraph_request: http_request(&request, graphql_request(&request)?)?,
+                    context: request_context(&request)?,
+                })
+                .await?;
+            observe_stream(response).await
+        }
+        TestService::Execution => {
+            let next = execution_service(response, received.clone())?;
+            let response = rhai
+                .execution_service(next)
+                .oneshot(
+                    execution::Request::fake_builder()
+                        .supergraph_request(http_request(&request, graphql_request(&request)?)?)
+                        .context(request_context(&request)?)
+                        .build(),
+                )
+                .await?;
+            observe_stream(response).await
+        }
+        TestService::Subgraph => {
+            let name = subgraph.ok_or("the subgraph service requires a subgraph name")?;
+            let next = subgraph_service(response, received.clone())?;
+            let subgraph::Response { response, context } = rhai
+                .subgraph_service(&name, next)
+                .oneshot(
+                    subgraph::Request::fake_builder()
+                        .subgraph_request(http_request(&request, graphql_request(&request)?)?)
+                        .context(request_context(&request)?)
+                        .subgraph_name(name)
+                        .build(),
+                )
+                .await?;
+            let (parts, body) = response.into_parts();
+            observe(
+                Some(parts.status),
+                &parts.headers,
+                &context,
+                serde_json::to_value(body)?,
+            )
+        }
+    };
+
+    let mut failures = Vec::new();
+    if let Some(mut expected) = expect.request {
+        lowercase_headers(&mut expected);
+        match received.lock().expect("lock poisoned").take() {
+            Some(received) => compare("request", &expected, Some(&received), &mut failures),
+            None => {
+                failures.push("request: the request did not reach the next service".to_string())
+            }
+        }
+    }
+    if let Some(mut expected) = expect.response {
+        lowercase_headers(&mut expected);
+        compare("response", &expected, Some(&response), &mut failures);
+    }
+    Ok(failures)
+}
+
+fn router_service(response: Message, received: Received) -> Result<router::BoxService, BoxError> {
+    let status = status(&response)?;
+    let headers = header_map(&response.headers)?;
+    let body = raw_body(response.body.as_ref())?;
+    Ok(tower::service_fn(move |request: router::Request| {
+        let received = received.clone();
+        let headers = headers.clone();
+        let body = body.clone();
+        async move {
+            let (parts, request_body) = request.router_request.into_parts();
+            let request_body = parse_body(&hyper::body::to_bytes(request_body).await?);
+            *received.lock().expect("lock poisoned") = Some(observe(
+                None,
+                &parts.headers,
+                &request.context,
+                request_body,
+            ));
+
+            let mut response = http::Response::new(hyper::Body::from(body));
+            *response.status_mut() = status;
+            *response.headers_mut() = headers;
+            Ok::<_, BoxError>(router::Response {
+                response,
+                context: request.context,
+            })
+        }
+    })
+    .boxed())
+}
+
+fn supergraph_service(
+    response: Message,
+    received: Received,
+) -> Result<supergraph::BoxService, BoxError> {
+    let status = status(&response)?;
+    let headers = header_map(&response.headers)?;
+    let body = graphql_response(&response)?;
+    Ok(tower::service_fn(move |request: supergraph::Request| {
+        let observed = serde_json::to_value(request.supergraph_request.body()).map(|body| {
+            observe(
+                None,
+                request.supergraph_request.headers(),
+                &request.context,
+                body,
+            )
+        });
+        let response = stream_response(status, headers.clone(), body.clone(), request.context);
+        *received.lock().expect("lock poisoned") = observed.ok();
+        ready(Ok::<_, BoxError>(response))
+    })
+    .boxed())
+}
+
+fn execution_service(
+    response: Message,
+    received: Received,
+) -> Result<execution::BoxService, BoxError> {
+    let status = status(&response)?;
+    let headers = header_map(&response.headers)?;
+    let body = graphql_response(&response)?;
+    Ok(tower::service_fn(move |request: execution::Request| {
+        let observed = serde_json::to_value(request.supergraph_request.body()).map(|body| {
+            observe(
+                None,
+                request.supergraph_request.headers(),
+                &request.context,
+                body,
+            )
+        });
+        let response = stream_response(status, headers.clone(), body.clone(), request.context);
+        *received.lock().expect("lock poisoned") = observed.ok();
+        ready(Ok::<_, BoxError>(response))
+    })
+    .boxed())
+}
+
+fn subgraph_service(
+    response: Message,
+    received: Received,
+) -> Result<subgraph::BoxService, BoxError> {
+    let status = status(&response)?;
+    let headers = header_map(&response.headers)?;
+    let body = graphql_response(&response)?;
+    Ok(tower::service_fn(move |request: subgraph::Request| {
+        let observed = serde_json::to_value(request.subgraph_request.body()).map(|body| {
+            observe(
+                None,
+                request.subgraph_request.headers(),
+                &request.context,
+                body,
+            )
+        });
+        *received.lock().expect("lock poisoned") = observed.ok();
+
+        let mut response = http::Response::new(body.clone());
+        *response.status_mut() = status;
+        *response.headers_mut() = headers.clone();
+        ready(Ok::<_, BoxError>(subgraph::Response {
+            response,
+            context: request.context,
+        }))
+    })
+    .boxed())
+}
+
+fn stream_response(
+    status: StatusCode,
+    headers: HeaderMap,
+    body: graphql::Response,
+    context: Context,
+) -> supergraph::Response {
+    let mut response = http::Response::new(once(ready(body)).boxed());
+    *response.status_mut() = status;
+    *response.headers_mut() = headers;
+    supergraph::Response { response, context }
+}
+
+// the deferred responses are compared as a list
+async fn observe_stream(response: supergraph::Response) -> Value {
+    let supergraph::Response { response, context } = response;
+    let (parts, stream) = response.into_parts();
+    let mut bodies: Vec<Value> = stream
+        .map(|response| serde_json::to_value(response).unwrap_or_default())
+        .collect()
+        .await;
+    let body = if bodies.len() == 1 {
+        bodies.remove(0)
+    } else {
+        Value::Array(bodies)
+    };
+    observe(Some(parts.status), &parts.headers, &context, body)
+}
+
+fn observe(
+    status: Option<StatusCode>,
+    headers: &HeaderMap,
+    context: &Context,
+    body: Value,
+) -> Value {
+    let mut observed = Map::new();
+    if let Some(status) = status {
+        observed.insert("status".to_string(), status.as_u16().into());
+    }
+    let mut header_values = Map::new();
+    for name in headers.keys() {
+        let mut values: Vec<Value> = headers
+            .get_all(name)
+            .iter()
+            .map(|value| {
+                String::from_utf8_lossy(value.as_bytes())
+                    .into_owned()
+                    .into()
+            })
+            .collect();
+        let value = if values.len() == 1 {
+            values.remove(0)
+        } else {
+            Value::Array(values)
+        };
+        header_values.insert(name.as_str().to_string(), value);
+    }
+    observed.insert("headers".to_string(), Value::Object(header_values));
+    observed.insert(
+        "context".to_string(),
+        Value::Object(
+            context
+                .iter()
+                .map(|entry| {
+                    (
+                        entry.key().clone(),
+                        serde_json::to_value(entry.value()).unwrap_or_default(),
+                    )
+                })
+                .collect(),
+        ),
+    );
+    observed.insert("body".to_string(), body);
+    Value::Object(observed)
+}
+
+fn compare(path: &str, expected: &Value, actual: Option<&Value>, failures: &mut Vec<String>) {
+    match (expected, actual) {
+        (Value::Null, None | Some(Value::Null)) => {}
+        (Value::Object(expected), Some(Value::Object(actual))) => {
+            for (key, expected) in expected {
+                compare(
+                    &format!("{path}.{key}"),
+                    expected,
+                    actual.get(key),
+                    failures,
+                );
+            }
+        }
+        (Value::Array(expected), Some(Value::Array(actual))) if expected.len() == actual.len() => {
+            for (index, (expected, actual)) in expected.iter().zip(actual).enumerate() {
+                compare(
+                    &format!("{path}[{index}]"),
+                    expected,
+                    Some(actual),
+                    failures,
+                );
+            }
+        }
+        (expected, Some(actual)) if expected == actual => {}
+        (expected, None) => failures.push(format!("{path}: expected {expected}, found nothing")),
+        (expected, Some(actual)) => {
+            failures.push(format!("{path}: expected {expected}, found {actual}"))
+        }
+    }
+}
+
+// header names are case insensitive
+fn lowercase_headers(expected: &mut Value) {
+    if let Some(Value::Object(headers)) = expected.get_mut("headers") {
+        *headers = std::mem::take(headers)
+            .into_iter()
+            .map(|(name, value)| (name.to_ascii_lowercase(), value))
+            .collect();
+    }
+}
+
+fn http_request<T>(message: &Message, body: T) -> Result<http::Request<T>, BoxError> {
+    let mut request = http::Request::builder()
+        .method(http::Method::POST)
+        .uri(message.uri.as_deref().unwrap_or(DEFAULT_URI))
+        .body(body)?;
+    *request.headers_mut() = header_map(&message.headers)?;
+    Ok(request)
+}
+
+fn header_map(headers: &Map<String, Value>) -> Result<HeaderMap, BoxError> {
+    let mut header_map = HeaderMap::new();
+    for (name, values) in headers {
+        let name = HeaderName::from_str(name)?;
+        let values = match values {
+            Value::Array(values) => values.iter().collect(),
+            value => vec![value],
+        };
+        for value in values {
+            let value = match value {
+                Value::String(value) => HeaderValue::from_str(value)?,
+                value => HeaderValue::from_str(&value.to_string())?,
+            };
+            header_map.append(name.clone(), value);
+        }
+    }
+    Ok(header_map)
+}
+
+fn request_context(message: &Message) -> Result<Context, BoxError> {
+    let context = Context::new();
+    for (key, value) in &message.context {
+        context.insert(key, value.clone())?;
+    }
+    Ok(context)
+}
+
+fn status(message: &Message) -> Result<StatusCode, BoxError> {
+    Ok(StatusCode::from_u16(message.status.unwrap_or(200))?)
+}
+
+fn graphql_request(message: &Message) -> Result<graphql::Request, BoxError> {
+    Ok(serde_json::from_value(
+        message
+            .body
+            .clone()
+            .unwrap_or_else(|| Value::Object(Map::new())),
+    )?)
+}
+
+fn graphql_response(message: &Message) -> Result<graphql::Response, BoxError> {
+    Ok(serde_json::from_value(
+        message
+            .body
+            .clone()
+            .unwrap_or_else(|| Value::Object(Map::new())),
+    )?)
+}
+
+// raw bodies are sent as is when they are strings, as JSON otherwise
+fn raw_body(body: Option<&Value>) -> Result<Vec<u8>, BoxError> {
+    Ok(match body {
+        None => Vec::new(),
+        Some(Value::String(body)) => body.as_bytes().to_vec(),
+        Some(body) => serde_json::to_vec(body)?,
+    })
+}
+
+fn parse_body(body: &[u8]) -> Value {
+    if body.is_empty() {
+        return Value::Null;
+    }
+    serde_json::from_slice(body)
+        .unwrap_or_else(|_| String::from_utf8_lossy(body).into_owned().into())
+}
@@ -6,6 +6,7 @@ use std::time::Duration;
 
 use bytes::Buf;
 use futures::future::BoxFuture;
+use futures::FutureExt;
 use hmac::Hmac;
 use hmac::Mac;
 use http::HeaderName;
@@ -30,6 +31,7 @@ use crate::context::Context;
 use crate::graphql;
 use crate::graphql::Response;
 use crate::json_ext::Object;
+use crate::layers::async_checkpoint::OneShotAsyncCheckpointLayer;
 use crate::layers::ServiceBuilderExt;
 use crate::notification::Notify;
 use crate::plugin::Plugin;
@@ -42,6 +44,17 @@ use crate::services::subgraph;
 use crate::Endpoint;
 use crate::ListenAddr;
 
+mod lifecycle;
+mod redis_streams;
+
+pub(crate) use self::lifecycle::ClientSubscriptionGuard;
+pub(crate) use self::lifecycle::ClientSubscriptionLimit;
+pub(crate) use self::lifecycle::SlowConsumerPolicy;
+pub(crate) use self::lifecycle::TerminationReason;
+pub(crate) use self::redis_streams::RedisStreamsMode;
+use self::redis_streams::RedisStreamsSource;
+pub(crate) use self::redis_streams::RedisStreamsSubgraphConfiguration;
+
 type HmacSha256 = Hmac<sha2::Sha256>;
 pub(crate) const APOLLO_SUBSCRIPTION_PLUGIN: &str = "apollo.subscription";
 #[cfg(not(test))]
@@ -56,6 +69,7 @@ const CALLBACK_SUBSCRIPTION_HEADER_VALUE: &str = "callback/1.0";
 pub(crate) struct Subscription {
     notify: Notify<String, graphql::Response>,
     callback_hmac_key: Option<String>,
+    redis_streams: Option<RedisStreamsSource>,
     pub(crate) config: SubscriptionConfig,
 }
 
@@ -74,6 +88,18 @@ pub(crate) struct SubscriptionConfig {
     pub(crate) max_opened_subscriptions: Option<usize>,
     /// It represent the capacity of the in memory queue to know how many events we can keep in a buffer
     pub(crate) queue_capacity: Option<usize>,
+    /// Limit the number of subscriptions opened at the same time by each client. By default if it's not set there is no limit.
+    pub(crate) max_opened_subscriptions_per_client: Option<ClientSubscriptionLimit>,
+    /// Close subscriptions which have been opened for longer than this duration. By default if it's not set there is no limit.
+    #[serde(with = "humantime_serde")]
+    #[schemars(with = "Option<String>", default)]
+    pub(crate) max_lifetime: Option<Duration>,
+    /// Close subscriptions which did not receive any event during this duration. By default if it's not set there is no timeout.
+    #[serde(with = "humantime_serde")]
+    #[schemars(with = "Option<String>", default)]
+    pub(crate) idle_timeout: Option<Duration>,
+    /// What to do when the queue of events for a client is full, possible values are: 'wait' | 'drop_events' | 'close' (default: wait)
+    pub(crate) slow_consumer: SlowConsumerPolicy,
 }
 
 impl Default for SubscriptionConfig {
@@ -84,6 +110,10 @@ impl Default for SubscriptionConfig {
             enable_deduplication: true,
             max_opened_subscriptions: None,
             queue_capacity: None,
+            max_opened_subscriptions_per_client: None,
+            max_lifetime: None,
+            idle_timeout: None,
+            slow_consumer: SlowConsumerPolicy::default(),
         }
     }
 }
@@ -95,10 +125,18 @@ pub(crate) struct SubscriptionModeConfig {
     pub(crate) callback: Option<CallbackMode>,
     /// Enable passthrough mode for subgraph(s)
     pub(crate) passthrough: Option<SubgraphPassthroughMode>,
+    /// Read subscription events from Redis Streams instead of subgraph(s) (takes precedence over other modes)
+    pub(crate) redis_streams: Option<RedisStreamsMode>,
 }
 
 impl SubscriptionModeConfig {
     pub(crate) fn get_subgraph_config(&self, service_name: &str) -> Option<SubscriptionMode> {
+        if let Some(redis_streams_cfg) = &self.redis_streams {
+            if let Some(subgraph_cfg) = redis_streams_cfg.subgraphs.get(service_name) {
+                return SubscriptionMode::RedisStreams(subgraph_cfg.clone()).into();
+            }
+        }
+
         if let Some(passthrough_cfg) = &self.passthrough {
             if let Some(subgraph_cfg) = passthrough_cfg.subgraphs.get(service_name) {
                 return SubscriptionMode::Passthrough(subgraph_cfg.clone()).into();
@@ -140,6 +178,8 @@ pub(crate) enum SubscriptionMode {
     Callback(CallbackMode),
     /// Using websocket to directly connect to subgraph
     Passthrough(WebSocketConfiguration),
+    /// Using Redis Streams as the source of events
+    RedisStreams(RedisStreamsSubgraphConfiguration),
 }
 
 /// Using a callback url
@@ -205,6 +245,60 @@ pub(crate) struct WebSocketConfiguration {
     pub(crate) path: Option<String>,
     /// Which WebSocket GraphQL protocol to use for this subgraph possible values are: 'graphql_ws' | 'graphql_transport_ws' (default: graphql_ws)
     pub(crate) protocol: WebSocketProtocol,
+    /// Interval at which the router sends a ping to the subgraph to keep the connection alive, only supported by the 'graphql_ws' protocol (default: disabled)
+    #[serde(with = "humantime_serde")]
+    #[schemars(with = "Option<String>", default)]
+    pub(crate) ping_interval: Option<Duration>,
+    /// Consider the connection lost if nothing has been received from the subgraph during this period, pongs and keep alive messages included (default: disabled)
+    #[serde(with = "humantime_serde")]
+    #[schemars(with = "Option<String>", default)]
+    pub(crate) idle_timeout: Option<Duration>,
+    /// Reconnect to the subgraph and resubscribe when the connection is lost
+    pub(crate) reconnect: WebSocketReconnectConfiguration,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields, default)]
+/// Reconnection configuration for WebSocket connections to a subgraph
+pub(crate) struct WebSocketReconnectConfiguration {
+    /// Reconnect and resubscribe with the same payload when the connection to the subgraph is lost, keeping the client subscription opened (default: false)
+    pub(crate) enabled: bool,
+    /// Delay before the first reconnection attempt, doubled after each failed attempt (default: 100ms)
+    #[serde(with = "humantime_serde")]
+    #[schemars(with = "String")]
+    pub(crate) initial_backoff: Duration,
+    /// Maximum delay between two reconnection attempts (default: 10s)
+    #[serde(with = "humantime_serde")]
+    #[schemars(with = "String")]
+    pub(crate) max_backoff: Duration,
+    /// Stop trying to reconnect and close the client subscription if the connection cannot be re-established within this period (default: 60s)
+    #[serde(with = "humantime_serde")]
+    #[schemars(with = "String")]
+    pub(crate) max_retry_window: Duration,
+    /// Send an event with a `SUBSCRIPTION_RECONNECTING` extension to the client before each reconnection attempt (default: false)
+    pub(crate) notify_client: bool,
+}
+
+impl Default for WebSocketReconnectConfiguration {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+            max_retry_window: Duration::from_secs(60),
+            notify_client: false,
+        }
+    }
+}
+
+impl WebSocketReconnectConfiguration {
+    /// Delay to wait before the given reconnection attempt (starting at 0)
+    pub(crate) fn backoff(&self, attempt: u32) -> Duration {
+        self.initial_backoff
+            .checked_mul(2u32.saturating_pow(attempt))
+            .unwrap_or(self.max_backoff)
+            .min(self.max_backoff)
+    }
 }
 
 fn default_path() -> String {
@@ -245,20 +339,35 @@ impl Plugin for Subscription {
             }
         }
 
+        let redis_streams = match &init.config.mode.redis_streams {
+            Some(redis_streams_cfg) if init.config.enabled => {
+                Some(RedisStreamsSource::new(redis_streams_cfg, init.notify.clone()).await?)
+            }
+            _ => None,
+        };
+
         Ok(Subscription {
             notify: init.notify,
             callback_hmac_key,
+            redis_streams,
             config: init.config,
         })
     }
 
     fn subgraph_service(
         &self,
-        _subgraph_name: &str,
+        subgraph_name: &str,
         service: subgraph::BoxService,
     ) -> subgraph::BoxService {
         let enabled = self.config.enabled
-            && (self.config.mode.callback.is_some() || self.config.mode.passthrough.is_some());
+            && (self.config.mode.callback.is_some()
+                || self.config.mode.passthrough.is_some()
+                || self.config.mode.redis_streams.is_some());
+        let redis_streams = self
+            .redis_streams
+            .clone()
+            .filter(|source| source.handles(subgraph_name));
+        let subgraph_name = subgraph_name.to_string();
         ServiceBuilder::new()
             .checkpoint(move |req: subgraph::Request| {
                 if req.operation_kind == OperationKind::Subscription && !enabled {
@@ -266,7 +375,27 @@ impl Plugin for Subscription {
                 } else {
                     Ok(ControlFlow::Continue(req))
                 }
-            }).service(service)
+            })
+            .option_layer(redis_streams.map(|source| {
+                // Subscriptions on this subgraph are served from redis streams, the subgraph is never called
+                OneShotAsyncCheckpointLayer::new(move |req: subgraph::Request| {
+                    let source = source.clone();
+                    let subgraph_name = subgraph_name.clone();
+                    async move {
+                        if req.operation_kind == OperationKind::Subscription
+                            && req.subscription_stream.is_some()
+                        {
+                            Ok(ControlFlow::Break(
+                                source.subscribe(&subgraph_name, req).await?,
+                            ))
+                        } else {
+                            Ok(ControlFlow::Continue(req))
+                        }
+                    }
+                    .boxed()
+                })
+            }))
+            .service(service)
             .boxed()
     }
 
@@ -1341,6 +1470,67 @@ mod tests {
         assert!(sub_config.max_opened_subscriptions.is_none());
         assert!(sub_config.queue_capacity.is_none());
     }
+
+    #[test]
+    fn it_test_websocket_reconnect_config() {
+        let ws_config: WebSocketConfiguration = serde_json::from_value(serde_json::json!({
+            "path": "/ws",
+            "ping_interval": "15s",
+            "idle_timeout": "1m",
+            "reconnect": {
+                "enabled": true,
+                "initial_backoff": "200ms",
+                "max_backoff": "1s"
+            }
+        }))
+        .unwrap();
+
+        assert_eq!(ws_config.ping_interval, Some(Duration::from_secs(15)));
+        assert_eq!(ws_config.idle_timeout, Some(Duration::from_secs(60)));
+        assert!(ws_config.reconnect.enabled);
+        assert!(!ws_config.reconnect.notify_client);
+        assert_eq!(
+            ws_config.reconnect.max_retry_window,
+            Duration::from_secs(60)
+        );
+        assert_eq!(ws_config.reconnect.backoff(0), Duration::from_millis(200));
+        assert_eq!(ws_config.reconnect.backoff(1), Duration::from_millis(400));
+        assert_eq!(ws_config.reconnect.backoff(2), Duration::from_millis(800));
+        assert_eq!(ws_config.reconnect.backoff(3), Duration::from_secs(1));
+        assert_eq!(ws_config.reconnect.backoff(64), Duration::from_secs(1));
+
+        let default_config: WebSocketConfiguration =
+            serde_json::from_value(serde_json::json!({})).unwrap();
+        assert!(default_config.ping_interval.is_none());
+        assert!(default_config.idle_timeout.is_none());
+        assert!(!default_config.reconnect.enabled);
+    }
+
+    #[test]
+    fn it_test_subscription_lifecycle_config() {
+        let config: SubscriptionConfig = serde_json::from_value(serde_json::json!({
+            "max_opened_subscriptions_per_client": {
+                "client_id": { "header": "x-client-id" },
+                "max_opened_subscriptions": 5
+            },
+            "max_lifetime": "1h",
+            "idle_timeout": "5m",
+            "slow_consumer": "drop_events"
+        }))
+        .unwrap();
+
+        let per_client = config.max_opened_subscriptions_per_client.unwrap();
+        assert_eq!(per_client.max_opened_subscriptions, 5);
+        assert_eq!(config.max_lifetime, Some(Duration::from_secs(3600)));
+        assert_eq!(config.idle_timeout, Some(Duration::from_secs(300)));
+        assert_eq!(config.slow_consumer, SlowConsumerPolicy::DropEvents);
+
+        let default_config = SubscriptionConfig::default();
+        assert!(default_config.max_opened_subscriptions_per_client.is_none());
+        assert!(default_config.max_lifetime.is_none());
+        assert!(default_config.idle_timeout.is_none());
+        assert_eq!(default_config.slow_consumer, SlowConsumerPolicy::Wait);
+    }
 }
 
 register_plugin!("apollo", "subscription", Subscription);
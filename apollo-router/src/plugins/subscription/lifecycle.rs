@@ -0,0 +1,300 @@
+//! Limits and lifecycle controls applied to client subscriptions
+//!
+//! Subscriptions can be limited per client, closed after a maximum lifetime or when no event was received for
+//! a while, and closed or thinned out when the client does not read events fast enough. Whatever the reason, the
+//! router reports why a subscription ended in its final payload and in metrics.
+
+use std::sync::Arc;
+
+use dashmap::DashMap;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json_bytes::Value;
+
+use crate::context::Context;
+use crate::graphql;
+use crate::plugins::authentication::APOLLO_AUTHENTICATION_JWT_CLAIMS;
+
+/// Limit of subscriptions opened at the same time by a single client
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ClientSubscriptionLimit {
+    /// How to identify the client, subscriptions from requests without a client identifier are not limited
+    pub(crate) client_id: ClientIdentifier,
+    /// Maximum number of subscriptions a client can have opened at the same time
+    pub(crate) max_opened_subscriptions: usize,
+    /// Subscriptions opened by each client, created with the configuration of a subscription plugin instance and
+    /// shared by its clones, so each plugin instance counts its own subscriptions
+    #[serde(skip)]
+    #[schemars(skip)]
+    pub(crate) opened_subscriptions: Arc<DashMap<String, usize>>,
+}
+
+/// Where to find the client identifier
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "snake_case")]
+pub(crate) enum ClientIdentifier {
+    /// Name of the request header containing the client identifier
+    Header(String),
+    /// Name of the JWT claim containing the client identifier (requires the JWT authentication plugin)
+    Claim(String),
+}
+
+/// What to do with a subscription when the client's queue of events is full
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum SlowConsumerPolicy {
+    /// Wait for the client to read its queued events before processing the next one
+    #[default]
+    Wait,
+    /// Drop the events received while the queue is full
+    DropEvents,
+    /// Close the subscription
+    Close,
+}
+
+impl ClientSubscriptionLimit {
+    /// Identifier of the client sending this request, if any
+    pub(crate) fn client_id(
+        &self,
+        request: &http::Request<graphql::Request>,
+        context: &Context,
+    ) -> Option<String> {
+        match &self.client_id {
+            ClientIdentifier::Header(name) => request
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.to_string()),
+            ClientIdentifier::Claim(name) => {
+                let claims = context
+                    .get::<_, Value>(APOLLO_AUTHENTICATION_JWT_CLAIMS)
+                    .ok()
+                    .flatten()?;
+                match claims.as_object()?.get(name.as_str())? {
+                    Value::String(value) => Some(value.as_str().to_string()),
+                    Value::Number(value) => Some(value.to_string()),
+                    _ => None,
+                }
+            }
+        }
+    }
+
+    /// Counts a new subscription for the client, or returns `None` if the client reached its limit
+    ///
+    /// The counter is checked and incremented while holding the lock of its entry, so concurrent requests of a
+    /// client cannot go over the limit.
+    pub(crate) fn try_reserve(&self, client_id: String) -> Option<ClientSubscriptionGuard> {
+        {
+            let mut opened = self
+                .opened_subscriptions
+                .entry(client_id.clone())
+                .or_insert(0);
+            if *opened < self.max_opened_subscriptions {
+                *opened += 1;
+                return Some(ClientSubscriptionGuard {
+                    client_id,
+                    opened_subscriptions: self.opened_subscriptions.clone(),
+                });
+            }
+        }
+        self.opened_subscriptions
+            .remove_if(&client_id, |_, opened| *opened == 0);
+        None
+    }
+}
+
+/// Counts a subscription as opened by a client until it is dropped
+pub(crate) struct ClientSubscriptionGuard {
+    client_id: String,
+    opened_subscriptions: Arc<DashMap<String, usize>>,
+}
+
+impl Drop for ClientSubscriptionGuard {
+    fn drop(&mut self) {
+        if let Some(mut opened) = self.opened_subscriptions.get_mut(&self.client_id) {
+            *opened = opened.saturating_sub(1);
+        }
+        self.opened_subscriptions
+            .remove_if(&self.client_id, |_, opened| *opened == 0);
+    }
+}
+
+/// Why a subscription ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TerminationReason {
+    /// The event source completed the subscription
+    Completed,
+    /// The client went away
+    ClientClosed,
+    JwtExpired,
+    SchemaReload,
+    /// The new configuration could not be applied to the subscription
+    ConfigurationReload,
+    MaxLifetime,
+    IdleTimeout,
+    SlowConsumer,
+}
+
+impl TerminationReason {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            TerminationReason::Completed => "completed",
+            TerminationReason::ClientClosed => "client_closed",
+            TerminationReason::JwtExpired => "jwt_expired",
+            TerminationReason::SchemaReload => "schema_reload",
+            TerminationReason::ConfigurationReload => "configuration_reload",
+            TerminationReason::MaxLifetime => "max_lifetime",
+            TerminationReason::IdleTimeout => "idle_timeout",
+            TerminationReason::SlowConsumer => "slow_consumer",
+        }
+    }
+
+    /// Final payload telling the client why the router closed its subscription
+    pub(crate) fn response(&self) -> Option<graphql::Response> {
+        let (message, code) = match self {
+            TerminationReason::Completed
+            | TerminationReason::ClientClosed
+            | TerminationReason::ConfigurationReload => return None,
+            TerminationReason::JwtExpired => (
+                "subscription closed because the JWT has expired",
+                "SUBSCRIPTION_JWT_EXPIRED",
+            ),
+            TerminationReason::SchemaReload => (
+                "subscription has been closed due to a schema reload",
+                "SUBSCRIPTION_SCHEMA_RELOAD",
+            ),
+            TerminationReason::MaxLifetime => (
+                "subscription closed because it reached its maximum lifetime",
+                "SUBSCRIPTION_MAX_LIFETIME",
+            ),
+            TerminationReason::IdleTimeout => (
+                "subscription closed because no event was received before the idle timeout",
+                "SUBSCRIPTION_IDLE_TIMEOUT",
+            ),
+            TerminationReason::SlowConsumer => (
+                "subscription closed because the client did not read events fast enough",
+                "SUBSCRIPTION_SLOW_CONSUMER",
+            ),
+        };
+
+        Some(
+            graphql::Response::builder()
+                .subscribed(false)
+                .error(
+                    graphql::Error::builder()
+                        .message(message)
+                        .extension_code(code)
+                        .build(),
+                )
+                .build(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::Barrier;
+
+    use super::*;
+
+    #[test]
+    fn per_client_counter() {
+        let limit = ClientSubscriptionLimit {
+            client_id: ClientIdentifier::Header("x-client-id".to_string()),
+            max_opened_subscriptions: 2,
+            opened_subscriptions: Default::default(),
+        };
+        let request = http::Request::builder()
+            .header("x-client-id", "per_client_counter")
+            .body(graphql::Request::default())
+            .unwrap();
+        let client_id = limit.client_id(&request, &Context::new()).unwrap();
+        assert_eq!(client_id, "per_client_counter");
+
+        let first = limit.try_reserve(client_id.clone()).unwrap();
+        let second = limit.try_reserve(client_id.clone()).unwrap();
+        assert!(limit.try_reserve(client_id.clone()).is_none());
+        drop(first);
+        let third = limit.try_reserve(client_id.clone()).unwrap();
+        drop(second);
+        drop(third);
+        assert!(!limit.opened_subscriptions.contains_key(&client_id));
+
+        let no_subscription = ClientSubscriptionLimit {
+            client_id: ClientIdentifier::Header("x-client-id".to_string()),
+            max_opened_subscriptions: 0,
+            opened_subscriptions: Default::default(),
+        };
+        assert!(no_subscription.try_reserve(client_id.clone()).is_none());
+        assert!(!no_subscription
+            .opened_subscriptions
+            .contains_key(&client_id));
+    }
+
+    #[test]
+    fn per_client_counter_per_plugin_instance() {
+        let config = serde_json::json!({
+            "client_id": { "header": "x-client-id" },
+            "max_opened_subscriptions": 1
+        });
+        let first: ClientSubscriptionLimit = serde_json::from_value(config.clone()).unwrap();
+        let second: ClientSubscriptionLimit = serde_json::from_value(config).unwrap();
+
+        let _guard = first.try_reserve("client".to_string()).unwrap();
+        assert!(first.clone().try_reserve("client".to_string()).is_none());
+        assert!(second.try_reserve("client".to_string()).is_some());
+    }
+
+    #[test]
+    fn per_client_counter_concurrent_reservations() {
+        let limit = Arc::new(ClientSubscriptionLimit {
+            client_id: ClientIdentifier::Header("x-client-id".to_string()),
+            max_opened_subscriptions: 3,
+            opened_subscriptions: Default::default(),
+        });
+        let barrier = Arc::new(Barrier::new(16));
+        let reservations: Vec<_> = (0..16)
+            .map(|_| {
+                let limit = limit.clone();
+                let barrier = barrier.clone();
+                std::thread::spawn(move || {
+                    barrier.wait();
+                    limit.try_reserve("per_client_counter_concurrent_reservations".to_string())
+                })
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|thread| thread.join().unwrap())
+            .collect();
+
+        assert_eq!(reservations.iter().flatten().count(), 3);
+        drop(reservations);
+        assert!(limit.opened_subscriptions.is_empty());
+    }
+
+    #[test]
+    fn client_id_from_claim() {
+        let limit = ClientSubscriptionLimit {
+            client_id: ClientIdentifier::Claim("sub".to_string()),
+            max_opened_subscriptions: 1,
+            opened_subscriptions: Default::default(),
+        };
+        let request = http::Request::new(graphql::Request::default());
+        let context = Context::new();
+        assert_eq!(limit.client_id(&request, &context), None);
+
+        context
+            .insert(
+                APOLLO_AUTHENTICATION_JWT_CLAIMS,
+                serde_json_bytes::json!({ "sub": "client" }),
+            )
+            .unwrap();
+        assert_eq!(
+            limit.client_id(&request, &context),
+            Some("client".to_string())
+        );
+    }
+}
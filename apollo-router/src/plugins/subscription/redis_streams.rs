@@ -0,0 +1,812 @@
+//! Subscription events read from Redis Streams instead of a subgraph
+//!
+//! A subscription root field is mapped to a stream key, built from a template using the field's arguments.
+//! Each entry added to the stream becomes a subscription event which then goes through the rest of the query plan.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::hash::Hasher;
+use std::sync::Arc;
+use std::time::Duration;
+
+use apollo_compiler::ast;
+use futures::SinkExt;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json_bytes::Value;
+use sha2::Digest;
+use sha2::Sha256;
+use tokio::sync::mpsc;
+use tower::BoxError;
+
+use crate::cache::redis::RedisCacheStorage;
+use crate::configuration::RedisCache;
+use crate::graphql;
+use crate::json_ext::Object;
+use crate::notification::HandleSink;
+use crate::services::subgraph;
+use crate::spec::query::parse_hir_value;
+use crate::Notify;
+
+const EVENT_SOURCE_ERROR_CODE: &str = "SUBSCRIPTION_EVENT_SOURCE_ERROR";
+/// Subscriptions waiting to be added to a stream reader
+const READER_QUEUE_CAPACITY: usize = 128;
+
+/// Using Redis Streams as the source of subscription events
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct RedisStreamsMode {
+    /// Redis configuration
+    pub(crate) redis: RedisCache,
+
+    /// Maximum time a read of a stream waits for new entries, before checking that the subscription is still open (default: 1s)
+    #[serde(with = "humantime_serde", default = "default_block_timeout")]
+    #[schemars(with = "String", default = "default_block_timeout")]
+    pub(crate) block_timeout: Duration,
+
+    /// Maximum number of entries read from a stream at once (default: 100)
+    #[serde(default = "default_batch_size")]
+    pub(crate) batch_size: u64,
+
+    /// Number of connections reading the streams. Each one reads many streams with a single blocking command (default: 4)
+    #[serde(default = "default_readers")]
+    pub(crate) readers: usize,
+
+    /// Subscription fields backed by a Redis stream, per subgraph
+    #[serde(default)]
+    pub(crate) subgraphs: HashMap<String, RedisStreamsSubgraphConfiguration>,
+}
+
+/// Redis Streams configuration for a specific subgraph
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct RedisStreamsSubgraphConfiguration {
+    /// Subscription root fields mapped to a stream key template. Arguments of the field can be used in the template with `{argument_name}` (example: `orders:{orderId}`)
+    pub(crate) fields: HashMap<String, String>,
+
+    /// Name of the stream entry field containing the JSON payload of the event (default: payload)
+    #[serde(default = "default_payload_field")]
+    pub(crate) payload_field: String,
+}
+
+fn default_block_timeout() -> Duration {
+    Duration::from_secs(1)
+}
+
+fn default_batch_size() -> u64 {
+    100
+}
+
+fn default_readers() -> usize {
+    4
+}
+
+fn default_payload_field() -> String {
+    String::from("payload")
+}
+
+/// Consumes Redis streams and publishes their entries as subscription events
+#[derive(Clone)]
+pub(crate) struct RedisStreamsSource {
+    storage: RedisCacheStorage,
+    notify: Notify<String, graphql::Response>,
+    /// Stream readers, a stream is always read by the same one
+    readers: Arc<Vec<mpsc::Sender<StreamSubscriber>>>,
+    subgraphs: Arc<HashMap<String, RedisStreamsSubgraphConfiguration>>,
+}
+
+impl std::fmt::Debug for RedisStreamsSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RedisStreamsSource")
+            .field("readers", &self.readers.len())
+            .field("subgraphs", &self.subgraphs)
+            .finish()
+    }
+}
+
+/// The subscription root field and the stream it reads from
+#[derive(Debug, PartialEq)]
+struct StreamSubscription {
+    response_key: String,
+    stream_key: String,
+}
+
+impl RedisStreamsSource {
+    pub(crate) async fn new(
+        config: &RedisStreamsMode,
+        notify: Notify<String, graphql::Response>,
+    ) -> Result<Self, BoxError> {
+        let storage = RedisCacheStorage::new(config.redis.clone()).await?;
+
+        Ok(Self::with_storage(storage, notify, config))
+    }
+
+    /// Spawns the stream readers, they stop once the source is dropped and their subscriptions have ended
+    fn with_storage(
+        storage: RedisCacheStorage,
+        notify: Notify<String, graphql::Response>,
+        config: &RedisStreamsMode,
+    ) -> Self {
+        let readers = (0..config.readers.max(1))
+            .map(|_| {
+                let (sender, receiver) = mpsc::channel(READER_QUEUE_CAPACITY);
+                tokio::task::spawn(
+                    StreamReader {
+                        storage: storage.clone(),
+                        connection: None,
+                        notify: notify.clone(),
+                        block_timeout: config.block_timeout,
+                        batch_size: config.batch_size,
+                        streams: HashMap::new(),
+                    }
+                    .run(receiver),
+                );
+                sender
+            })
+            .collect();
+
+        Self {
+            storage,
+            notify,
+            readers: Arc::new(readers),
+            subgraphs: Arc::new(config.subgraphs.clone()),
+        }
+    }
+
+    pub(crate) fn handles(&self, subgraph_name: &str) -> bool {
+        self.subgraphs.contains_key(subgraph_name)
+    }
+
+    /// Subscribe to the stream matching the subscription root field, instead of calling the subgraph
+    pub(crate) async fn subscribe(
+        &self,
+        subgraph_name: &str,
+        request: subgraph::Request,
+    ) -> Result<subgraph::Response, BoxError> {
+        let subgraph_cfg = self
+            .subgraphs
+            .get(subgraph_name)
+            .ok_or_else(|| format!("subgraph {subgraph_name:?} is not backed by redis streams"))?;
+        let stream_tx = request
+            .subscription_stream
+            .clone()
+            .ok_or("cannot get the subscription stream")?;
+
+        let body = request.subgraph_request.body();
+        let subscription = match stream_subscription(
+            body.query.as_deref().unwrap_or_default(),
+            &body.variables,
+            &subgraph_cfg.fields,
+        ) {
+            Ok(subscription) => subscription,
+            Err(message) => {
+                return Ok(subgraph::Response::builder()
+                    .context(request.context)
+                    .error(
+                        graphql::Error::builder()
+                            .message(message)
+                            .extension_code(EVENT_SOURCE_ERROR_CODE)
+                            .build(),
+                    )
+                    .extensions(Object::default())
+                    .build());
+            }
+        };
+
+        // Deduplicate subscriptions reading the same stream with the same operation
+        let mut hasher = Sha256::new();
+        hasher.update(subscription.stream_key.as_bytes());
+        hasher.update(body.query.as_deref().unwrap_or_default().as_bytes());
+        hasher.update(serde_json::to_vec(&body.variables)?);
+        let topic = format!("redis_stream:{}", hex::encode(hasher.finalize()));
+
+        let mut notify = self.notify.clone();
+        let (handle, created) = notify.create_or_subscribe(topic.clone(), false).await?;
+        tracing::info!(
+            monotonic_counter.apollo.router.operations.subscriptions = 1u64,
+            subscriptions.mode = %"redis_streams",
+            subscriptions.deduplicated = !created,
+            subgraph.service.name = subgraph_name,
+        );
+
+        if created {
+            let (sink, stream) = handle.split();
+            self.read_stream(
+                topic,
+                subscription,
+                subgraph_cfg.payload_field.clone(),
+                sink,
+            )
+            .await;
+            stream_tx.send(Box::pin(stream)).await?;
+        } else {
+            tracing::info!(
+                monotonic_counter.apollo_router_deduplicated_subscriptions_total = 1u64,
+                mode = %"redis_streams",
+            );
+            stream_tx.send(Box::pin(handle.into_stream())).await?;
+        }
+
+        Ok(subgraph::Response::builder()
+            .context(request.context)
+            .extensions(Object::default())
+            .build())
+    }
+
+    /// Hand the subscription to the reader of its stream
+    async fn read_stream(
+        &self,
+        topic: String,
+        subscription: StreamSubscription,
+        payload_field: String,
+        sink: HandleSink<String, graphql::Response>,
+    ) {
+        let StreamSubscription {
+            response_key,
+            stream_key,
+        } = subscription;
+
+        // Only entries added after the subscription was created are sent
+        let last_id = match self.storage.last_stream_id(stream_key.clone()).await {
+            Ok(last_id) => last_id.unwrap_or_else(|| String::from("0-0")),
+            Err(err) => {
+                tracing::error!("cannot read redis stream {stream_key:?}: {err}");
+                StreamSubscriber::end_with_error(sink, &stream_key).await;
+                return;
+            }
+        };
+
+        let mut hasher = DefaultHasher::new();
+        stream_key.hash(&mut hasher);
+        let reader = &self.readers[hasher.finish() as usize % self.readers.len()];
+        let subscriber = StreamSubscriber {
+            topic,
+            stream_key,
+            response_key,
+            payload_field,
+            last_id,
+            ended: false,
+            sink,
+        };
+        if let Err(mpsc::error::SendError(subscriber)) = reader.send(subscriber).await {
+            tracing::error!(
+                "cannot read redis stream {:?}: the reader stopped",
+                subscriber.stream_key
+            );
+            let stream_key = subscriber.stream_key.clone();
+            StreamSubscriber::end_with_error(subscriber.sink, &stream_key).await;
+        }
+    }
+}
+
+/// A subscription to a stream, read by a stream reader
+struct StreamSubscriber {
+    topic: String,
+    stream_key: String,
+    response_key: String,
+    payload_field: String,
+    /// Id of the last entry sent to the subscription
+    last_id: String,
+    ended: bool,
+    sink: HandleSink<String, graphql::Response>,
+}
+
+impl StreamSubscriber {
+    async fn end_with_error(mut sink: HandleSink<String, graphql::Response>, stream_key: &str) {
+        let _ = sink
+            .send(source_error(format!(
+                "cannot read the redis stream {stream_key:?}"
+            )))
+            .await;
+        Self::close(sink).await;
+    }
+
+    async fn close(mut sink: HandleSink<String, graphql::Response>) {
+        if let Err(err) = sink.close().await {
+            tracing::trace!("cannot close the subscription handle: {err:?}");
+        }
+    }
+}
+
+/// Reads all the streams of its subscriptions with one blocking command at a time, on its own connection
+///
+/// New subscriptions are added when the current read ends, after at most `block_timeout`.
+struct StreamReader {
+    storage: RedisCacheStorage,
+    /// Blocking reads get their own connection, so they don't delay the other commands
+    connection: Option<RedisCacheStorage>,
+    notify: Notify<String, graphql::Response>,
+    block_timeout: Duration,
+    batch_size: u64,
+    /// Subscriptions, per stream key
+    streams: HashMap<String, Vec<StreamSubscriber>>,
+}
+
+impl StreamReader {
+    async fn run(mut self, mut receiver: mpsc::Receiver<StreamSubscriber>) {
+        loop {
+            if self.streams.is_empty() {
+                match receiver.recv().await {
+                    Some(subscriber) => self.add(subscriber),
+                    None => break,
+                }
+            }
+            while let Ok(subscriber) = receiver.try_recv() {
+                self.add(subscriber);
+            }
+
+            self.read().await;
+            self.remove_ended_subscriptions().await;
+        }
+
+        if let Some(connection) = self.connection.take() {
+            connection.quit().await;
+        }
+    }
+
+    fn add(&mut self, subscriber: StreamSubscriber) {
+        self.streams
+            .entry(subscriber.stream_key.clone())
+            .or_default()
+            .push(subscriber);
+    }
+
+    async fn read(&mut self) {
+        if self.connection.is_none() {
+            match self.storage.with_new_connection().await {
+                Ok(connection) => self.connection = Some(connection),
+                Err(err) => {
+                    tracing::error!("cannot connect to redis to read streams: {err}");
+                    for (stream_key, subscribers) in self.streams.drain() {
+                        for subscriber in subscribers {
+                            StreamSubscriber::end_with_error(subscriber.sink, &stream_key).await;
+                        }
+                    }
+                    return;
+                }
+            }
+        }
+        let Some(connection) = &self.connection else {
+            return;
+        };
+
+        // each stream is read from the oldest entry one of its subscriptions is waiting for
+        let streams = self
+            .streams
+            .iter()
+            .filter_map(|(stream_key, subscribers)| {
+                subscribers
+                    .iter()
+                    .map(|subscriber| &subscriber.last_id)
+                    .min_by_key(|last_id| stream_id(last_id))
+                    .map(|last_id| (stream_key.clone(), last_id.clone()))
+            })
+            .collect();
+
+        let entries = match connection
+            .read_streams(streams, self.batch_size, self.block_timeout)
+            .await
+        {
+            Ok(entries) => entries,
+            Err(err) => {
+                tracing::error!("cannot read redis streams: {err}");
+                tokio::time::sleep(self.block_timeout).await;
+                return;
+            }
+        };
+
+        for (stream_key, entries) in entries {
+            let Some(subscribers) = self.streams.get_mut(&stream_key) else {
+                continue;
+            };
+            for (id, fields) in entries {
+                for subscriber in subscribers.iter_mut() {
+                    if subscriber.ended || stream_id(&id) <= stream_id(&subscriber.last_id) {
+                        continue;
+                    }
+                    subscriber.last_id = id.clone();
+                    let event =
+                        stream_event(&subscriber.response_key, &subscriber.payload_field, &fields);
+                    tracing::info!(
+                        monotonic_counter
+                            .apollo
+                            .router
+                            .operations
+                            .subscriptions
+                            .events = 1u64,
+                        subscriptions.mode = "redis_streams"
+                    );
+                    subscriber.ended = subscriber.sink.send(event).await.is_err();
+                }
+            }
+        }
+    }
+
+    /// Stop reading for the subscriptions nobody listens to anymore
+    async fn remove_ended_subscriptions(&mut self) {
+        for subscribers in self.streams.values_mut() {
+            let mut index = 0;
+            while index < subscribers.len() {
+                let subscriber = &subscribers[index];
+                if !subscriber.ended
+                    && matches!(self.notify.exist(subscriber.topic.clone()).await, Ok(true))
+                {
+                    index += 1;
+                } else {
+                    StreamSubscriber::close(subscribers.swap_remove(index).sink).await;
+                }
+            }
+        }
+        self.streams
+            .retain(|_, subscribers| !subscribers.is_empty());
+    }
+}
+
+/// Stream entry ids are made of a millisecond timestamp and a sequence number
+fn stream_id(id: &str) -> (u64, u64) {
+    let (milliseconds, sequence) = id.split_once('-').unwrap_or((id, "0"));
+    (
+        milliseconds.parse().unwrap_or_default(),
+        sequence.parse().unwrap_or_default(),
+    )
+}
+
+/// Find the subscription root field in the subgraph operation and render the key of the stream it reads from
+fn stream_subscription(
+    operation: &str,
+    variables: &Object,
+    fields: &HashMap<String, String>,
+) -> Result<StreamSubscription, String> {
+    let document = ast::Document::parse(operation, "subscription.graphql")
+        .map_err(|_| "cannot parse the subscription operation".to_string())?;
+    let field = document
+        .definitions
+        .iter()
+        .find_map(|definition| match definition {
+            ast::Definition::OperationDefinition(operation) => operation
+                .selection_set
+                .iter()
+                .find_map(|selection| match selection {
+                    ast::Selection::Field(field) if field.name.as_str() != "__typename" => {
+                        Some(field)
+                    }
+                    _ => None,
+                }),
+            _ => None,
+        })
+        .ok_or_else(|| "cannot find the subscription root field".to_string())?;
+
+    let template = fields.get(field.name.as_str()).ok_or_else(|| {
+        format!(
+            "subscription field {:?} is not mapped to a redis stream",
+            field.name.as_str()
+        )
+    })?;
+
+    let mut arguments = Object::default();
+    for argument in &field.arguments {
+        let value = match argument.value.as_ref() {
+            ast::Value::Variable(variable) => variables.get(variable.as_str()).cloned(),
+            value => parse_hir_value(value),
+        };
+        if let Some(value) = value {
+            arguments.insert(argument.name.as_str(), value);
+        }
+    }
+
+    Ok(StreamSubscription {
+        response_key: field
+            .alias
+            .as_ref()
+            .unwrap_or(&field.name)
+            .as_str()
+            .to_string(),
+        stream_key: render_stream_key(template, &arguments)?,
+    })
+}
+
+/// Replace every `{argument_name}` in the template with the value of the argument
+fn render_stream_key(template: &str, arguments: &Object) -> Result<String, String> {
+    let mut stream_key = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        stream_key.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| format!("invalid stream key template {template:?}"))?
+            + start;
+        let argument_name = &rest[start + 1..end];
+        match arguments.get(argument_name) {
+            Some(Value::String(value)) => stream_key.push_str(value.as_str()),
+            Some(Value::Number(value)) => stream_key.push_str(&value.to_string()),
+            Some(Value::Bool(value)) => stream_key.push_str(&value.to_string()),
+            _ => {
+                return Err(format!(
+                    "argument {argument_name:?} used in the stream key template is missing or is not a scalar"
+                ))
+            }
+        }
+        rest = &rest[end + 1..];
+    }
+    stream_key.push_str(rest);
+
+    Ok(stream_key)
+}
+
+/// Turn a stream entry into a subscription event for the root field
+fn stream_event(
+    response_key: &str,
+    payload_field: &str,
+    fields: &HashMap<String, String>,
+) -> graphql::Response {
+    let payload = fields
+        .get(payload_field)
+        .ok_or_else(|| format!("stream entry doesn't contain the {payload_field:?} field"))
+        .and_then(|payload| {
+            serde_json::from_str::<Value>(payload)
+                .map_err(|err| format!("cannot deserialize the stream entry payload: {err}"))
+        });
+
+    match payload {
+        Ok(payload) => {
+            let mut data = Object::default();
+            data.insert(response_key, payload);
+            graphql::Response::builder()
+                .data(Value::Object(data))
+                .subscribed(true)
+                .build()
+        }
+        Err(message) => source_error(message),
+    }
+}
+
+fn source_error(message: String) -> graphql::Response {
+    graphql::Response::builder()
+        .error(
+            graphql::Error::builder()
+                .message(message)
+                .extension_code(EVENT_SOURCE_ERROR_CODE)
+                .build(),
+        )
+        .subscribed(true)
+        .build()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+
+    use fred::error::RedisErrorKind;
+    use fred::mocks::MockCommand;
+    use fred::mocks::Mocks;
+    use fred::prelude::RedisError;
+    use fred::prelude::RedisValue;
+    use futures::StreamExt;
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::graphql::Request;
+
+    /// `orders:*` streams, with an entry added before the subscriptions and one after
+    #[derive(Debug, Default)]
+    struct StreamMock {
+        /// Largest number of streams read by one command
+        max_streams: AtomicUsize,
+        quit: AtomicBool,
+    }
+
+    fn entry(id: &str, payload: &str) -> RedisValue {
+        RedisValue::Array(vec![
+            id.into(),
+            RedisValue::Array(vec!["payload".into(), payload.into()]),
+        ])
+    }
+
+    impl Mocks for StreamMock {
+        fn process_command(&self, command: MockCommand) -> Result<RedisValue, RedisError> {
+            match &*command.cmd {
+                "XREVRANGE" => Ok(RedisValue::Array(vec![entry("1-0", r#"{"id": "0"}"#)])),
+                "XREAD" => {
+                    // COUNT count BLOCK milliseconds STREAMS key... id...
+                    let arguments: Vec<String> = command
+                        .args
+                        .iter()
+                        .skip(5)
+                        .filter_map(RedisValue::as_string)
+                        .collect();
+                    let (keys, ids) = arguments.split_at(arguments.len() / 2);
+                    self.max_streams.fetch_max(keys.len(), Ordering::SeqCst);
+
+                    let streams: Vec<RedisValue> = keys
+                        .iter()
+                        .zip(ids)
+                        .filter(|(_, id)| id.as_str() == "1-0")
+                        .map(|(key, _)| {
+                            let id = key.trim_start_matches("orders:");
+                            RedisValue::Array(vec![
+                                key.as_str().into(),
+                                RedisValue::Array(vec![entry(
+                                    "2-0",
+                                    &format!(r#"{{"id": "{id}", "status": "SHIPPED"}}"#),
+                                )]),
+                            ])
+                        })
+                        .collect();
+                    if streams.is_empty() {
+                        // no new entry before the end of the blocking read
+                        Ok(RedisValue::Null)
+                    } else {
+                        Ok(RedisValue::Array(streams))
+                    }
+                }
+                "QUIT" => {
+                    self.quit.store(true, Ordering::SeqCst);
+                    Ok(RedisValue::new_ok())
+                }
+                _ => Err(RedisError::new(RedisErrorKind::NotFound, "mock not found")),
+            }
+        }
+    }
+
+    async fn source(mock: Arc<StreamMock>) -> RedisStreamsSource {
+        let config: RedisStreamsMode = serde_json::from_value(serde_json::json!({
+            "redis": { "urls": ["redis://localhost:6379"] },
+            "block_timeout": "10ms",
+            "batch_size": 10,
+            "readers": 1,
+            "subgraphs": {
+                "orders": { "fields": { "orderUpdated": "orders:{orderId}" } }
+            }
+        }))
+        .unwrap();
+        RedisStreamsSource::with_storage(
+            RedisCacheStorage::from_mocks(mock).await.unwrap(),
+            Notify::builder().build(),
+            &config,
+        )
+    }
+
+    async fn subscribe(
+        source: &RedisStreamsSource,
+        order_id: &str,
+    ) -> crate::services::subgraph::BoxGqlStream {
+        let (tx, mut rx) = mpsc::channel(1);
+        let request = subgraph::Request::fake_builder()
+            .subgraph_request(
+                http::Request::builder()
+                    .body(
+                        Request::fake_builder()
+                            .query(format!(
+                                r#"subscription {{ orderUpdated(orderId: "{order_id}") {{ id status }} }}"#
+                            ))
+                            .build(),
+                    )
+                    .unwrap(),
+            )
+            .subscription_stream(tx)
+            .build();
+        let response = source.subscribe("orders", request).await.unwrap();
+        assert!(response.response.body().errors.is_empty());
+        rx.recv().await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn it_sends_the_new_stream_entries_until_the_subscription_ends() {
+        let mock = Arc::new(StreamMock::default());
+        let source = source(mock.clone()).await;
+
+        let mut events = subscribe(&source, "42").await;
+        let event = events.next().await.unwrap();
+        assert_eq!(
+            event.data,
+            Some(serde_json_bytes::json!({
+                "orderUpdated": { "id": "42", "status": "SHIPPED" }
+            }))
+        );
+
+        // the reader stops and closes its connection once the source is dropped and nobody is subscribed anymore
+        drop(events);
+        drop(source);
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !mock.quit.load(Ordering::SeqCst) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn it_reads_many_streams_with_one_command() {
+        let mock = Arc::new(StreamMock::default());
+        let source = source(mock.clone()).await;
+
+        let mut first = subscribe(&source, "1").await;
+        let mut second = subscribe(&source, "2").await;
+        assert_eq!(
+            first.next().await.unwrap().data,
+            Some(serde_json_bytes::json!({
+                "orderUpdated": { "id": "1", "status": "SHIPPED" }
+            }))
+        );
+        assert_eq!(
+            second.next().await.unwrap().data,
+            Some(serde_json_bytes::json!({
+                "orderUpdated": { "id": "2", "status": "SHIPPED" }
+            }))
+        );
+        assert_eq!(mock.max_streams.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn it_compares_stream_ids() {
+        assert!(stream_id("1-1") > stream_id("1-0"));
+        assert!(stream_id("10-0") > stream_id("9-5"));
+        assert_eq!(stream_id("0-0"), (0, 0));
+    }
+
+    #[test]
+    fn it_renders_stream_keys_from_arguments() {
+        let fields: HashMap<String, String> = [(
+            "orderUpdated".to_string(),
+            "orders:{region}:{orderId}".to_string(),
+        )]
+        .into();
+        let variables = serde_json_bytes::json!({ "id": 42 })
+            .as_object()
+            .cloned()
+            .unwrap();
+
+        let subscription = stream_subscription(
+            r#"subscription Orders($id: ID!) { order: orderUpdated(orderId: $id, region: "eu") { id status } }"#,
+            &variables,
+            &fields,
+        )
+        .unwrap();
+        assert_eq!(
+            subscription,
+            StreamSubscription {
+                response_key: "order".to_string(),
+                stream_key: "orders:eu:42".to_string(),
+            }
+        );
+
+        assert!(stream_subscription(
+            "subscription { userCreated { id } }",
+            &Object::default(),
+            &fields
+        )
+        .is_err());
+        assert!(stream_subscription(
+            "subscription { orderUpdated(orderId: 1) { id } }",
+            &Object::default(),
+            &fields
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn it_converts_stream_entries_to_events() {
+        let fields: HashMap<String, String> = [(
+            "payload".to_string(),
+            r#"{"id": "1", "status": "SHIPPED"}"#.to_string(),
+        )]
+        .into();
+        let event = stream_event("orderUpdated", "payload", &fields);
+        assert_eq!(
+            event.data,
+            Some(serde_json_bytes::json!({
+                "orderUpdated": { "id": "1", "status": "SHIPPED" }
+            }))
+        );
+        assert_eq!(event.subscribed, Some(true));
+
+        let event = stream_event("orderUpdated", "data", &fields);
+        assert!(event.data.is_none());
+        assert_eq!(event.errors.len(), 1);
+    }
+}
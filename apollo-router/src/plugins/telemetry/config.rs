@@ -19,6 +19,7 @@ use serde::Serialize;
 
 use super::metrics::MetricsAttributesConf;
 use super::*;
+use crate::metrics::custom::CustomMetricsLimits;
 use crate::plugin::serde::deserialize_option_header_name;
 use crate::plugins::telemetry::metrics;
 use crate::plugins::telemetry::resource::ConfigResource;
@@ -120,6 +121,8 @@ pub(crate) struct MetricsCommon {
     pub(crate) buckets: Vec<f64>,
     /// Views applied on metrics
     pub(crate) views: Vec<MetricView>,
+    /// Cardinality limits of the metrics recorded by Rhai scripts and coprocessors
+    pub(crate) custom_metrics: CustomMetricsLimits,
 }
 
 impl Default for MetricsCommon {
@@ -130,6 +133,7 @@ impl Default for MetricsCommon {
             service_namespace: None,
             resource: BTreeMap::new(),
             views: Vec::with_capacity(0),
+            custom_metrics: Default::default(),
             buckets: vec![
                 0.001, 0.005, 0.015, 0.05, 0.1, 0.2, 0.3, 0.4, 0.5, 1.0, 5.0, 10.0,
             ],
@@ -1,4 +1,8 @@
+use std::marker::PhantomData;
+
 use opentelemetry::Value;
+use schemars::gen::SchemaGenerator;
+use schemars::schema::Schema;
 use schemars::JsonSchema;
 use serde::Deserialize;
 
@@ -11,6 +15,8 @@ use crate::plugins::telemetry::config_new::Selector;
 pub(crate) enum Condition<T> {
     /// A condition to check a selection against a value.
     Eq([SelectorOrValue<T>; 2]),
+    /// The selection must return a value.
+    Exists(#[schemars(with = "SharedSelector<T>")] T),
     /// All sub-conditions must be true.
     All(Vec<Condition<T>>),
     /// At least one sub-conditions must be true.
@@ -32,7 +38,30 @@ pub(crate) enum SelectorOrValue<T> {
     /// A constant value.
     Value(AttributeValue),
     /// Selector to extract a value from the pipeline.
-    Selector(T),
+    Selector(#[schemars(with = "SharedSelector<T>")] T),
+}
+
+/// The configuration schema inlines subschemas, but selectors are large and used by many conditions, so
+/// they are published once in the definitions and referenced
+struct SharedSelector<T>(PhantomData<T>);
+
+impl<T: JsonSchema> JsonSchema for SharedSelector<T> {
+    fn is_referenceable() -> bool {
+        false
+    }
+
+    fn schema_name() -> String {
+        T::schema_name()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        let name = T::schema_name();
+        if !gen.definitions().contains_key(&name) {
+            let schema = T::json_schema(gen);
+            gen.definitions_mut().insert(name.clone(), schema);
+        }
+        Schema::new_ref(format!("{}{}", gen.settings().definitions_path, name))
+    }
 }
 
 #[allow(dead_code)]
@@ -52,11 +81,37 @@ where
                     .or_else(|| eq[1].on_response(response));
                 left == right
             }
+            Condition::Exists(exists) => exists
+                .on_request(request)
+                .or_else(|| exists.on_response(response))
+                .is_some(),
             Condition::All(all) => all.iter().all(|c| c.evaluate(request, response)),
             Condition::Any(any) => any.iter().any(|c| c.evaluate(request, response)),
             Condition::Not(not) => !not.evaluate(request, response),
         }
     }
+
+    /// Evaluates the condition when the response is not available yet, selections only look at the request.
+    pub(crate) fn evaluate_request(&self, request: &T::Request) -> bool {
+        match self {
+            Condition::Eq(eq) => eq[0].on_request(request) == eq[1].on_request(request),
+            Condition::Exists(exists) => exists.on_request(request).is_some(),
+            Condition::All(all) => all.iter().all(|c| c.evaluate_request(request)),
+            Condition::Any(any) => any.iter().any(|c| c.evaluate_request(request)),
+            Condition::Not(not) => !not.evaluate_request(request),
+        }
+    }
+
+    /// Evaluates the condition once the request is gone, selections only look at the response.
+    pub(crate) fn evaluate_response(&self, response: &T::Response) -> bool {
+        match self {
+            Condition::Eq(eq) => eq[0].on_response(response) == eq[1].on_response(response),
+            Condition::Exists(exists) => exists.on_response(response).is_some(),
+            Condition::All(all) => all.iter().all(|c| c.evaluate_response(response)),
+            Condition::Any(any) => any.iter().any(|c| c.evaluate_response(response)),
+            Condition::Not(not) => !not.evaluate_response(response),
+        }
+    }
 }
 
 impl<T> Selector for SelectorOrValue<T>
@@ -153,6 +208,29 @@ mod test {
         .evaluate(&None, &None));
     }
 
+    #[test]
+    fn test_condition_exists() {
+        assert!(Condition::<TestSelector>::Exists(TestSelector).evaluate(&Some(1i64), &None));
+        assert!(Condition::<TestSelector>::Exists(TestSelector).evaluate(&None, &Some(1i64)));
+        assert!(!Condition::<TestSelector>::Exists(TestSelector).evaluate(&None, &None));
+    }
+
+    #[test]
+    fn test_condition_request_response() {
+        let condition = Condition::<TestSelector>::Eq([
+            SelectorOrValue::Selector(TestSelector),
+            SelectorOrValue::Value(1i64.into()),
+        ]);
+        assert!(condition.evaluate_request(&Some(1i64)));
+        assert!(!condition.evaluate_request(&Some(2i64)));
+        assert!(condition.evaluate_response(&Some(1i64)));
+        assert!(!condition.evaluate_response(&None));
+
+        let condition = Condition::<TestSelector>::Not(Box::new(Condition::Exists(TestSelector)));
+        assert!(condition.evaluate_request(&None));
+        assert!(!condition.evaluate_response(&Some(1i64)));
+    }
+
     #[test]
     fn test_condition_not() {
         assert!(Condition::<TestSelector>::Not(Box::new(Condition::Eq([
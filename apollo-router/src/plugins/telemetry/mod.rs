@@ -123,7 +123,7 @@ use crate::ListenAddr;
 pub(crate) mod apollo;
 pub(crate) mod apollo_exporter;
 pub(crate) mod config;
-mod config_new;
+pub(crate) mod config_new;
 pub(crate) mod dynamic_attribute;
 mod endpoint;
 mod fmt_layer;
@@ -681,6 +681,7 @@ impl Telemetry {
         }
 
         activation.reload_metrics();
+        crate::metrics::custom::configure(&self.config.exporters.metrics.common.custom_metrics);
 
         reload_fmt(create_fmt_layer(&self.config));
         activation.is_active = true;
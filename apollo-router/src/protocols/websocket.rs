@@ -16,6 +16,7 @@ use serde::Serialize;
 use serde_json_bytes::Value;
 use tokio::io::AsyncRead;
 use tokio::io::AsyncWrite;
+use tokio::time::Instant;
 use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
 use tokio_tungstenite::tungstenite::protocol::CloseFrame;
 use tokio_tungstenite::tungstenite::Message;
@@ -222,6 +223,8 @@ pub(crate) struct GraphqlWebSocket<S> {
     // Booleans for state machine when closing the stream
     completed: bool,
     terminated: bool,
+    // Last time we received a message from the server, used to detect idle connections
+    last_activity: Instant,
 }
 }
 
@@ -291,8 +294,33 @@ where
             protocol,
             completed: false,
             terminated: false,
+            last_activity: Instant::now(),
         })
     }
+
+    /// Last time a message (including pings, pongs and keep alives) was received from the server
+    pub(crate) fn last_activity(&self) -> Instant {
+        self.last_activity
+    }
+
+    /// Send a ping to the server to keep the connection alive
+    ///
+    /// Only the graphql_ws protocol supports pings, it's a no-op for the other protocol.
+    pub(crate) async fn ping(&mut self) -> Result<(), graphql::Error> {
+        if let WebSocketProtocol::SubscriptionsTransportWs = self.protocol {
+            return Ok(());
+        }
+
+        self.stream
+            .send(ClientMessage::Ping { payload: None })
+            .await
+            .map_err(|_err| {
+                graphql::Error::builder()
+                    .message("cannot send ping through websocket connection")
+                    .extension_code("WEBSOCKET_PING_ERROR")
+                    .build()
+            })
+    }
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -377,6 +405,7 @@ where
             Poll::Ready(message) => match message {
                 Some(server_message) => match server_message {
                     Ok(server_message) => {
+                        *this.last_activity = Instant::now();
                         if let Some(id) = &server_message.id() {
                             if this.id != id {
                                 tracing::error!("we should not receive data from other subscriptions, closing the stream");
@@ -4,8 +4,6 @@ use std::sync::Arc;
 use std::task;
 
 use futures::future::BoxFuture;
-use indexmap::IndexMap;
-use query_planner::QueryPlannerPlugin;
 use rand::seq::SliceRandom;
 use rand::thread_rng;
 use router_bridge::planner::PlanOptions;
@@ -33,6 +31,7 @@ use crate::services::layers::persisted_queries::PersistedQueryLayer;
 use crate::services::layers::query_analysis::ParsedDocument;
 use crate::services::layers::query_analysis::QueryAnalysisLayer;
 use crate::services::query_planner;
+use crate::services::Plugins;
 use crate::services::QueryPlannerContent;
 use crate::services::QueryPlannerRequest;
 use crate::services::QueryPlannerResponse;
@@ -42,9 +41,6 @@ use crate::spec::SpecError;
 use crate::Configuration;
 use crate::Context;
 
-/// An [`IndexMap`] of available plugins.
-pub(crate) type Plugins = IndexMap<String, Box<dyn QueryPlannerPlugin>>;
-
 /// A query planner wrapper that caches results.
 ///
 /// The query planner performs LRU caching.
@@ -73,7 +69,7 @@ where
         delegate: T,
         schema: Arc<Schema>,
         configuration: &Configuration,
-        plugins: Plugins,
+        plugins: Arc<Plugins>,
     ) -> Result<CachingQueryPlanner<T>, BoxError> {
         let cache = Arc::new(
             DeduplicatingCache::from_configuration(
@@ -89,7 +85,7 @@ where
             cache,
             delegate,
             schema,
-            plugins: Arc::new(plugins),
+            plugins,
             enable_authorization_directives,
         })
     }
@@ -286,7 +282,7 @@ where
     <T as tower::Service<QueryPlannerRequest>>::Future: Send,
 {
     async fn plan(
-        mut self,
+        self,
         request: query_planner::CachingRequest,
     ) -> Result<<T as tower::Service<QueryPlannerRequest>>::Response, CacheResolverError> {
         let schema_id = self.schema.schema_id.clone();
@@ -369,7 +365,16 @@ where
                         return Err(CacheResolverError::RetrievalError(e));
                     }
 
-                    let res = self.delegate.ready().await?.call(request).await;
+                    // plugins run on cache misses only: a plan they reject is not cached, so
+                    // they will be asked again for the next similar query
+                    let service = self
+                        .plugins
+                        .iter()
+                        .rev()
+                        .fold(self.delegate.boxed(), |acc, (_, e)| {
+                            e.query_planner_service(acc)
+                        });
+                    let res = service.oneshot(request).await;
 
                     match res {
                         Ok(QueryPlannerResponse {
@@ -585,7 +590,7 @@ mod tests {
         );
 
         let mut planner =
-            CachingQueryPlanner::new(delegate, schema, &configuration, IndexMap::new())
+            CachingQueryPlanner::new(delegate, schema, &configuration, Default::default())
                 .await
                 .unwrap();
 
@@ -668,10 +673,14 @@ mod tests {
 
         let doc = Query::parse_document("query Me { me { username } }", &schema, &configuration);
 
-        let mut planner =
-            CachingQueryPlanner::new(delegate, Arc::new(schema), &configuration, IndexMap::new())
-                .await
-                .unwrap();
+        let mut planner = CachingQueryPlanner::new(
+            delegate,
+            Arc::new(schema),
+            &configuration,
+            Default::default(),
+        )
+        .await
+        .unwrap();
 
         let context = Context::new();
         context.extensions().lock().insert::<ParsedDocument>(doc);
@@ -25,6 +25,7 @@ use crate::json_ext::Value;
 use crate::json_ext::ValueExt;
 use crate::plugins::authorization::AuthorizationPlugin;
 use crate::plugins::authorization::CacheKeyMetadata;
+use crate::services::subgraph::FetchMetadata;
 use crate::services::SubgraphRequest;
 use crate::spec::query::change::QueryHashVisitor;
 use crate::spec::query::traverse;
@@ -296,6 +297,10 @@ impl FetchNode {
             .build();
         subgraph_request.query_hash = self.schema_aware_hash.clone();
         subgraph_request.authorization = self.authorization.clone();
+        subgraph_request.fetch = Some(FetchMetadata {
+            path: current_dir.clone(),
+            representations: (!self.requires.is_empty()).then_some(paths.len()),
+        });
 
         let service = parameters
             .service_factory
@@ -482,7 +487,6 @@ impl FetchNode {
         }
     }
 
-    #[cfg(test)]
     pub(crate) fn service_name(&self) -> &str {
         &self.service_name
     }
@@ -108,6 +108,31 @@ impl SubscriptionNode {
                 });
             }
         }
+        // the slot of the client is reserved now, and released when the subscription task ends
+        let mut client_subscription_guard = None;
+        if let Some(per_client) = parameters
+            .subscription_config
+            .as_ref()
+            .and_then(|s| s.max_opened_subscriptions_per_client.as_ref())
+        {
+            if let Some(client_id) =
+                per_client.client_id(parameters.supergraph_request, parameters.context)
+            {
+                match per_client.try_reserve(client_id) {
+                    Some(guard) => client_subscription_guard = Some(guard),
+                    None => {
+                        return Box::pin(async {
+                            vec![Error::builder()
+                                .message(
+                                    "can't open new subscription, limit reached for this client",
+                                )
+                                .extension_code("SUBSCRIPTION_CLIENT_MAX_LIMIT")
+                                .build()]
+                        });
+                    }
+                }
+            }
+        }
         let subscription_handle = parameters
             .subscription_handle
             .as_ref()
@@ -151,6 +176,7 @@ impl SubscriptionNode {
                         subscription_config,
                         stream_rx: rx_handle.into(),
                         service_name: self.service_name.clone(),
+                        client_subscription_guard,
                     };
 
                     if let Err(err) = subscription_conf_tx.send(subs_params).await {
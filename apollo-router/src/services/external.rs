@@ -22,6 +22,9 @@ use tower::BoxError;
 use tower::Service;
 use tracing_opentelemetry::OpenTelemetrySpanExt;
 
+use crate::metrics::custom;
+use crate::metrics::custom::CustomMetricKind;
+use crate::plugins::coprocessor::StagePayload;
 use crate::plugins::telemetry::reload::prepare_context;
 use crate::query_planner::QueryPlan;
 use crate::Context;
@@ -31,12 +34,14 @@ pub(crate) const DEFAULT_EXTERNALIZATION_TIMEOUT: Duration = Duration::from_secs
 /// Version of our externalised data. Rev this if it changes
 pub(crate) const EXTERNALIZABLE_VERSION: u8 = 1;
 
-#[derive(Clone, Debug, Display, Deserialize, PartialEq, Serialize, JsonSchema)]
+#[derive(Clone, Copy, Debug, Display, Deserialize, PartialEq, Serialize, JsonSchema)]
 pub(crate) enum PipelineStep {
     RouterRequest,
     RouterResponse,
     SupergraphRequest,
     SupergraphResponse,
+    QueryPlannerRequest,
+    QueryPlannerResponse,
     ExecutionRequest,
     ExecutionResponse,
     SubgraphRequest,
@@ -100,7 +105,61 @@ pub(crate) struct Externalizable<T> {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) has_next: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    query_plan: Option<Arc<QueryPlan>>,
+    pub(crate) sequence: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) query_plan: Option<Arc<QueryPlan>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) override_labels: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) body_patch: Option<json_patch::Patch>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) headers_patch: Option<Vec<HeaderOperation>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) context_patch: Option<Vec<ContextOperation>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) metrics: Option<Vec<CoprocessorMetric>>,
+}
+
+/// Change of the headers in a version 2 coprocessor response
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "op", rename_all = "camelCase")]
+pub(crate) enum HeaderOperation {
+    Add { name: String, value: String },
+    Remove { name: String },
+}
+
+/// Change of the context in a version 2 coprocessor response
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "op", rename_all = "camelCase")]
+pub(crate) enum ContextOperation {
+    Add {
+        key: String,
+        value: serde_json_bytes::Value,
+    },
+    Remove {
+        key: String,
+    },
+}
+
+/// Custom metric recorded by a coprocessor response
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct CoprocessorMetric {
+    #[serde(rename = "type")]
+    pub(crate) kind: CustomMetricKind,
+    pub(crate) name: String,
+    pub(crate) value: f64,
+    #[serde(default)]
+    pub(crate) attributes: serde_json::Map<String, serde_json::Value>,
+}
+
+impl CoprocessorMetric {
+    fn record(self) {
+        let result = custom::json_attributes(self.attributes)
+            .and_then(|attributes| custom::record(self.kind, &self.name, self.value, attributes));
+        if let Err(error) = result {
+            tracing::warn!("coprocessor metric not recorded: {error}");
+        }
+    }
 }
 
 #[buildstructor::buildstructor]
@@ -143,7 +202,13 @@ where
             method,
             service_name: None,
             has_next: None,
+            sequence: None,
             query_plan: None,
+            override_labels: None,
+            body_patch: None,
+            headers_patch: None,
+            context_patch: None,
+            metrics: None,
         }
     }
 
@@ -162,6 +227,7 @@ where
         method: Option<String>,
         sdl: Option<String>,
         has_next: Option<bool>,
+        sequence: Option<u64>,
     ) -> Self {
         assert!(matches!(
             stage,
@@ -182,7 +248,55 @@ where
             method,
             service_name: None,
             has_next,
+            sequence,
             query_plan: None,
+            override_labels: None,
+            body_patch: None,
+            headers_patch: None,
+            context_patch: None,
+            metrics: None,
+        }
+    }
+
+    #[builder(visibility = "pub(crate)")]
+    /// This is the constructor (or builder) to use when constructing a QueryPlanner
+    /// `Externalizable`.
+    ///
+    fn query_planner_new(
+        stage: PipelineStep,
+        control: Option<Control>,
+        id: String,
+        body: Option<T>,
+        sdl: Option<String>,
+        override_labels: Option<Vec<String>>,
+        query_plan: Option<Arc<QueryPlan>>,
+    ) -> Self {
+        assert!(matches!(
+            stage,
+            PipelineStep::QueryPlannerRequest | PipelineStep::QueryPlannerResponse
+        ));
+        Externalizable {
+            version: EXTERNALIZABLE_VERSION,
+            stage: stage.to_string(),
+            control,
+            id: Some(id),
+            headers: None,
+            body,
+            context: None,
+            status_code: None,
+            sdl,
+            uri: None,
+            path: None,
+            method: None,
+            service_name: None,
+            has_next: None,
+            sequence: None,
+            query_plan,
+            override_labels,
+            body_patch: None,
+            headers_patch: None,
+            context_patch: None,
+            metrics: None,
         }
     }
 
@@ -201,6 +315,7 @@ where
         method: Option<String>,
         sdl: Option<String>,
         has_next: Option<bool>,
+        sequence: Option<u64>,
         query_plan: Option<Arc<QueryPlan>>,
     ) -> Self {
         assert!(matches!(
@@ -222,7 +337,13 @@ where
             method,
             service_name: None,
             has_next,
+            sequence,
             query_plan,
+            override_labels: None,
+            body_patch: None,
+            headers_patch: None,
+            context_patch: None,
+            metrics: None,
         }
     }
 
@@ -261,7 +382,13 @@ where
             method,
             service_name,
             has_next: None,
+            sequence: None,
             query_plan: None,
+            override_labels: None,
+            body_patch: None,
+            headers_patch: None,
+            context_patch: None,
+            metrics: None,
         }
     }
 
@@ -272,6 +399,7 @@ where
             + Send
             + Sync
             + 'static,
+        T: 'static,
     {
         tracing::debug!("forwarding json: {}", serde_json::to_string(&self)?);
 
@@ -281,6 +409,7 @@ where
             .header(ACCEPT, "application/json")
             .header(CONTENT_TYPE, "application/json")
             .body(serde_json::to_vec(&self)?.into())?;
+        request.extensions_mut().insert(StagePayload::new(self));
 
         get_text_map_propagator(|propagator| {
             propagator.inject_context(
@@ -290,10 +419,14 @@ where
         });
 
         let response = client.call(request).await?;
-        hyper::body::to_bytes(response.into_body())
+        let mut output: Self = hyper::body::to_bytes(response.into_body())
             .await
             .map_err(BoxError::from)
-            .and_then(|bytes| serde_json::from_slice(&bytes).map_err(BoxError::from))
+            .and_then(|bytes| serde_json::from_slice(&bytes).map_err(BoxError::from))?;
+        for metric in output.metrics.take().into_iter().flatten() {
+            metric.record();
+        }
+        Ok(output)
     }
 }
 
@@ -313,6 +446,8 @@ pub(crate) fn externalize_header_map(
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::metrics::FutureMetricsExt;
+    use crate::plugin::test::MockHttpClientService;
 
     #[test]
     fn it_will_build_router_externalizable_correctly() {
@@ -376,4 +511,58 @@ mod test {
             .id(String::default())
             .build();
     }
+
+    #[test]
+    fn it_will_build_query_planner_externalizable_correctly() {
+        Externalizable::<String>::query_planner_builder()
+            .stage(PipelineStep::QueryPlannerRequest)
+            .id(String::default())
+            .build();
+        Externalizable::<String>::query_planner_builder()
+            .stage(PipelineStep::QueryPlannerResponse)
+            .id(String::default())
+            .build();
+    }
+
+    #[test]
+    #[should_panic]
+    fn it_will_not_build_query_planner_externalizable_incorrectly() {
+        Externalizable::<String>::query_planner_builder()
+            .stage(PipelineStep::ExecutionRequest)
+            .id(String::default())
+            .build();
+    }
+
+    #[tokio::test]
+    async fn it_records_coprocessor_metrics() {
+        async {
+            let mut mock_http_client = MockHttpClientService::new();
+            mock_http_client.expect_call().returning(|_| {
+                Box::pin(async {
+                    let response = serde_json::json!({
+                        "version": 1,
+                        "stage": "RouterRequest",
+                        "control": "continue",
+                        "metrics": [
+                            { "type": "counter", "name": "orders", "value": 2, "attributes": { "partner": "acme" } },
+                            { "type": "histogram", "name": "apollo_router_forbidden", "value": 1 }
+                        ]
+                    });
+                    Ok(hyper::Response::new(Body::from(response.to_string())))
+                }) as futures::future::BoxFuture<'static, _>
+            });
+
+            let output = Externalizable::<String>::router_builder()
+                .stage(PipelineStep::RouterRequest)
+                .id(String::default())
+                .build()
+                .call(mock_http_client, "http://coprocessor")
+                .await
+                .unwrap();
+            assert!(output.metrics.is_none());
+            assert_counter!("orders", 2.0, "partner" = "acme");
+        }
+        .with_metrics()
+        .await;
+    }
 }
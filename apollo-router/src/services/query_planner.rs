@@ -2,7 +2,6 @@
 
 use std::sync::Arc;
 
-use async_trait::async_trait;
 use derivative::Derivative;
 use serde::Deserialize;
 use serde::Serialize;
@@ -112,10 +111,3 @@ pub(crate) type ServiceResult = Result<Response, QueryPlannerError>;
 pub(crate) type Body = hyper::Body;
 #[allow(dead_code)]
 pub(crate) type Error = hyper::Error;
-
-#[async_trait]
-pub(crate) trait QueryPlannerPlugin: Send + Sync + 'static {
-    /// This service runs right after the query planner cache, which means that it will be called once per unique
-    /// query, unless the cache entry was evicted
-    fn query_planner_service(&self, service: BoxService) -> BoxService;
-}
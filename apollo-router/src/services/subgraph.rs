@@ -58,6 +58,18 @@ pub struct Request {
 
     // authorization metadata for this request
     pub(crate) authorization: Arc<CacheKeyMetadata>,
+
+    /// The fetch node of the query plan that sent this request
+    pub(crate) fetch: Option<FetchMetadata>,
+}
+
+/// Metadata of the fetch node that sent a subgraph request
+#[derive(Clone, Debug)]
+pub(crate) struct FetchMetadata {
+    /// The path of the fetched fields in the response
+    pub(crate) path: Path,
+    /// The number of entity representations, for entity fetches
+    pub(crate) representations: Option<usize>,
 }
 
 #[buildstructor::buildstructor]
@@ -85,6 +97,7 @@ impl Request {
             connection_closed_signal,
             query_hash: Default::default(),
             authorization: Default::default(),
+            fetch: None,
         }
     }
 
@@ -148,6 +161,7 @@ impl Clone for Request {
                 .map(|s| s.resubscribe()),
             query_hash: self.query_hash.clone(),
             authorization: self.authorization.clone(),
+            fetch: self.fetch.clone(),
         }
     }
 }
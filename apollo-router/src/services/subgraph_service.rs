@@ -25,6 +25,7 @@ use mediatype::MediaType;
 use mime::APPLICATION_JSON;
 use rustls::RootCertStore;
 use serde::Serialize;
+use tokio::time::Instant;
 use tokio_tungstenite::connect_async;
 use tokio_tungstenite::connect_async_tls_with_config;
 use tokio_tungstenite::tungstenite::client::IntoClientRequest;
@@ -43,6 +44,7 @@ use crate::configuration::TlsClientAuth;
 use crate::error::FetchError;
 use crate::graphql;
 use crate::json_ext::Object;
+use crate::notification::HandleSink;
 use crate::plugins::authentication::subgraph::SigningParamsConfig;
 use crate::plugins::file_uploads;
 use crate::plugins::subscription::create_verifier;
@@ -54,8 +56,11 @@ use crate::plugins::subscription::WebSocketConfiguration;
 use crate::plugins::subscription::SUBSCRIPTION_WS_CUSTOM_CONNECTION_PARAMS;
 use crate::plugins::telemetry::LOGGING_DISPLAY_BODY;
 use crate::plugins::telemetry::LOGGING_DISPLAY_HEADERS;
+use crate::protocols::websocket;
 use crate::protocols::websocket::convert_websocket_stream;
+use crate::protocols::websocket::ClientMessage;
 use crate::protocols::websocket::GraphqlWebSocket;
+use crate::protocols::websocket::ServerMessage;
 use crate::query_planner::OperationKind;
 use crate::services::layers::apq;
 use crate::services::SubgraphRequest;
@@ -495,124 +500,381 @@ async fn call_websocket(
 
     let request = get_websocket_request(service_name.clone(), parts, subgraph_cfg)?;
 
-    let display_headers = context.contains_key(LOGGING_DISPLAY_HEADERS);
-    let display_body = context.contains_key(LOGGING_DISPLAY_BODY);
-
     let signing_params = context
         .extensions()
         .lock()
         .get::<SigningParamsConfig>()
         .cloned();
 
-    let request = if let Some(signing_params) = signing_params {
-        signing_params
-            .sign_empty(request, service_name.as_str())
-            .await?
-    } else {
-        request
-    };
+    let connector = Arc::new(WebSocketConnector {
+        service_name,
+        operation_name,
+        request,
+        signing_params,
+        subgraph_cfg: subgraph_cfg.clone(),
+        connection_params,
+        subscription_hash,
+        display_headers: context.contains_key(LOGGING_DISPLAY_HEADERS),
+        display_body: context.contains_key(LOGGING_DISPLAY_BODY),
+    });
 
-    if display_headers {
-        tracing::info!(http.request.headers = ?request.headers(), apollo.subgraph.name = %service_name, "Websocket request headers to subgraph {service_name:?}");
-    }
+    let (gql_stream, resp) = connector.clone().connect(body.clone()).await?;
+    let (mut handle_sink, handle_stream) = handle.split();
 
-    if display_body {
-        tracing::info!(http.request.body = ?request.body(), apollo.subgraph.name = %service_name, "Websocket request body to subgraph {service_name:?}");
-    }
+    tokio::task::spawn(async move {
+        let mut outcome = forward_websocket_events(
+            gql_stream,
+            &mut handle_sink,
+            &connector.subgraph_cfg,
+            &connector.service_name,
+        )
+        .await;
 
-    let uri = request.uri();
-    let path = uri.path();
-    let host = uri.host().unwrap_or_default();
-    let port = uri.port_u16().unwrap_or_else(|| {
-        let scheme = uri.scheme_str();
-        if scheme == Some("wss") {
-            443
-        } else if scheme == Some("ws") {
-            80
-        } else {
-            0
+        while let WebSocketOutcome::ConnectionLost(error_response) = outcome {
+            let reconnect_cfg = &connector.subgraph_cfg.reconnect;
+            if !reconnect_cfg.enabled {
+                let _ = handle_sink.send(error_response).await;
+                break;
+            }
+
+            let service_name = &connector.service_name;
+            let retry_started = Instant::now();
+            let mut attempt = 0u32;
+            let mut client_gone = false;
+            let mut new_connection = None;
+            while retry_started.elapsed() < reconnect_cfg.max_retry_window {
+                if reconnect_cfg.notify_client
+                    && handle_sink
+                        .send(subscription_reconnecting_event(attempt + 1))
+                        .await
+                        .is_err()
+                {
+                    client_gone = true;
+                    break;
+                }
+                tokio::time::sleep(reconnect_cfg.backoff(attempt)).await;
+                attempt += 1;
+                tracing::info!(
+                    monotonic_counter.apollo.router.operations.subscriptions.reconnections = 1u64,
+                    subscriptions.mode = %"passthrough",
+                    subgraph.service.name = %service_name,
+                );
+                match connector.clone().connect(body.clone()).await {
+                    Ok((gql_stream, _resp)) => {
+                        new_connection = Some(gql_stream);
+                        break;
+                    }
+                    Err(err) => {
+                        tracing::debug!(
+                            "cannot reconnect websocket to subgraph {service_name:?} (attempt {attempt}): {err}"
+                        );
+                    }
+                }
+            }
+
+            outcome = match new_connection {
+                Some(gql_stream) => {
+                    forward_websocket_events(
+                        gql_stream,
+                        &mut handle_sink,
+                        &connector.subgraph_cfg,
+                        service_name,
+                    )
+                    .await
+                }
+                None => {
+                    if !client_gone {
+                        tracing::error!(
+                            "cannot reconnect websocket to subgraph {service_name:?} after {attempt} attempts, closing the subscription"
+                        );
+                        let _ = handle_sink.send(error_response).await;
+                    }
+                    break;
+                }
+            };
+        }
+
+        if let Err(err) = handle_sink.close().await {
+            tracing::trace!("cannot close the subscription handle: {err:?}");
         }
     });
 
-    let subgraph_req_span = tracing::info_span!("subgraph_request",
-        "otel.kind" = "CLIENT",
-        "net.peer.name" = %host,
-        "net.peer.port" = %port,
-        "http.route" = %path,
-        "http.url" = %uri,
-        "net.transport" = "ip_tcp",
-        "apollo.subgraph.name" = %service_name,
-        "graphql.operation.name" = %operation_name,
-    );
+    subscription_stream_tx.send(Box::pin(handle_stream)).await?;
 
-    let (ws_stream, mut resp) = match request.uri().scheme_str() {
-        Some("wss") => {
-            connect_async_tls_with_config(request, None, false, None)
-                .instrument(subgraph_req_span)
+    Ok(SubgraphResponse::new_from_response(
+        resp.map(|_| graphql::Response::default()),
+        context,
+    ))
+}
+
+/// Everything needed to (re)open a websocket connection to a subgraph for a subscription
+struct WebSocketConnector {
+    service_name: String,
+    operation_name: String,
+    request: http::Request<()>,
+    signing_params: Option<SigningParamsConfig>,
+    subgraph_cfg: WebSocketConfiguration,
+    connection_params: Option<serde_json_bytes::Value>,
+    subscription_hash: String,
+    display_headers: bool,
+    display_body: bool,
+}
+
+impl WebSocketConnector {
+    /// Open the websocket connection, initialize the GraphQL websocket protocol and send the subscription
+    async fn connect(
+        self: Arc<Self>,
+        body: graphql::Request,
+    ) -> Result<
+        (
+            GraphqlWebSocket<
+                impl futures::Stream<Item = serde_json::Result<ServerMessage>>
+                    + futures::Sink<ClientMessage, Error = websocket::Error>
+                    + Unpin,
+            >,
+            http::Response<Option<Vec<u8>>>,
+        ),
+        FetchError,
+    > {
+        let service_name = &self.service_name;
+        let display_headers = self.display_headers;
+        let display_body = self.display_body;
+
+        let mut request = http::Request::new(());
+        *request.method_mut() = self.request.method().clone();
+        *request.uri_mut() = self.request.uri().clone();
+        *request.version_mut() = self.request.version();
+        *request.headers_mut() = self.request.headers().clone();
+
+        // Signatures are time dependent so we have to sign every new connection
+        let request = if let Some(signing_params) = &self.signing_params {
+            signing_params
+                .clone()
+                .sign_empty(request, service_name.as_str())
                 .await
+                .map_err(|err| FetchError::SubrequestWsError {
+                    service: service_name.clone(),
+                    reason: format!("cannot sign the websocket request: {err}"),
+                })?
+        } else {
+            request
+        };
+
+        if display_headers {
+            tracing::info!(http.request.headers = ?request.headers(), apollo.subgraph.name = %service_name, "Websocket request headers to subgraph {service_name:?}");
         }
-        _ => connect_async(request).instrument(subgraph_req_span).await,
-    }
-    .map_err(|err| {
-        if display_body || display_headers {
+
+        if display_body {
+            tracing::info!(http.request.body = ?request.body(), apollo.subgraph.name = %service_name, "Websocket request body to subgraph {service_name:?}");
+        }
+
+        let uri = request.uri();
+        let path = uri.path();
+        let host = uri.host().unwrap_or_default();
+        let port = uri.port_u16().unwrap_or_else(|| {
+            let scheme = uri.scheme_str();
+            if scheme == Some("wss") {
+                443
+            } else if scheme == Some("ws") {
+                80
+            } else {
+                0
+            }
+        });
+
+        let subgraph_req_span = tracing::info_span!("subgraph_request",
+            "otel.kind" = "CLIENT",
+            "net.peer.name" = %host,
+            "net.peer.port" = %port,
+            "http.route" = %path,
+            "http.url" = %uri,
+            "net.transport" = "ip_tcp",
+            "apollo.subgraph.name" = %service_name,
+            "graphql.operation.name" = %self.operation_name,
+        );
+
+        let (ws_stream, mut resp) = match request.uri().scheme_str() {
+            Some("wss") => {
+                connect_async_tls_with_config(request, None, false, None)
+                    .instrument(subgraph_req_span)
+                    .await
+            }
+            _ => connect_async(request).instrument(subgraph_req_span).await,
+        }
+        .map_err(|err| {
+            if display_body || display_headers {
+                tracing::info!(
+                    http.response.error = format!("{:?}", &err), apollo.subgraph.name = %service_name, "Websocket connection error from subgraph {service_name:?} received"
+                );
+            }
+            FetchError::SubrequestWsError {
+                service: service_name.clone(),
+                reason: format!("cannot connect websocket to subgraph: {err}"),
+            }
+        })?;
+
+        if display_headers {
+            tracing::info!(response.headers = ?resp.headers(), apollo.subgraph.name = %service_name, "Websocket response headers to subgraph {service_name:?}");
+        }
+        if display_body {
             tracing::info!(
-                http.response.error = format!("{:?}", &err), apollo.subgraph.name = %service_name, "Websocket connection error from subgraph {service_name:?} received"
+                response.body = %String::from_utf8_lossy(&resp.body_mut().take().unwrap_or_default()), apollo.subgraph.name = %service_name, "Websocket response body from subgraph {service_name:?} received"
             );
         }
-        FetchError::SubrequestWsError {
+
+        let mut gql_stream = GraphqlWebSocket::new(
+            convert_websocket_stream(ws_stream, self.subscription_hash.clone()),
+            self.subscription_hash.clone(),
+            self.subgraph_cfg.protocol,
+            self.connection_params.clone(),
+        )
+        .await
+        .map_err(|_| FetchError::SubrequestWsError {
             service: service_name.clone(),
-            reason: format!("cannot connect websocket to subgraph: {err}"),
-        }
-    })?;
+            reason: "cannot get the GraphQL websocket stream".to_string(),
+        })?;
 
-    if display_headers {
-        tracing::info!(response.headers = ?resp.headers(), apollo.subgraph.name = %service_name, "Websocket response headers to subgraph {service_name:?}");
-    }
-    if display_body {
-        tracing::info!(
-            response.body = %String::from_utf8_lossy(&resp.body_mut().take().unwrap_or_default()), apollo.subgraph.name = %service_name, "Websocket response body from subgraph {service_name:?} received"
-        );
+        gql_stream
+            .send(body)
+            .await
+            .map_err(|err| FetchError::SubrequestWsError {
+                service: service_name.clone(),
+                reason: format!("cannot send the subgraph request to websocket stream: {err:?}"),
+            })?;
+
+        Ok((gql_stream, resp))
     }
+}
 
-    let mut gql_stream = GraphqlWebSocket::new(
-        convert_websocket_stream(ws_stream, subscription_hash.clone()),
-        subscription_hash,
-        subgraph_cfg.protocol,
-        connection_params,
-    )
-    .await
-    .map_err(|_| FetchError::SubrequestWsError {
-        service: service_name.clone(),
-        reason: "cannot get the GraphQL websocket stream".to_string(),
-    })?;
+/// How a websocket connection to a subgraph ended
+enum WebSocketOutcome {
+    /// The subgraph completed the subscription or the client went away
+    Closed,
+    /// The connection has been lost, contains the error to send to the client if we don't reconnect
+    ConnectionLost(graphql::Response),
+}
 
-    gql_stream
-        .send(body)
-        .await
-        .map_err(|err| FetchError::SubrequestWsError {
-            service: service_name,
-            reason: format!("cannot send the subgraph request to websocket stream: {err:?}"),
-        })?;
-    let (mut gql_sink, gql_stream) = gql_stream.split();
-    let (handle_sink, handle_stream) = handle.split();
+enum WebSocketEvent {
+    Message(Option<graphql::Response>),
+    Ping,
+    IdleDeadline,
+}
 
-    tokio::task::spawn(async move {
-        let _ = gql_stream
-            .map(Ok::<_, graphql::Error>)
-            .forward(handle_sink)
-            .await;
+/// Forward the subgraph events to the subscription handle until the connection ends
+async fn forward_websocket_events<S>(
+    mut gql_stream: GraphqlWebSocket<S>,
+    handle_sink: &mut HandleSink<String, graphql::Response>,
+    subgraph_cfg: &WebSocketConfiguration,
+    service_name: &str,
+) -> WebSocketOutcome
+where
+    S: futures::Stream<Item = serde_json::Result<ServerMessage>>
+        + futures::Sink<ClientMessage>
+        + Unpin,
+{
+    let mut ping_interval = subgraph_cfg.ping_interval.map(|period| {
+        let mut interval = tokio::time::interval_at(Instant::now() + period, period);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        interval
+    });
+
+    let outcome = loop {
+        let idle_deadline = subgraph_cfg
+            .idle_timeout
+            .map(|idle_timeout| gql_stream.last_activity() + idle_timeout);
+        let event = tokio::select! {
+            message = gql_stream.next() => WebSocketEvent::Message(message),
+            _ = async {
+                match ping_interval.as_mut() {
+                    Some(interval) => {
+                        interval.tick().await;
+                    }
+                    None => futures::future::pending::<()>().await,
+                }
+            } => WebSocketEvent::Ping,
+            _ = async {
+                match idle_deadline {
+                    Some(deadline) => tokio::time::sleep_until(deadline).await,
+                    None => futures::future::pending::<()>().await,
+                }
+            } => WebSocketEvent::IdleDeadline,
+        };
 
-        if let Err(err) = gql_sink.close().await {
-            tracing::trace!("cannot close the websocket stream: {err:?}");
+        match event {
+            WebSocketEvent::Message(Some(response)) => {
+                if is_websocket_connection_error(&response) {
+                    break WebSocketOutcome::ConnectionLost(response);
+                }
+                if handle_sink.send(response).await.is_err() {
+                    break WebSocketOutcome::Closed;
+                }
+            }
+            WebSocketEvent::Message(None) => break WebSocketOutcome::Closed,
+            WebSocketEvent::Ping => {
+                if let Err(err) = gql_stream.ping().await {
+                    // If the connection is really broken the next read will tell us
+                    tracing::debug!("cannot send ping to subgraph {service_name:?}: {err:?}");
+                }
+            }
+            WebSocketEvent::IdleDeadline => {
+                // Messages received in the meantime moved the deadline
+                if subgraph_cfg
+                    .idle_timeout
+                    .map(|idle_timeout| gql_stream.last_activity().elapsed() >= idle_timeout)
+                    .unwrap_or_default()
+                {
+                    break WebSocketOutcome::ConnectionLost(
+                        graphql::Response::builder()
+                            .error(
+                                graphql::Error::builder()
+                                    .message(format!(
+                                        "websocket connection to subgraph {service_name:?} has been idle for too long"
+                                    ))
+                                    .extension_code("WEBSOCKET_IDLE_TIMEOUT")
+                                    .build(),
+                            )
+                            .subscribed(false)
+                            .build(),
+                    );
+                }
+            }
         }
-    });
+    };
 
-    subscription_stream_tx.send(Box::pin(handle_stream)).await?;
+    if let Err(err) = gql_stream.close().await {
+        tracing::trace!("cannot close the websocket stream: {err:?}");
+    }
 
-    Ok(SubgraphResponse::new_from_response(
-        resp.map(|_| graphql::Response::default()),
-        context,
-    ))
+    outcome
+}
+
+/// Errors produced by the websocket layer when the connection itself failed, not by the subgraph
+fn is_websocket_connection_error(response: &graphql::Response) -> bool {
+    response.errors.iter().any(|error| {
+        matches!(
+            error
+                .extensions
+                .get(CODE_STRING)
+                .and_then(|code| code.as_str()),
+            Some("WEBSOCKET_MESSAGE_ERROR") | Some("WEBSOCKET_CLOSE_ERROR")
+        )
+    })
+}
+
+fn subscription_reconnecting_event(attempt: u32) -> graphql::Response {
+    let mut extensions = Object::default();
+    extensions.insert(
+        "subscription",
+        serde_json_bytes::json!({
+            "code": "SUBSCRIPTION_RECONNECTING",
+            "attempt": attempt,
+        }),
+    );
+
+    graphql::Response::builder()
+        .subscribed(true)
+        .extensions(extensions)
+        .build()
 }
 
 /// call_http makes http calls with modified graphql::Request (body)
@@ -1026,12 +1288,16 @@ mod tests {
     use std::net::SocketAddr;
     use std::net::TcpListener;
     use std::str::FromStr;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+    use std::time::Duration;
 
     use axum::extract::ws::Message;
     use axum::extract::ConnectInfo;
     use axum::extract::WebSocketUpgrade;
     use axum::response::IntoResponse;
     use axum::routing::get;
+    use axum::Extension;
     use axum::Router;
     use axum::Server;
     use bytes::Buf;
@@ -1057,12 +1323,14 @@ mod tests {
     use crate::plugins::subscription::Disabled;
     use crate::plugins::subscription::SubgraphPassthroughMode;
     use crate::plugins::subscription::SubscriptionModeConfig;
+    use crate::plugins::subscription::WebSocketReconnectConfiguration;
     use crate::plugins::subscription::SUBSCRIPTION_CALLBACK_HMAC_KEY;
     use crate::plugins::traffic_shaping::Http2Config;
     use crate::protocols::websocket::ClientMessage;
     use crate::protocols::websocket::ServerMessage;
     use crate::protocols::websocket::WebSocketProtocol;
     use crate::query_planner::fetch::OperationKind;
+    use crate::services::subgraph::BoxGqlStream;
     use crate::Context;
 
     // starts a local server emulating a subgraph returning status code 400
@@ -1582,6 +1850,63 @@ mod tests {
         server.await.unwrap();
     }
 
+    /// Sends one event on each connection then drops it, and refuses the connections after `accepted_connections`
+    async fn emulate_websocket_server_dropping_connections(
+        listener: TcpListener,
+        connections: Arc<AtomicUsize>,
+        accepted_connections: usize,
+    ) {
+        async fn ws_handler(
+            ws: WebSocketUpgrade,
+            Extension((connections, accepted_connections)): Extension<(Arc<AtomicUsize>, usize)>,
+        ) -> axum::response::Response {
+            let connection = connections.fetch_add(1, Ordering::SeqCst);
+            if connection >= accepted_connections {
+                return (http::StatusCode::BAD_REQUEST, "bad request").into_response();
+            }
+
+            ws.on_upgrade(move |mut socket| async move {
+                let connection_init = socket.recv().await.unwrap().unwrap().into_text().unwrap();
+                let init_msg: ClientMessage = serde_json::from_str(&connection_init).unwrap();
+                assert!(matches!(init_msg, ClientMessage::ConnectionInit { .. }));
+                socket
+                    .send(Message::Text(
+                        serde_json::to_string(&ServerMessage::ConnectionAck).unwrap(),
+                    ))
+                    .await
+                    .unwrap();
+
+                // every connection subscribes again
+                let new_message = socket.recv().await.unwrap().unwrap().into_text().unwrap();
+                let ClientMessage::Subscribe { id, .. } = serde_json::from_str(&new_message).unwrap()
+                else {
+                    panic!("subscribe message should be sent");
+                };
+                socket
+                    .send(Message::Text(
+                        serde_json::to_string(&ServerMessage::Next {
+                            id,
+                            payload: graphql::Response::builder()
+                                .data(serde_json_bytes::json!({"userWasCreated": {"username": format!("user {connection}")}}))
+                                .build(),
+                        })
+                        .unwrap(),
+                    ))
+                    .await
+                    .unwrap();
+                // the socket is dropped without closing the websocket connection
+            })
+        }
+
+        let app = Router::new()
+            .route("/ws", get(ws_handler))
+            .layer(Extension((connections, accepted_connections)));
+        let server = Server::from_tcp(listener)
+            .unwrap()
+            .serve(app.into_make_service());
+        server.await.unwrap();
+    }
+
     async fn emulate_subgraph_with_callback_data(listener: TcpListener) {
         async fn handle(request: http::Request<Body>) -> Result<http::Response<Body>, Infallible> {
             let (parts, body) = request.into_parts();
@@ -1648,15 +1973,36 @@ mod tests {
                         WebSocketConfiguration {
                             path: Some(String::from("/ws")),
                             protocol: WebSocketProtocol::default(),
+                            ping_interval: None,
+                            idle_timeout: None,
+                            reconnect: Default::default(),
                         },
                     )]
                     .into(),
                 }),
+                redis_streams: None,
             },
             enable_deduplication: true,
             max_opened_subscriptions: None,
             queue_capacity: None,
+            ..Default::default()
+        }
+    }
+
+    fn reconnecting_subscription_config(max_retry_window: Duration) -> SubscriptionConfig {
+        let mut config = subscription_config();
+        if let Some(passthrough) = config.mode.passthrough.as_mut() {
+            for subgraph_cfg in passthrough.subgraphs.values_mut() {
+                subgraph_cfg.reconnect = WebSocketReconnectConfiguration {
+                    enabled: true,
+                    initial_backoff: Duration::from_millis(10),
+                    max_backoff: Duration::from_millis(20),
+                    max_retry_window,
+                    notify_client: true,
+                };
+            }
         }
+        config
     }
 
     fn supergraph_request(query: &str) -> Arc<http::Request<Request>> {
@@ -2011,6 +2357,125 @@ mod tests {
         );
     }
 
+    async fn subscribe_with_reconnection(
+        socket_addr: SocketAddr,
+        max_retry_window: Duration,
+    ) -> BoxGqlStream {
+        let subgraph_service = SubgraphService::new(
+            "test",
+            true,
+            Some(reconnecting_subscription_config(max_retry_window)),
+            Notify::builder().build(),
+            HttpClientServiceFactory::from_config(
+                "test",
+                &Configuration::default(),
+                Http2Config::Enable,
+            ),
+        )
+        .expect("can create a SubgraphService");
+        let (tx, mut rx) = mpsc::channel(2);
+
+        let url = Uri::from_str(&format!("ws://{socket_addr}")).unwrap();
+        let response = subgraph_service
+            .oneshot(
+                SubgraphRequest::builder()
+                    .supergraph_request(supergraph_request(
+                        "subscription {\n  userWasCreated {\n    username\n  }\n}",
+                    ))
+                    .subgraph_request(subgraph_http_request(
+                        url,
+                        "subscription {\n  userWasCreated {\n    username\n  }\n}",
+                    ))
+                    .operation_kind(OperationKind::Subscription)
+                    .subscription_stream(tx)
+                    .subgraph_name(String::from("test"))
+                    .context(Context::new())
+                    .build(),
+            )
+            .await
+            .unwrap();
+        assert!(response.response.body().errors.is_empty());
+
+        rx.recv().await.unwrap()
+    }
+
+    fn user_created_event(username: &str) -> graphql::Response {
+        graphql::Response::builder()
+            .subscribed(true)
+            .data(serde_json_bytes::json!({"userWasCreated": {"username": username}}))
+            .build()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_subgraph_service_websocket_reconnects_and_resubscribes() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let socket_addr = listener.local_addr().unwrap();
+        let connections = Arc::new(AtomicUsize::new(0));
+        let spawned_task = tokio::task::spawn(emulate_websocket_server_dropping_connections(
+            listener,
+            connections.clone(),
+            usize::MAX,
+        ));
+
+        let mut gql_stream = subscribe_with_reconnection(socket_addr, Duration::from_secs(5)).await;
+        assert_eq!(
+            gql_stream.next().await.unwrap(),
+            user_created_event("user 0")
+        );
+        // the client is told about the reconnection, then receives the events of the new connection
+        assert_eq!(
+            gql_stream.next().await.unwrap(),
+            subscription_reconnecting_event(1)
+        );
+        assert_eq!(
+            gql_stream.next().await.unwrap(),
+            user_created_event("user 1")
+        );
+        assert_eq!(
+            gql_stream.next().await.unwrap(),
+            subscription_reconnecting_event(1)
+        );
+        assert_eq!(
+            gql_stream.next().await.unwrap(),
+            user_created_event("user 2")
+        );
+        spawned_task.abort();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_subgraph_service_websocket_stops_reconnecting_after_the_retry_window() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let socket_addr = listener.local_addr().unwrap();
+        let connections = Arc::new(AtomicUsize::new(0));
+        let spawned_task = tokio::task::spawn(emulate_websocket_server_dropping_connections(
+            listener,
+            connections.clone(),
+            1,
+        ));
+
+        let mut gql_stream =
+            subscribe_with_reconnection(socket_addr, Duration::from_millis(200)).await;
+        assert_eq!(
+            gql_stream.next().await.unwrap(),
+            user_created_event("user 0")
+        );
+
+        // every attempt is announced to the client, until the retry window is over
+        let mut attempts = 0;
+        let last_message = loop {
+            let message = gql_stream.next().await.unwrap();
+            if message != subscription_reconnecting_event(attempts + 1) {
+                break message;
+            }
+            attempts += 1;
+        };
+        assert!(attempts >= 2, "{attempts} reconnection attempts");
+        assert_eq!(connections.load(Ordering::SeqCst), 1 + attempts as usize);
+        assert!(is_websocket_connection_error(&last_message));
+        assert!(gql_stream.next().await.is_none());
+        spawned_task.abort();
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     async fn test_bad_status_code_should_not_fail() {
         let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
@@ -3,6 +3,7 @@
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::task::Poll;
+use std::time::Duration;
 use std::time::Instant;
 
 use futures::future::BoxFuture;
@@ -31,7 +32,10 @@ use crate::graphql;
 use crate::graphql::IntoGraphQLErrors;
 use crate::graphql::Response;
 use crate::plugin::DynPlugin;
+use crate::plugins::subscription::ClientSubscriptionGuard;
+use crate::plugins::subscription::SlowConsumerPolicy;
 use crate::plugins::subscription::SubscriptionConfig;
+use crate::plugins::subscription::TerminationReason;
 use crate::plugins::telemetry::tracing::apollo_telemetry::APOLLO_PRIVATE_DURATION_NS;
 use crate::plugins::telemetry::Telemetry;
 use crate::plugins::telemetry::LOGGING_DISPLAY_BODY;
@@ -340,6 +344,8 @@ pub struct SubscriptionTaskParams {
     pub(crate) subscription_config: SubscriptionConfig,
     pub(crate) stream_rx: ReceiverStream<BoxGqlStream>,
     pub(crate) service_name: String,
+    /// Counts the subscription for its client until the task ends
+    pub(crate) client_subscription_guard: Option<ClientSubscriptionGuard>,
 }
 
 async fn subscription_task(
@@ -361,6 +367,7 @@ async fn subscription_task(
     let service_name = sub_params.service_name;
     let mut receiver = sub_params.stream_rx;
     let sender = sub_params.client_sender;
+    let _client_subscription_guard = sub_params.client_subscription_guard;
 
     // Get the rest of the query_plan to execute for subscription events
     let query_plan = match &query_plan.root {
@@ -423,30 +430,56 @@ async fn subscription_task(
     let expires_in = crate::plugins::authentication::jwt_expires_in(&supergraph_req.context);
 
     let mut timeout = Box::pin(tokio::time::sleep(expires_in));
+    let mut lifetime = Box::pin(tokio::time::sleep(
+        subscription_config.max_lifetime.unwrap_or(Duration::MAX),
+    ));
+    let idle_timeout = subscription_config.idle_timeout.unwrap_or(Duration::MAX);
+    let mut idle = Box::pin(tokio::time::sleep(idle_timeout));
+    let mut termination_reason = TerminationReason::Completed;
 
     loop {
         tokio::select! {
             // We prefer to specify the order of checks within the select
             biased;
             _ = subscription_handle.closed_signal.recv() => {
+                termination_reason = TerminationReason::ClientClosed;
                 break;
             }
             _ = &mut timeout => {
-                let response = Response::builder()
-                    .subscribed(false)
-                    .error(
-                        crate::error::Error::builder()
-                            .message("subscription closed because the JWT has expired")
-                            .extension_code("SUBSCRIPTION_JWT_EXPIRED")
-                            .build(),
-                    )
-                    .build();
-                let _ = sender.send(response).await;
+                termination_reason = TerminationReason::JwtExpired;
+                break;
+            },
+            _ = &mut lifetime, if subscription_config.max_lifetime.is_some() => {
+                termination_reason = TerminationReason::MaxLifetime;
+                break;
+            },
+            _ = &mut idle, if subscription_config.idle_timeout.is_some() => {
+                termination_reason = TerminationReason::IdleTimeout;
                 break;
             },
             message = receiver.next() => {
                 match message {
                     Some(mut val) => {
+                        if subscription_config.idle_timeout.is_some() {
+                            idle.as_mut().reset(tokio::time::Instant::now() + idle_timeout);
+                        }
+                        // The client's queue is full, it does not read events as fast as they are produced
+                        if sender.capacity() == 0 {
+                            match subscription_config.slow_consumer {
+                                SlowConsumerPolicy::Wait => {}
+                                SlowConsumerPolicy::DropEvents => {
+                                    tracing::info!(
+                                        monotonic_counter.apollo.router.operations.subscriptions.dropped_events = 1u64,
+                                        subgraph.service.name = service_name.as_str(),
+                                    );
+                                    continue;
+                                }
+                                SlowConsumerPolicy::Close => {
+                                    termination_reason = TerminationReason::SlowConsumer;
+                                    break;
+                                }
+                            }
+                        }
                         if display_body {
                             tracing::info!(http.request.body = ?val, apollo.subgraph.name = %service_name, "Subscription event body from subgraph {service_name:?}");
                         }
@@ -460,6 +493,7 @@ async fn subscription_task(
                             ).await;
                         if let Err(err) = res {
                                 tracing::error!("cannot send the subscription to the client: {err:?}");
+                            termination_reason = TerminationReason::ClientClosed;
                             break;
                         }
                     }
@@ -474,6 +508,7 @@ async fn subscription_task(
                         Ok(plugins) => Arc::new(plugins),
                         Err(err) => {
                             tracing::error!("cannot re-create plugins with the new configuration (closing existing subscription): {err:?}");
+                            termination_reason = TerminationReason::ConfigurationReload;
                             break;
                         },
                     };
@@ -481,6 +516,7 @@ async fn subscription_task(
                         Ok(subgraph_services) => subgraph_services,
                         Err(err) => {
                             tracing::error!("cannot re-create subgraph service with the new configuration (closing existing subscription): {err:?}");
+                            termination_reason = TerminationReason::ConfigurationReload;
                             break;
                         },
                     };
@@ -495,20 +531,31 @@ async fn subscription_task(
             }
             Some(new_schema) = schema_updated_rx.next() => {
                 if new_schema.raw_sdl != execution_service_factory.schema.raw_sdl {
-                    let _ = sender
-                        .send(
-                            Response::builder()
-                                .subscribed(false)
-                                .error(graphql::Error::builder().message("subscription has been closed due to a schema reload").extension_code("SUBSCRIPTION_SCHEMA_RELOAD").build())
-                                .build(),
-                        )
-                        .await;
-
+                    termination_reason = TerminationReason::SchemaReload;
                     break;
                 }
             }
         }
     }
+    if let Some(response) = termination_reason.response() {
+        if termination_reason == TerminationReason::SlowConsumer {
+            // Waiting for room in the queue of a slow client would keep the subscription alive, drop the final
+            // payload if it is still full
+            let _ = sender.try_send(response);
+        } else {
+            let _ = sender.send(response).await;
+        }
+    }
+    tracing::info!(
+        monotonic_counter
+            .apollo
+            .router
+            .operations
+            .subscriptions
+            .terminated = 1u64,
+        subscriptions.termination_reason = termination_reason.as_str(),
+        subgraph.service.name = service_name.as_str(),
+    );
     drop(sender);
     tracing::trace!("Leaving the task for subscription");
     if limit_is_set {
@@ -526,6 +573,8 @@ async fn dispatch_event(
 ) -> Result<(), SendError<Response>> {
     let start = Instant::now();
     let span = Span::current();
+    // Events without data nor errors (like reconnection notices) have nothing to execute
+    let query_plan = query_plan.filter(|_| val.data.is_some() || !val.errors.is_empty());
     let res = match query_plan {
         Some(query_plan) => {
             let cloned_supergraph_req = clone_supergraph_request(
@@ -703,7 +752,7 @@ impl PluggableSupergraphServiceBuilder {
             self.planner,
             schema.clone(),
             &configuration,
-            IndexMap::new(),
+            self.plugins.clone(),
         )
         .await?;
 
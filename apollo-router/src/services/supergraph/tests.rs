@@ -1163,6 +1163,128 @@ async fn subscription_with_callback_with_limit() {
     assert!(res.errors.is_empty());
 }
 
+#[tokio::test]
+async fn subscription_with_callback_with_client_limit() {
+    let mut notify = Notify::builder().build();
+    let (handle, _) = notify
+        .create_or_subscribe("TEST_TOPIC".to_string(), false)
+        .await
+        .unwrap();
+    let subgraphs = MockedSubgraphs([
+            ("user", MockSubgraph::builder().with_json(
+                    serde_json::json!{{"query":"subscription{userWasCreated{name activeOrganization{__typename id}}}"}},
+                    serde_json::json!{{"data": {"userWasCreated": { "__typename": "User", "id": "1", "activeOrganization": { "__typename": "Organization", "id": "0" } }}}}
+                ).with_subscription_stream(handle.clone()).build()),
+            ("orga", MockSubgraph::builder().with_json(
+                serde_json::json!{{
+                    "query":"query($representations:[_Any!]!){_entities(representations:$representations){...on Organization{suborga{id name}}}}",
+                    "variables": {
+                        "representations":[{"__typename": "Organization", "id":"0"}]
+                    }
+                }},
+                serde_json::json!{{
+                    "data": {
+                        "_entities": [{ "suborga": [
+                        { "__typename": "Organization", "id": "1", "name": "A"},
+                        ] }]
+                    },
+                    }}
+            ).build())
+        ].into_iter().collect());
+
+    let mut configuration: Configuration = serde_json::from_value(serde_json::json!({
+        "include_subgraph_errors": { "all": true },
+        "subscription": {
+            "enabled": true,
+            "max_opened_subscriptions_per_client": {
+                "client_id": { "header": "x-client-id" },
+                "max_opened_subscriptions": 2
+            },
+            "mode": {"callback": {"public_url": "http://localhost:4545/callback"}}
+        }
+    }))
+    .unwrap();
+    configuration.notify = notify.clone();
+    let mut service = TestHarness::builder()
+        .configuration(Arc::new(configuration))
+        .schema(SCHEMA)
+        .extra_plugin(subgraphs)
+        .build_supergraph()
+        .await
+        .unwrap();
+
+    let request = |client_id: &str| {
+        supergraph::Request::fake_builder()
+            .query(
+                "subscription { userWasCreated { name activeOrganization { id  suborga { id name } } } }",
+            )
+            .header("x-client-id", client_id)
+            .context(subscription_context())
+            .build()
+            .unwrap()
+    };
+
+    let mut first = service
+        .ready()
+        .await
+        .unwrap()
+        .call(request("client_limit"))
+        .await
+        .unwrap();
+    assert!(first.next_response().await.unwrap().errors.is_empty());
+    let mut second = service
+        .ready()
+        .await
+        .unwrap()
+        .call(request("client_limit"))
+        .await
+        .unwrap();
+    assert!(second.next_response().await.unwrap().errors.is_empty());
+
+    // the third subscription of the client is rejected
+    let mut third = service
+        .ready()
+        .await
+        .unwrap()
+        .call(request("client_limit"))
+        .await
+        .unwrap();
+    let res = third.next_response().await.unwrap();
+    assert_eq!(res.errors.len(), 1);
+    assert_eq!(
+        res.errors[0].message,
+        "can't open new subscription, limit reached for this client"
+    );
+    assert_eq!(
+        res.errors[0].extensions.get("code"),
+        Some(&serde_json_bytes::json!("SUBSCRIPTION_CLIENT_MAX_LIMIT"))
+    );
+
+    // other clients are not limited
+    let mut other = service
+        .ready()
+        .await
+        .unwrap()
+        .call(request("other_client"))
+        .await
+        .unwrap();
+    assert!(other.next_response().await.unwrap().errors.is_empty());
+
+    // closing a subscription releases its slot
+    drop(first);
+    drop(third);
+    // Wait a bit to ensure all the closed signals has been triggered
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let mut fourth = service
+        .ready()
+        .await
+        .unwrap()
+        .call(request("client_limit"))
+        .await
+        .unwrap();
+    assert!(fourth.next_response().await.unwrap().errors.is_empty());
+}
+
 #[tokio::test]
 async fn subscription_without_header() {
     let subgraphs = MockedSubgraphs(HashMap::new());
@@ -51,6 +51,7 @@ pub(crate) mod transform;
 pub(crate) mod traverse;
 
 pub(crate) const TYPENAME: &str = "__typename";
+const STREAM_DIRECTIVE_NAME: &str = "stream";
 
 /// A GraphQL query.
 #[derive(Derivative, Serialize, Deserialize)]
@@ -341,10 +342,12 @@ impl Query {
         })
     }
 
-    /// Check for parse errors in a query in the compiler.
+    /// Check for parse errors in a query in the compiler, and for unsupported directives.
     pub(crate) fn check_errors(document: &ParsedDocument) -> Result<(), SpecError> {
         match document.parse_errors.clone() {
             Some(errors) => Err(SpecError::ParsingError(errors.to_string())),
+            // The query planner cannot plan `@stream`, reject it before validation reports it as unknown
+            None if uses_stream(&document.ast) => Err(SpecError::StreamNotSupported),
             None => Ok(()),
         }
     }
@@ -1244,5 +1247,34 @@ pub(crate) fn parse_hir_value(value: &executable::Value) -> Option<Value> {
     }
 }
 
+/// Whether a field of the document uses the `@stream` directive
+fn uses_stream(document: &ast::Document) -> bool {
+    fn selection_set_uses_stream(selection_set: &[ast::Selection]) -> bool {
+        selection_set.iter().any(|selection| match selection {
+            ast::Selection::Field(field) => {
+                field.directives.get(STREAM_DIRECTIVE_NAME).is_some()
+                    || selection_set_uses_stream(&field.selection_set)
+            }
+            ast::Selection::InlineFragment(fragment) => {
+                selection_set_uses_stream(&fragment.selection_set)
+            }
+            ast::Selection::FragmentSpread(_) => false,
+        })
+    }
+
+    document
+        .definitions
+        .iter()
+        .any(|definition| match definition {
+            ast::Definition::OperationDefinition(operation) => {
+                selection_set_uses_stream(&operation.selection_set)
+            }
+            ast::Definition::FragmentDefinition(fragment) => {
+                selection_set_uses_stream(&fragment.selection_set)
+            }
+            _ => false,
+        })
+}
+
 #[cfg(test)]
 mod tests;
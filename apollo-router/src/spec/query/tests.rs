@@ -5775,6 +5775,33 @@ fn test_query_not_named_query() {
     );
 }
 
+#[test]
+fn stream_directive_is_rejected() {
+    let config = Configuration::default();
+    let schema = Schema::parse_test(
+        &with_supergraph_boilerplate(
+            "type Query { products: [Product] } type Product { id: ID! reviews: [String] }",
+            "Query",
+        ),
+        &config,
+    )
+    .unwrap();
+
+    for query in [
+        "{ products @stream(initialCount: 1) { id } }",
+        "{ products { ...ProductReviews } } fragment ProductReviews on Product { reviews @stream }",
+    ] {
+        assert!(
+            matches!(
+                Query::parse(query, &schema, &config),
+                Err(SpecError::StreamNotSupported)
+            ),
+            "{query} should be rejected"
+        );
+    }
+    assert!(Query::parse("{ products { id reviews } }", &schema, &config).is_ok());
+}
+
 #[test]
 fn filtered_defer_fragment() {
     let config = Configuration::default();